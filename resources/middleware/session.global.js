@@ -0,0 +1,13 @@
+export default defineNuxtRouteMiddleware(async (to) => {
+    if (process.server || to.path === '/login') {
+        return
+    }
+
+    const session = useState('session', () => null)
+    if (!session.value) {
+        session.value = await $fetch('/api/session').catch(() => null)
+    }
+    if (!session.value) {
+        return navigateTo({path: '/login', query: {redirect: to.fullPath}})
+    }
+})
@@ -0,0 +1,230 @@
+use crate::util::matches;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a user may do with a repository, each role allowing what the ones before it do.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Listing and inspecting images
+    Viewer,
+    /// Retagging, and promoting images into the repository
+    Developer,
+    /// Deleting images
+    Maintainer,
+    /// Everything, replication rules and retention policies included when granted on all
+    /// repositories of all registries
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Role::Viewer => "viewer",
+            Role::Developer => "developer",
+            Role::Maintainer => "maintainer",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An `[[access]]` entry of the config file: a role for some users, or members of some
+/// OpenID Connect groups, on the repositories matching a pattern like `payments/*`.
+///
+/// ```toml
+/// [[access]]
+/// users = ["alice", "bob"]
+/// groups = ["payments-team"]
+/// role = "maintainer"
+/// repositories = ["payments/*"]
+/// registries = ["default"]
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Grant {
+    /// `*` stands for every identified user
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub role: Role,
+    #[serde(default = "all")]
+    pub repositories: Vec<String>,
+    /// Registry ids, every registry when empty
+    #[serde(default)]
+    pub registries: Vec<String>,
+}
+
+fn all() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+impl Grant {
+    fn applies_to(&self, user: &str, groups: &[String]) -> bool {
+        self.users.iter().any(|name| name == "*" || name == user)
+            || self.groups.iter().any(|group| groups.contains(group))
+    }
+
+    fn covers(&self, registry: &str, repository: &str) -> bool {
+        (self.registries.is_empty() || self.registries.iter().any(|id| id == registry))
+            && self.repositories.iter().any(|pattern| matches(pattern, repository))
+    }
+}
+
+/// The grants of the config file. Users without any grant see nothing.
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    grants: Vec<Grant>,
+}
+
+impl AccessPolicy {
+    pub fn new(grants: Vec<Grant>) -> Self {
+        Self { grants }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grants.is_empty()
+    }
+
+    /// Groups no grant names don't matter, and sessions don't keep them.
+    pub fn names_group(&self, group: &str) -> bool {
+        self.grants.iter().any(|grant| grant.groups.iter().any(|g| g == group))
+    }
+
+    /// The highest role granted to `user` or its groups on the repository.
+    pub fn role(&self, user: &str, groups: &[String], registry: &str, repository: &str) -> Option<Role> {
+        self.grants
+            .iter()
+            .filter(|grant| grant.applies_to(user, groups) && grant.covers(registry, repository))
+            .map(|grant| grant.role)
+            .max()
+    }
+
+    /// Admin on every repository of every registry, needed for settings spanning repositories.
+    pub fn is_admin(&self, user: &str, groups: &[String]) -> bool {
+        self.grants.iter().any(|grant| {
+            grant.role == Role::Admin
+                && grant.applies_to(user, groups)
+                && grant.registries.is_empty()
+                && grant.repositories.iter().any(|pattern| pattern == "*")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct File {
+        access: Vec<Grant>,
+    }
+
+    /// User, groups, registry, repository and the role expected
+    type Case<'a> = (&'a str, &'a [String], &'a str, &'a str, Option<Role>);
+
+    fn policy() -> AccessPolicy {
+        let file: File = toml::from_str(
+            r#"
+            [[access]]
+            users = ["*"]
+            role = "viewer"
+
+            [[access]]
+            users = ["alice"]
+            role = "admin"
+
+            [[access]]
+            users = ["bob"]
+            role = "developer"
+            repositories = ["payments/*"]
+
+            [[access]]
+            groups = ["payments-team"]
+            role = "maintainer"
+            repositories = ["payments/*"]
+
+            [[access]]
+            users = ["carol"]
+            role = "maintainer"
+            repositories = ["ci/*"]
+            registries = ["staging"]
+
+            [[access]]
+            users = ["dave"]
+            role = "admin"
+            repositories = ["*"]
+            registries = ["default"]
+            "#,
+        )
+        .unwrap();
+
+        AccessPolicy::new(file.access)
+    }
+
+    #[test]
+    fn grants_the_highest_matching_role() {
+        let team = ["payments-team".to_string()];
+        let cases: &[Case] = &[
+            // Wildcard users
+            ("eve", &[], "default", "library/alpine", Some(Role::Viewer)),
+            ("bob", &[], "default", "library/alpine", Some(Role::Viewer)),
+            // The highest of several grants
+            ("alice", &[], "default", "payments/api", Some(Role::Admin)),
+            ("bob", &[], "default", "payments/api", Some(Role::Developer)),
+            ("bob", &team, "default", "payments/api", Some(Role::Maintainer)),
+            // Group grants
+            ("eve", &team, "default", "payments/api", Some(Role::Maintainer)),
+            ("eve", &team, "default", "orders/api", Some(Role::Viewer)),
+            // `*` spans `/`
+            ("bob", &[], "default", "payments/eu/api", Some(Role::Developer)),
+            ("bob", &[], "default", "payments", Some(Role::Viewer)),
+            // Registry-scoped grants
+            ("carol", &[], "staging", "ci/build", Some(Role::Maintainer)),
+            ("carol", &[], "default", "ci/build", Some(Role::Viewer)),
+            ("dave", &[], "default", "ci/build", Some(Role::Admin)),
+            ("dave", &[], "staging", "ci/build", Some(Role::Viewer)),
+        ];
+
+        let policy = policy();
+        for (user, groups, registry, repository, role) in cases {
+            assert_eq!(
+                policy.role(user, groups, registry, repository),
+                *role,
+                "{} on {}/{}",
+                user,
+                registry,
+                repository
+            );
+        }
+    }
+
+    #[test]
+    fn admins_need_every_repository_of_every_registry() {
+        let policy = policy();
+
+        assert!(policy.is_admin("alice", &[]));
+        assert!(!policy.is_admin("dave", &[]));
+        assert!(!policy.is_admin("bob", &["payments-team".to_string()]));
+        assert!(policy.names_group("payments-team"));
+        assert!(!policy.names_group("everyone"));
+    }
+
+    #[test]
+    fn users_without_grants_get_nothing() {
+        // Without the wildcard grant
+        let policy = AccessPolicy::new(policy().grants.into_iter().skip(1).collect());
+
+        assert_eq!(policy.role("eve", &[], "default", "library/alpine"), None);
+        assert_eq!(policy.role("bob", &[], "default", "library/alpine"), None);
+        assert_eq!(
+            policy.role("alice", &[], "default", "library/alpine"),
+            Some(Role::Admin)
+        );
+        assert_eq!(
+            AccessPolicy::default().role("alice", &[], "default", "library/alpine"),
+            None
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use crate::types::Config;
+use rocket::http::{Cookie, CookieJar, HeaderMap, SameSite};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use time::{Duration, OffsetDateTime};
+
+mod access;
+mod oidc;
+mod tokens;
+mod users;
+
+pub use access::{AccessPolicy, Grant, Role};
+pub use oidc::{Oidc, OidcError, PendingLogin};
+pub use tokens::{ApiToken, TokenError, TokenScope, Tokens, TOKEN_PREFIX};
+pub use users::Users;
+
+/// Private cookie, encrypted and signed with `SECRET_KEY`.
+pub const SESSION_COOKIE: &str = "harbui_session";
+/// Requests changing something carry the token of their session in it.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Who may use harbui. Without a users file, a proxy header or an OpenID provider every request
+/// is let in, as before logins existed.
+pub struct Authentication {
+    users: Option<Users>,
+    oidc: Option<Oidc>,
+    proxy_user_header: Option<String>,
+    session_lifetime: Duration,
+    tokens: Tokens,
+}
+
+impl Authentication {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let users = match &config.users_file {
+            Some(file) => Some(Users::load(PathBuf::from(file))?),
+            None => None,
+        };
+
+        Ok(Self {
+            users,
+            oidc: Oidc::new(config)?,
+            proxy_user_header: config.proxy_user_header.clone(),
+            session_lifetime: Duration::hours(config.session_hours as i64),
+            tokens: Tokens::new(config.tokens_file.clone())?,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.users.is_some() || self.oidc.is_some() || self.proxy_user_header.is_some()
+    }
+
+    pub fn logins_enabled(&self) -> bool {
+        self.users.is_some()
+    }
+
+    pub fn oidc(&self) -> Option<&Oidc> {
+        self.oidc.as_ref()
+    }
+
+    pub fn tokens(&self) -> &Tokens {
+        &self.tokens
+    }
+
+    /// The user named by the reverse proxy. The header is trusted as is, so harbui must only
+    /// be reachable through the proxy when it's configured.
+    pub fn proxy_user(&self, headers: &HeaderMap) -> Option<String> {
+        let header = self.proxy_user_header.as_deref()?;
+        let user = headers.get_one(header)?.trim();
+
+        (!user.is_empty()).then(|| user.to_string())
+    }
+
+    pub async fn login(&self, name: &str, password: &str) -> Option<Session> {
+        let users = self.users.as_ref()?;
+        if !users.verify(name, password).await {
+            return None;
+        }
+
+        Some(self.new_session(name.to_string(), Vec::new(), Provider::Local))
+    }
+
+    pub fn new_session(&self, user: String, groups: Vec<String>, provider: Provider) -> Session {
+        Session {
+            user,
+            groups,
+            provider,
+            csrf_token: uuid::Uuid::new_v4().simple().to_string(),
+            expires_at: (OffsetDateTime::now_utc() + self.session_lifetime).unix_timestamp(),
+        }
+    }
+
+    /// The session of the cookie, unless it expired, its user was removed from the users file
+    /// or its way of logging in was turned off since.
+    pub fn session(&self, cookies: &CookieJar) -> Option<Session> {
+        let cookie = cookies.get_private(SESSION_COOKIE)?;
+        let session: Session = serde_json::from_str(cookie.value()).ok()?;
+        if session.expires_at <= OffsetDateTime::now_utc().unix_timestamp() {
+            return None;
+        }
+
+        self.still_allowed(&session.user, session.provider).then_some(session)
+    }
+
+    /// The API token with that secret, under the same conditions as sessions.
+    pub fn token(&self, secret: &str) -> Option<ApiToken> {
+        let token = self.tokens.verify(secret)?;
+        self.still_allowed(&token.user, token.provider).then_some(token)
+    }
+
+    fn still_allowed(&self, user: &str, provider: Provider) -> bool {
+        match provider {
+            Provider::Local => self.users.as_ref().is_some_and(|users| users.exists(user)),
+            Provider::Oidc => self.oidc.is_some(),
+            Provider::Proxy => self.proxy_user_header.is_some(),
+        }
+    }
+}
+
+/// Where a session or an API token comes from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    /// The users file
+    #[default]
+    Local,
+    Oidc,
+    /// The reverse proxy header, only for API tokens
+    Proxy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub user: String,
+    /// Groups of the identity provider that `[[access]]` grants name
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub provider: Provider,
+    pub csrf_token: String,
+    /// Unix timestamp
+    pub expires_at: i64,
+}
+
+impl Session {
+    pub fn cookie(&self) -> Cookie<'static> {
+        let mut cookie = Cookie::new(SESSION_COOKIE, serde_json::to_string(self).unwrap_or_default());
+        cookie.set_http_only(true);
+        cookie.set_same_site(SameSite::Strict);
+        cookie.set_path("/");
+        if let Ok(expires) = OffsetDateTime::from_unix_timestamp(self.expires_at) {
+            cookie.set_expires(expires);
+        }
+
+        cookie
+    }
+
+    /// Compares in constant time, not to leak how much of a guess was right.
+    pub fn csrf_matches(&self, token: Option<&str>) -> bool {
+        let Some(token) = token else {
+            return false;
+        };
+        let expected = self.csrf_token.as_bytes();
+
+        token.len() == expected.len()
+            && token
+                .as_bytes()
+                .iter()
+                .zip(expected)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
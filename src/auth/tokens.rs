@@ -0,0 +1,293 @@
+use crate::auth::{Provider, Role};
+use crate::store::{JsonStore, StoreError};
+use crate::util::{matches, now};
+use rocket::tokio;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Start of every token, telling them apart from other bearer tokens and secrets.
+pub const TOKEN_PREFIX: &str = "harbui_";
+/// `last_used_at` is saved at most that often, not to write the file on every request.
+const LAST_USED_SAVE_INTERVAL: time::Duration = time::Duration::minutes(5);
+
+#[derive(Debug)]
+pub enum TokenError {
+    NotFound(String),
+    Storage(String),
+}
+
+impl From<StoreError> for TokenError {
+    fn from(e: StoreError) -> Self {
+        TokenError::Storage(e.to_string())
+    }
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenError::NotFound(id) => write!(f, "No API token {}", id),
+            TokenError::Storage(e) => write!(f, "Can't save API tokens: {}", e),
+        }
+    }
+}
+
+/// What a token may do, on top of the grants of its owner.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenScope {
+    /// `viewer` for read-only tokens, `maintainer` to delete images
+    pub role: Role,
+    /// Patterns like `payments/*`
+    pub repositories: Vec<String>,
+}
+
+impl TokenScope {
+    /// The role of the owner on the repository, lowered to the one of the token.
+    pub fn limit(&self, repository: &str, granted: Role) -> Option<Role> {
+        self.repositories
+            .iter()
+            .any(|pattern| matches(pattern, repository))
+            .then(|| granted.min(self.role))
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin && self.repositories.iter().any(|pattern| pattern == "*")
+    }
+}
+
+/// A personal API token, as listed. The secret is only shown once, when it's created.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub user: String,
+    /// Groups of the owner's session when the token was created
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub provider: Provider,
+    pub scope: TokenScope,
+    /// Start of the secret, to recognize it
+    pub hint: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub last_used_at: Option<String>,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self) -> bool {
+        OffsetDateTime::parse(&self.expires_at, &Rfc3339).map_or(true, |expires| expires <= OffsetDateTime::now_utc())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: ApiToken,
+    /// SHA-256 of the secret. Secrets are random, so unlike passwords they need no slow hash.
+    hash: String,
+}
+
+/// API tokens, kept in `HARBUI_TOKENS_FILE` when set and only in memory otherwise.
+pub struct Tokens {
+    store: Arc<JsonStore<StoredToken>>,
+}
+
+impl Tokens {
+    pub fn new(file: Option<String>) -> Result<Self, String> {
+        Ok(Self {
+            store: Arc::new(JsonStore::open("API tokens", file)?),
+        })
+    }
+
+    /// The tokens of `user`, or of everyone.
+    pub fn list(&self, user: Option<&str>) -> Vec<ApiToken> {
+        self.store
+            .read()
+            .iter()
+            .filter(|stored| user.is_none_or(|user| stored.token.user == user))
+            .map(|stored| stored.token.clone())
+            .collect()
+    }
+
+    /// Stores the token with a new id and secret, and returns the secret with it.
+    pub fn create(&self, mut token: ApiToken) -> Result<(String, ApiToken), TokenError> {
+        let secret = format!(
+            "{}{}{}",
+            TOKEN_PREFIX,
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        token.id = uuid::Uuid::new_v4().to_string();
+        token.hint = secret[..TOKEN_PREFIX.len() + 6].to_string();
+        token.created_at = now();
+        token.last_used_at = None;
+
+        self.store.update(|tokens| {
+            tokens.push(StoredToken {
+                token: token.clone(),
+                hash: hash(&secret),
+            });
+            Ok::<_, TokenError>(())
+        })?;
+
+        Ok((secret, token))
+    }
+
+    /// Revokes the token, if `user` owns it or is `None`.
+    pub fn revoke(&self, id: &str, user: Option<&str>) -> Result<ApiToken, TokenError> {
+        self.store.update(|tokens| {
+            let index = tokens
+                .iter()
+                .position(|stored| stored.token.id == id && user.is_none_or(|user| stored.token.user == user))
+                .ok_or_else(|| TokenError::NotFound(id.to_string()))?;
+
+            Ok(tokens.remove(index).token)
+        })
+    }
+
+    /// Revokes every token of `user`, returning them.
+    pub fn revoke_all(&self, user: &str) -> Result<Vec<ApiToken>, TokenError> {
+        if !self.store.read().iter().any(|stored| stored.token.user == user) {
+            return Ok(Vec::new());
+        }
+
+        self.store.update(|tokens| {
+            let (revoked, kept): (Vec<StoredToken>, Vec<StoredToken>) =
+                tokens.drain(..).partition(|stored| stored.token.user == user);
+            *tokens = kept;
+            Ok(revoked.into_iter().map(|stored| stored.token).collect())
+        })
+    }
+
+    /// The unexpired token with that secret, marked as used.
+    pub fn verify(&self, secret: &str) -> Option<ApiToken> {
+        let hash = hash(secret);
+        let mut token = self
+            .store
+            .read()
+            .iter()
+            .find(|stored| stored.hash == hash)?
+            .token
+            .clone();
+        if token.is_expired() {
+            return None;
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let saved_lately = token
+            .last_used_at
+            .as_deref()
+            .and_then(|used| OffsetDateTime::parse(used, &Rfc3339).ok())
+            .is_some_and(|used| now - used < LAST_USED_SAVE_INTERVAL);
+        if saved_lately {
+            return Some(token);
+        }
+
+        token.last_used_at = now.format(&Rfc3339).ok();
+        self.store.update_unsaved(|tokens| {
+            if let Some(stored) = tokens.iter_mut().find(|stored| stored.hash == hash) {
+                stored.token.last_used_at = token.last_used_at.clone();
+            }
+        });
+        // Saved without holding up the request using the token
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = store.save() {
+                warn!("Can't save API tokens: {}", e);
+            }
+        });
+
+        Some(token)
+    }
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    fn file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("harbui-{}-{}.json", name, uuid::Uuid::new_v4().simple()))
+    }
+
+    fn token(user: &str) -> ApiToken {
+        ApiToken {
+            id: String::new(),
+            name: "ci".to_string(),
+            user: user.to_string(),
+            groups: Vec::new(),
+            provider: Provider::Local,
+            scope: TokenScope {
+                role: Role::Viewer,
+                repositories: vec!["*".to_string()],
+            },
+            hint: String::new(),
+            created_at: String::new(),
+            expires_at: "2999-01-01T00:00:00Z".to_string(),
+            last_used_at: None,
+        }
+    }
+
+    fn open(file: &Path) -> Result<Tokens, String> {
+        Tokens::new(Some(file.to_string_lossy().to_string()))
+    }
+
+    #[test]
+    fn revokes_every_token_of_a_user() {
+        let file = file("revoke");
+        let tokens = open(&file).unwrap();
+        let (_, first) = tokens.create(token("alice")).unwrap();
+        tokens.create(token("alice")).unwrap();
+        let (_, other) = tokens.create(token("bob")).unwrap();
+
+        assert!(matches!(
+            tokens.revoke(&first.id, Some("bob")),
+            Err(TokenError::NotFound(_))
+        ));
+        assert_eq!(tokens.revoke_all("alice").unwrap().len(), 2);
+        assert!(tokens.revoke_all("alice").unwrap().is_empty());
+
+        let ids: Vec<String> = open(&file)
+            .unwrap()
+            .list(None)
+            .into_iter()
+            .map(|token| token.id)
+            .collect();
+        assert_eq!(ids, vec![other.id]);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn saves_use_times_in_the_background() {
+        let file = file("used");
+        let tokens = open(&file).unwrap();
+        let (secret, _) = tokens.create(token("alice")).unwrap();
+
+        let used = tokens.verify(&secret).unwrap().last_used_at;
+        assert!(used.is_some());
+        assert!(tokens.verify("harbui_wrong").is_none());
+
+        let mut saved = None;
+        for _ in 0..50 {
+            saved = open(&file).unwrap().list(None)[0].last_used_at.clone();
+            if saved.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(saved, used);
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use rocket::tokio::task;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// Local users from an htpasswd file with bcrypt hashes, as written by `htpasswd -B`.
+/// The file is read again when it changes, so users come and go without a restart.
+pub struct Users {
+    file: PathBuf,
+    loaded: RwLock<Loaded>,
+    /// Checked against for unknown users, so they take as long as wrong passwords
+    dummy_hash: String,
+}
+
+struct Loaded {
+    modified: Option<SystemTime>,
+    hashes: Arc<HashMap<String, String>>,
+}
+
+impl Users {
+    pub fn load(file: PathBuf) -> Result<Self, String> {
+        let hashes = read(&file)?;
+        let dummy_hash = bcrypt::hash("harbui", bcrypt::DEFAULT_COST).map_err(|e| e.to_string())?;
+        info!("{} users loaded from {}", hashes.len(), file.display());
+
+        Ok(Self {
+            loaded: RwLock::new(Loaded {
+                modified: modified(&file),
+                hashes: Arc::new(hashes),
+            }),
+            file,
+            dummy_hash,
+        })
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.hashes().contains_key(name)
+    }
+
+    /// Bcrypt is slow on purpose, so it runs off the async workers.
+    pub async fn verify(&self, name: &str, password: &str) -> bool {
+        let (hash, known) = match self.hashes().get(name) {
+            Some(hash) => (hash.clone(), true),
+            None => (self.dummy_hash.clone(), false),
+        };
+        let password = password.to_string();
+
+        let matches = task::spawn_blocking(move || bcrypt::verify(password, &hash))
+            .await
+            .map(|verified| verified.unwrap_or(false))
+            .unwrap_or(false);
+
+        known && matches
+    }
+
+    /// A file with errors keeps the users read before.
+    fn hashes(&self) -> Arc<HashMap<String, String>> {
+        let current = modified(&self.file);
+        {
+            let loaded = self.loaded.read().unwrap_or_else(|e| e.into_inner());
+            if loaded.modified == current {
+                return loaded.hashes.clone();
+            }
+        }
+
+        let mut loaded = self.loaded.write().unwrap_or_else(|e| e.into_inner());
+        loaded.modified = current;
+        match read(&self.file) {
+            Ok(hashes) => {
+                info!("{} users reloaded from {}", hashes.len(), self.file.display());
+                loaded.hashes = Arc::new(hashes);
+            }
+            Err(e) => error!("Keeping the current users: {}", e),
+        }
+
+        loaded.hashes.clone()
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+fn read(file: &Path) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(file).map_err(|e| format!("Can't read {}: {}", file.display(), e))?;
+    parse(&content).map_err(|e| format!("{}: {}", file.display(), e))
+}
+
+fn parse(content: &str) -> Result<HashMap<String, String>, String> {
+    let mut hashes = HashMap::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, hash)) = line.split_once(':') else {
+            return Err(format!("line {} isn't `user:hash`", index + 1));
+        };
+        if !["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix)) {
+            return Err(format!(
+                "line {}: {} has no bcrypt hash, create it with `htpasswd -B`",
+                index + 1,
+                name
+            ));
+        }
+        hashes.insert(name.to_string(), hash.to_string());
+    }
+
+    Ok(hashes)
+}
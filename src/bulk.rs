@@ -0,0 +1,376 @@
+use crate::manager::{image_created, matching_repositories, tag_digests};
+use crate::registry_api::types::ClientError;
+use crate::registry_api::RegistryClient;
+use crate::types::{BulkDeleteItem, BulkDeleteReport, BulkDeleteStatus};
+use regex::Regex;
+use rocket::futures::future::join_all;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use time::OffsetDateTime;
+
+/// Manifest lookups at the same time.
+const RESOLVE_CONCURRENCY: usize = 8;
+/// Deletions at the same time, kept low as registries lock the repository for each.
+const DELETE_CONCURRENCY: usize = 4;
+
+/// Tags picked by repository pattern, tag pattern and creation date.
+pub struct Selection {
+    pub repositories: Vec<String>,
+    pub tags: Option<Regex>,
+    pub created_before: Option<OffsetDateTime>,
+}
+
+impl Selection {
+    /// Repository and tag pairs. Images without a creation date are never old enough.
+    pub async fn images(&self, client: &RegistryClient) -> Result<Vec<(String, String)>, ClientError> {
+        let mut images = Vec::new();
+        for repository in matching_repositories(client, &self.repositories).await? {
+            let tags = match client.get_tags(&repository).await {
+                Ok(answer) => answer.content.tags.unwrap_or_default(),
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => return Err(e),
+            };
+            let tags: Vec<String> = tags
+                .into_iter()
+                .filter(|tag| self.tags.as_ref().is_none_or(|pattern| pattern.is_match(tag)))
+                .collect();
+
+            let Some(before) = self.created_before else {
+                images.extend(tags.into_iter().map(|tag| (repository.clone(), tag)));
+                continue;
+            };
+            let mut dated = Vec::new();
+            for chunk in tags.chunks(RESOLVE_CONCURRENCY) {
+                let created = join_all(chunk.iter().map(|tag| image_created(client, &repository, tag))).await;
+                dated.extend(chunk.iter().cloned().zip(created));
+            }
+
+            for (tag, created) in dated {
+                let created = match created {
+                    Ok(created) => created,
+                    // Deleted since the tags were listed
+                    Err(e) if e.is_not_found() => continue,
+                    Err(e) => return Err(e),
+                };
+                if created.is_some_and(|created| created < before) {
+                    images.push((repository.clone(), tag));
+                }
+            }
+        }
+
+        Ok(images)
+    }
+}
+
+/// Deletes images by digest, each digest once however many of the images point at it.
+/// As with single deletes, a digest other tags point at takes them along, so it's skipped
+/// unless `force` is given; those tags are then reported.
+pub async fn delete(
+    client: &RegistryClient,
+    mut images: Vec<(String, String)>,
+    dry_run: bool,
+    force: bool,
+) -> BulkDeleteReport {
+    let mut seen = HashSet::new();
+    images.retain(|image| seen.insert(image.clone()));
+
+    let mut resolved: Vec<Result<String, String>> = Vec::new();
+    for chunk in images.chunks(RESOLVE_CONCURRENCY) {
+        resolved.extend(
+            join_all(
+                chunk
+                    .iter()
+                    .map(|(repository, reference)| resolve(client, repository, reference)),
+            )
+            .await,
+        );
+    }
+    let targets: BTreeSet<(&str, &str)> = images
+        .iter()
+        .zip(&resolved)
+        .filter_map(|((repository, _), digest)| Some((repository.as_str(), digest.as_deref().ok()?)))
+        .collect();
+
+    let mut warnings = Vec::new();
+    let repositories: BTreeSet<&str> = targets.iter().map(|(repository, _)| *repository).collect();
+    let mut tags_by_digest: HashMap<(&str, String), Vec<String>> = HashMap::new();
+    let mut unchecked: HashMap<&str, String> = HashMap::new();
+    for repository in repositories {
+        match tag_digests(client, repository).await {
+            Ok(digests) => {
+                for (tag, digest) in digests {
+                    tags_by_digest.entry((repository, digest)).or_default().push(tag);
+                }
+            }
+            Err(e) => {
+                unchecked.insert(repository, e.to_string());
+            }
+        }
+    }
+
+    let requested: HashSet<(&str, &str)> = images
+        .iter()
+        .map(|(repository, reference)| (repository.as_str(), reference.as_str()))
+        .collect();
+    let also_deleted = |repository: &str, digest: &str| -> Vec<String> {
+        tags_by_digest
+            .get(&(repository, digest.to_string()))
+            .into_iter()
+            .flatten()
+            .filter(|tag| !requested.contains(&(repository, tag.as_str())))
+            .cloned()
+            .collect()
+    };
+
+    let mut skipped: HashMap<(&str, &str), String> = HashMap::new();
+    for &(repository, digest) in &targets {
+        if let Some(e) = unchecked.get(repository) {
+            if !force {
+                skipped.insert(
+                    (repository, digest),
+                    format!(
+                        "Can't check {} for other tags on {}: {}, pass force=true to delete it anyway",
+                        repository, digest, e
+                    ),
+                );
+            }
+            continue;
+        }
+        let others = also_deleted(repository, digest);
+        if !others.is_empty() && !force {
+            skipped.insert(
+                (repository, digest),
+                format!(
+                    "Deleting {}@{} also removes {}, pass force=true to delete them all",
+                    repository,
+                    digest,
+                    others.join(", ")
+                ),
+            );
+        }
+    }
+    if force {
+        warnings.extend(
+            unchecked
+                .iter()
+                .map(|(repository, e)| format!("Can't check {} for other tags on the same digests: {}", repository, e)),
+        );
+    }
+
+    let mut outcomes: HashMap<(&str, &str), Result<(), String>> = HashMap::new();
+    let targets: Vec<(&str, &str)> = targets
+        .into_iter()
+        .filter(|target| !skipped.contains_key(target))
+        .collect();
+    for chunk in targets.chunks(DELETE_CONCURRENCY) {
+        let results = if dry_run {
+            chunk.iter().map(|_| Ok(())).collect()
+        } else {
+            join_all(
+                chunk
+                    .iter()
+                    .map(|(repository, digest)| delete_digest(client, repository, digest)),
+            )
+            .await
+        };
+        outcomes.extend(chunk.iter().copied().zip(results));
+    }
+
+    let mut shared: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+    let items: Vec<BulkDeleteItem> = images
+        .iter()
+        .zip(&resolved)
+        .map(|((repository, reference), digest)| {
+            let item =
+                |digest: Option<&str>, status, error: Option<String>, also_deleted: Vec<String>| BulkDeleteItem {
+                    repository: repository.clone(),
+                    reference: reference.clone(),
+                    digest: digest.map(str::to_string),
+                    status,
+                    error,
+                    also_deleted,
+                };
+            let digest = match digest {
+                Ok(digest) => digest.as_str(),
+                Err(e) => return item(None, BulkDeleteStatus::Failed, Some(e.clone()), Vec::new()),
+            };
+            if let Some(reason) = skipped.get(&(repository.as_str(), digest)) {
+                return item(
+                    Some(digest),
+                    BulkDeleteStatus::Skipped,
+                    Some(reason.clone()),
+                    Vec::new(),
+                );
+            }
+
+            let others = also_deleted(repository, digest);
+            if !others.is_empty() {
+                shared.insert((repository.as_str(), digest), others.clone());
+            }
+            match &outcomes[&(repository.as_str(), digest)] {
+                Ok(()) if dry_run => item(Some(digest), BulkDeleteStatus::WouldDelete, None, others),
+                Ok(()) => item(Some(digest), BulkDeleteStatus::Deleted, None, others),
+                Err(e) => item(Some(digest), BulkDeleteStatus::Failed, Some(e.clone()), others),
+            }
+        })
+        .collect();
+
+    warnings.extend(shared.into_iter().map(|((repository, digest), tags)| {
+        format!("Deleting {}@{} also removes {}", repository, digest, tags.join(", "))
+    }));
+
+    let count = |status| items.iter().filter(|item| item.status == status).count();
+    BulkDeleteReport {
+        dry_run,
+        digests: outcomes.values().filter(|outcome| outcome.is_ok()).count(),
+        failed: count(BulkDeleteStatus::Failed),
+        skipped: count(BulkDeleteStatus::Skipped),
+        warnings,
+        items,
+    }
+}
+
+async fn delete_digest(client: &RegistryClient, repository: &str, digest: &str) -> Result<(), String> {
+    match client.delete_manifest(repository, digest).await {
+        Ok(_) => {
+            info!("Deleted {}@{}", repository, digest);
+            Ok(())
+        }
+        Err(e) => {
+            warn!("Can't delete {}@{}: {}", repository, digest, e);
+            Err(e.to_string())
+        }
+    }
+}
+
+async fn resolve(client: &RegistryClient, repository: &str, reference: &str) -> Result<String, String> {
+    match client.head_manifest(repository, reference).await {
+        Ok(Some(digest)) => Ok(digest),
+        Ok(None) => Err("Not found".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AccessPolicy, Grant, Role};
+    use crate::registry_api::Config;
+    use crate::testing::{serve, Reply};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    const SHARED: &str = "sha256:1111111111111111111111111111111111111111111111111111111111111111";
+    const ALONE: &str = "sha256:2222222222222222222222222222222222222222222222222222222222222222";
+
+    /// `app` with `1` and `latest` on one digest and `2` on another, recording deleted digests.
+    async fn registry(deleted: Arc<Mutex<Vec<String>>>) -> RegistryClient {
+        let address = serve(move |req| {
+            if req.path == "/v2/app/tags/list" {
+                return Reply::json(200, &json!({"name": "app", "tags": ["1", "2", "latest"]}));
+            }
+            let reference = req.path.trim_start_matches("/v2/app/manifests/");
+            if req.method == "DELETE" {
+                deleted.lock().unwrap().push(reference.to_string());
+                return Reply::status(202);
+            }
+            match reference {
+                "1" | "latest" | SHARED => Reply::status(200).header("Docker-Content-Digest", SHARED),
+                "2" | ALONE => Reply::status(200).header("Docker-Content-Digest", ALONE),
+                _ => Reply::status(404),
+            }
+        })
+        .await;
+
+        RegistryClient::new(&Config {
+            base_uri: address,
+            is_secured: false,
+            http_basic_user: None,
+            http_basic_pass: None,
+        })
+    }
+
+    fn images(tags: &[&str]) -> Vec<(String, String)> {
+        tags.iter().map(|tag| ("app".to_string(), tag.to_string())).collect()
+    }
+
+    #[rocket::async_test]
+    async fn skips_digests_other_tags_point_at() {
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let client = registry(deleted.clone()).await;
+
+        let report = delete(&client, images(&["1", "2"]), false, false).await;
+
+        assert_eq!(*deleted.lock().unwrap(), vec![ALONE]);
+        assert_eq!((report.digests, report.skipped, report.failed), (1, 1, 0));
+        assert_eq!(report.items[0].status, BulkDeleteStatus::Skipped);
+        assert!(report.items[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("also removes latest"));
+        assert_eq!(report.items[1].status, BulkDeleteStatus::Deleted);
+    }
+
+    #[rocket::async_test]
+    async fn deletes_shared_digests_when_forced_or_all_tags_are_given() {
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let client = registry(deleted.clone()).await;
+
+        let report = delete(&client, images(&["1", "latest"]), false, false).await;
+        assert_eq!(*deleted.lock().unwrap(), vec![SHARED]);
+        assert!(report.warnings.is_empty());
+
+        deleted.lock().unwrap().clear();
+        let report = delete(&client, images(&["1"]), false, true).await;
+        assert_eq!(*deleted.lock().unwrap(), vec![SHARED]);
+        assert_eq!(report.items[0].status, BulkDeleteStatus::Deleted);
+        assert_eq!(report.items[0].also_deleted, vec!["latest"]);
+    }
+
+    #[rocket::async_test]
+    async fn selects_nothing_outside_the_patterns_namespace() {
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let log = requested.clone();
+        let address = serve(move |req| {
+            log.lock().unwrap().push(req.path.clone());
+            match req.path.as_str() {
+                "/v2/_catalog" => Reply::json(200, &json!({"repositories": ["team/app", "other/app"]})),
+                "/v2/team/app/tags/list" => Reply::json(200, &json!({"name": "team/app", "tags": ["1"]})),
+                _ => Reply::json(200, &json!({"name": "other/app", "tags": ["1"]})),
+            }
+        })
+        .await;
+        let client = RegistryClient::new(&Config {
+            base_uri: address,
+            is_secured: false,
+            http_basic_user: None,
+            http_basic_pass: None,
+        });
+        let maintainer = AccessPolicy::new(vec![Grant {
+            users: vec!["mallory".to_string()],
+            groups: Vec::new(),
+            role: Role::Maintainer,
+            repositories: vec!["team/*".to_string()],
+            registries: Vec::new(),
+        }]);
+        let selection = |repositories: &[&str]| Selection {
+            repositories: repositories.iter().map(|r| r.to_string()).collect(),
+            tags: None,
+            created_before: None,
+        };
+
+        // These read as inside `team/*` to the grant, the registry would resolve them elsewhere
+        for pattern in ["team/../other/app", "team/*/../../other/app"] {
+            assert_eq!(
+                maintainer.role("mallory", &[], "default", pattern),
+                Some(Role::Maintainer)
+            );
+            assert!(selection(&[pattern]).images(&client).await.unwrap().is_empty());
+        }
+        assert!(requested.lock().unwrap().is_empty());
+
+        let images = selection(&["team/*", "team/../*"]).images(&client).await.unwrap();
+        assert_eq!(images, vec![("team/app".to_string(), "1".to_string())]);
+        assert!(!requested.lock().unwrap().iter().any(|path| path.contains("other")));
+    }
+}
@@ -0,0 +1,358 @@
+use crate::registry_api::types::{ClientError, Manifest, UploadSession};
+use crate::registry_api::RegistryClient;
+use crate::types::CopyReport;
+use bytes::{Bytes, BytesMut};
+use rocket::futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Large enough to keep the request count low, small enough for most proxies' body limits.
+const UPLOAD_CHUNK_SIZE: usize = 16 << 20;
+/// Attempts per blob, interrupted uploads resume from the offset the registry accepted.
+const MAX_BLOB_ATTEMPTS: usize = 5;
+
+/// Copies images within or between registries. Blobs are mounted when both sides are the same
+/// registry and streamed through otherwise, manifests are pushed byte for byte so the copy keeps
+/// the source digest. Every blob and manifest is checked against its digest on the way.
+pub struct Copier<'a> {
+    source: &'a RegistryClient,
+    target: &'a RegistryClient,
+    /// Mounting only works within a registry
+    mount: bool,
+    chunk_size: usize,
+}
+
+impl<'a> Copier<'a> {
+    pub fn within(client: &'a RegistryClient) -> Self {
+        Self {
+            source: client,
+            target: client,
+            mount: true,
+            chunk_size: UPLOAD_CHUNK_SIZE,
+        }
+    }
+
+    pub fn between(source: &'a RegistryClient, target: &'a RegistryClient) -> Self {
+        Self {
+            source,
+            target,
+            mount: false,
+            chunk_size: UPLOAD_CHUNK_SIZE,
+        }
+    }
+
+    pub async fn copy(
+        &self,
+        image: &str,
+        reference: &str,
+        target: &str,
+        target_reference: &str,
+    ) -> Result<CopyReport, ClientError> {
+        let mut report = CopyReport::default();
+        report.digest = self
+            .copy_manifest(image, reference, target, target_reference, &mut report)
+            .await?;
+
+        Ok(report)
+    }
+
+    /// Children of an index are copied first, registries reject indexes referencing unknown manifests.
+    fn copy_manifest<'b>(
+        &'b self,
+        image: &'b str,
+        reference: &'b str,
+        target: &'b str,
+        target_reference: &'b str,
+        report: &'b mut CopyReport,
+    ) -> Pin<Box<dyn Future<Output = Result<String, ClientError>> + Send + 'b>> {
+        Box::pin(async move {
+            let raw = self.source.get_raw_manifest(image, reference).await?.content;
+            let digest = sha256_digest(&raw.content);
+            if reference.starts_with("sha256:") && reference != digest {
+                return Err(digest_mismatch(reference, &digest));
+            }
+
+            // Within a repository everything the manifest references is already there
+            match &raw.manifest {
+                _ if self.mount && image == target => {}
+                Manifest::OCIImageIndexV1(index) => {
+                    for child in &index.manifests {
+                        self.copy_manifest(image, &child.digest, target, &child.digest, report)
+                            .await?;
+                    }
+                }
+                Manifest::DockerDistributionManifestListV2(list) => {
+                    for child in &list.manifests {
+                        self.copy_manifest(image, &child.digest, target, &child.digest, report)
+                            .await?;
+                    }
+                }
+                Manifest::OCIImageManifestV1(manifest) => {
+                    self.copy_blob(image, target, &manifest.config.digest, report).await?;
+                    for layer in manifest.layers.iter().filter(|l| l.is_distributable()) {
+                        self.copy_blob(image, target, &layer.digest, report).await?;
+                    }
+                }
+                Manifest::DockerDistributionManifestV2(manifest) => {
+                    self.copy_blob(image, target, &manifest.config.digest, report).await?;
+                    for layer in manifest.layers.iter().filter(|l| l.is_distributable()) {
+                        self.copy_blob(image, target, &layer.digest, report).await?;
+                    }
+                }
+            }
+
+            let pushed = self
+                .target
+                .put_manifest(target, target_reference, &raw.manifest.media_type(), raw.content)
+                .await?;
+            if let Some(pushed) = pushed.digest.filter(|pushed| *pushed != digest) {
+                return Err(digest_mismatch(&digest, &pushed));
+            }
+            report.manifests += 1;
+
+            Ok(digest)
+        })
+    }
+
+    async fn copy_blob(
+        &self,
+        image: &str,
+        target: &str,
+        digest: &str,
+        report: &mut CopyReport,
+    ) -> Result<(), ClientError> {
+        if self.target.blob_exists(target, digest).await? {
+            report.blobs_skipped += 1;
+            return Ok(());
+        }
+
+        let session = if self.mount {
+            match self.target.mount_blob(target, digest, image).await {
+                Ok(None) => {
+                    report.blobs_mounted += 1;
+                    return Ok(());
+                }
+                Ok(Some(session)) => session,
+                // Some registries reject the mount parameters instead of ignoring them
+                Err(e) => {
+                    warn!("Can't mount {} from {} into {}: {}", digest, image, target, e);
+                    self.target.start_upload(target).await?
+                }
+            }
+        } else {
+            self.target.start_upload(target).await?
+        };
+
+        report.bytes_copied += self.transfer_blob(image, target, digest, session).await?;
+        report.blobs_copied += 1;
+
+        Ok(())
+    }
+
+    /// Streams the blob in chunks, retrying transient failures from where the upload stopped.
+    /// The upload starts over when the registry lost track of it.
+    async fn transfer_blob(
+        &self,
+        image: &str,
+        target: &str,
+        digest: &str,
+        mut session: UploadSession,
+    ) -> Result<u64, ClientError> {
+        let mut hasher = Sha256::new();
+        let mut attempt = 1;
+
+        loop {
+            let error = match self.send_chunks(image, digest, &mut session, &mut hasher).await {
+                Ok(()) => return Ok(session.offset),
+                Err(e) if e.is_transient() && attempt < MAX_BLOB_ATTEMPTS => e,
+                Err(e) => return Err(e),
+            };
+            attempt += 1;
+
+            session = match self.target.upload_status(&session).await {
+                Ok(status) if status.offset == session.offset => status,
+                _ => {
+                    hasher = Sha256::new();
+                    self.target.start_upload(target).await?
+                }
+            };
+            warn!(
+                "Resuming upload of {} to {} at {} bytes (attempt {}): {}",
+                digest, target, session.offset, attempt, error
+            );
+        }
+    }
+
+    /// Sends the blob from the session offset on. The hasher and the session only move forward
+    /// with chunks the registry accepted, so a failed call can be resumed as is.
+    async fn send_chunks(
+        &self,
+        image: &str,
+        digest: &str,
+        session: &mut UploadSession,
+        hasher: &mut Sha256,
+    ) -> Result<(), ClientError> {
+        let mut stream = Box::pin(self.source.get_blob_stream_at(image, digest, session.offset).await?);
+        let mut chunk = BytesMut::with_capacity(self.chunk_size);
+
+        while let Some(bytes) = stream.try_next().await? {
+            chunk.extend_from_slice(&bytes);
+            while chunk.len() >= self.chunk_size {
+                let chunk = chunk.split_to(self.chunk_size).freeze();
+                *session = self.target.upload_chunk(session, chunk.clone()).await?;
+                hasher.update(&chunk);
+            }
+        }
+
+        // Blobs smaller than a chunk go out as a single monolithic `PUT`
+        let last: Bytes = chunk.freeze();
+        let mut verified = hasher.clone();
+        verified.update(&last);
+        let actual = format!("sha256:{:x}", verified.finalize());
+        if digest.starts_with("sha256:") && actual != digest {
+            return Err(digest_mismatch(digest, &actual));
+        }
+
+        let size = session.offset + last.len() as u64;
+        self.target.complete_upload(session, digest, last).await?;
+        session.offset = size;
+
+        Ok(())
+    }
+}
+
+fn sha256_digest(content: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(content))
+}
+
+fn digest_mismatch(expected: &str, actual: &str) -> ClientError {
+    ClientError::Decode {
+        message: format!("content doesn't match its digest, got {}", actual),
+        excerpt: expected.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{registry_client, serve_registry, FakeRegistry};
+    use std::sync::{Arc, Mutex};
+
+    async fn start(registry: FakeRegistry) -> (RegistryClient, Arc<Mutex<FakeRegistry>>) {
+        let (address, registry) = serve_registry(registry).await;
+
+        (registry_client(address), registry)
+    }
+
+    #[rocket::async_test]
+    async fn uploads_in_chunks_and_resumes_interrupted_uploads() {
+        let layer = b"0123456789".to_vec();
+        let mut source = FakeRegistry::default();
+        let digest = source.push("app", "1.0", &layer);
+        let (source, source_registry) = start(source).await;
+        let (target, target_registry) = start(FakeRegistry {
+            failing_uploads: vec![3],
+            ..Default::default()
+        })
+        .await;
+
+        let copier = Copier {
+            chunk_size: 4,
+            ..Copier::between(&source, &target)
+        };
+        let report = copier.copy("app", "1.0", "copy", "latest").await.unwrap();
+
+        assert_eq!(report.digest, digest);
+        assert_eq!(
+            (
+                report.manifests,
+                report.blobs_copied,
+                report.blobs_skipped,
+                report.bytes_copied
+            ),
+            (1, 2, 0, 12)
+        );
+        {
+            let target_registry = target_registry.lock().unwrap();
+            assert_eq!(target_registry.blobs[&format!("copy@{}", sha256_digest(&layer))], layer);
+            assert!(target_registry.manifests.contains_key(&format!("copy:{}", digest)));
+            // The layer goes out in 4 byte chunks, resumed at the offset accepted before the failure
+            let patches: Vec<&String> = target_registry.log.iter().filter(|l| l.starts_with("PATCH")).collect();
+            assert_eq!(
+                patches,
+                vec![
+                    "PATCH /v2/copy/blobs/uploads/1 0-3",
+                    "PATCH /v2/copy/blobs/uploads/1 4-7",
+                    "PATCH /v2/copy/blobs/uploads/1 4-7",
+                ]
+            );
+            assert!(target_registry
+                .log
+                .contains(&"GET /v2/copy/blobs/uploads/1".to_string()));
+        }
+        let resumed = format!("GET /v2/app/blobs/{} bytes=4-", sha256_digest(&layer));
+        assert!(source_registry.lock().unwrap().log.contains(&resumed));
+
+        let again = copier.copy("app", "1.0", "copy", "latest").await.unwrap();
+        assert_eq!((again.blobs_copied, again.blobs_skipped), (0, 2));
+    }
+
+    #[rocket::async_test]
+    async fn mounts_blobs_and_uploads_them_when_refused() {
+        let mut registry = FakeRegistry {
+            mountable: true,
+            ..Default::default()
+        };
+        registry.push("app", "1.0", b"layer");
+        let (client, registry) = start(registry).await;
+
+        let report = Copier::within(&client)
+            .copy("app", "1.0", "mounted", "1.0")
+            .await
+            .unwrap();
+        assert_eq!((report.blobs_mounted, report.blobs_copied), (2, 0));
+
+        registry.lock().unwrap().mountable = false;
+        let report = Copier::within(&client)
+            .copy("app", "1.0", "uploaded", "1.0")
+            .await
+            .unwrap();
+        assert_eq!(
+            (report.blobs_mounted, report.blobs_copied, report.bytes_copied),
+            (0, 2, 7)
+        );
+        assert!(registry
+            .lock()
+            .unwrap()
+            .blobs
+            .contains_key(&format!("uploaded@{}", sha256_digest(b"layer"))));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_content_not_matching_its_digest() {
+        let mut source = FakeRegistry::default();
+        let digest = source.push("app", "1.0", b"layer");
+        let tampered = format!("app@{}", sha256_digest(b"layer"));
+        source.blobs.insert(tampered, b"tampered".to_vec());
+        let other = source.push("other", "1.0", b"other");
+        let other = source.manifests[&format!("other:{}", other)].clone();
+        source.manifests.insert(format!("app:{}", digest), other);
+        let (source, _) = start(source).await;
+        let (target, target_registry) = start(FakeRegistry::default()).await;
+        let copier = Copier::between(&source, &target);
+
+        let error = copier.copy("app", "1.0", "copy", "1.0").await.unwrap_err();
+        assert!(error.to_string().contains("doesn't match"), "{}", error);
+        {
+            let target_registry = target_registry.lock().unwrap();
+            let layer = format!("copy@{}", sha256_digest(b"layer"));
+            assert!(!target_registry.blobs.contains_key(&layer));
+            assert!(target_registry.manifests.is_empty());
+        }
+
+        // A manifest asked for by digest must have that digest
+        let error = copier.copy("app", &digest, "copy", "1.0").await.unwrap_err();
+        assert!(error.to_string().contains("doesn't match"), "{}", error);
+    }
+}
@@ -0,0 +1,378 @@
+use crate::manager::PlatformManifest;
+use crate::registry_api::types::{ImageConfigResponse, Layer};
+use crate::sbom::Inventory;
+use crate::types::{HistoryDiff, LayerRef, MapDiff, PackageType, PlatformDiff, ValueChange};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+pub type ResolvedImage = (PlatformManifest, ImageConfigResponse);
+
+pub fn platform_key(image: &ResolvedImage) -> String {
+    image
+        .0
+        .platform
+        .as_ref()
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| image.1.platform().to_string())
+}
+
+/// Pairs both sides by platform. Single-platform images are always compared with each other,
+/// so `app:v1` built for amd64 can still be diffed against an arm64 rebuild.
+pub fn pair_platforms<'a>(
+    from: &'a [ResolvedImage],
+    to: &'a [ResolvedImage],
+) -> Vec<(&'a ResolvedImage, &'a ResolvedImage)> {
+    if let ([from], [to]) = (from, to) {
+        return vec![(from, to)];
+    }
+
+    from.iter()
+        .filter_map(|f| {
+            let key = platform_key(f);
+            to.iter().find(|t| platform_key(t) == key).map(|t| (f, t))
+        })
+        .collect()
+}
+
+pub fn diff_images(
+    from: &ResolvedImage,
+    to: &ResolvedImage,
+    inventories: Option<(&Inventory, &Inventory)>,
+) -> PlatformDiff {
+    let (from_manifest, from_config) = from;
+    let (to_manifest, to_config) = to;
+
+    let from_digests: HashSet<&str> = from_manifest.layers.iter().map(|l| l.digest.as_str()).collect();
+    let to_digests: HashSet<&str> = to_manifest.layers.iter().map(|l| l.digest.as_str()).collect();
+
+    let from_size = from_manifest.get_total_size();
+    let to_size = to_manifest.get_total_size();
+
+    PlatformDiff {
+        from_platform: platform_key(from),
+        to_platform: platform_key(to),
+        from_digest: from_manifest.digest.clone(),
+        to_digest: to_manifest.digest.clone(),
+        shared_layers: layer_refs(&from_manifest.layers, |d| to_digests.contains(d)),
+        removed_layers: layer_refs(&from_manifest.layers, |d| !to_digests.contains(d)),
+        added_layers: layer_refs(&to_manifest.layers, |d| !from_digests.contains(d)),
+        from_size,
+        to_size,
+        size_delta: to_size as i64 - from_size as i64,
+        env: diff_maps(&env_map(from_config), &env_map(to_config)),
+        labels: diff_maps(
+            &from_config
+                .config
+                .labels
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            &to_config
+                .config
+                .labels
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+        ),
+        entrypoint: value_change(&from_config.config.entrypoint, &to_config.config.entrypoint),
+        cmd: value_change(&from_config.config.cmd, &to_config.config.cmd),
+        history: diff_history(from_config, to_config),
+        packages: inventories.map(|(from, to)| diff_maps(&package_versions(from), &package_versions(to))),
+    }
+}
+
+fn layer_refs(layers: &[Layer], keep: impl Fn(&str) -> bool) -> Vec<LayerRef> {
+    layers
+        .iter()
+        .filter(|l| keep(&l.digest))
+        .map(|l| LayerRef {
+            digest: l.digest.clone(),
+            size: l.size,
+        })
+        .collect()
+}
+
+fn env_map(config: &ImageConfigResponse) -> BTreeMap<String, String> {
+    config
+        .config
+        .env
+        .iter()
+        .flatten()
+        .map(|e| match e.split_once('=') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (e.to_string(), String::new()),
+        })
+        .collect()
+}
+
+fn diff_maps(from: &BTreeMap<String, String>, to: &BTreeMap<String, String>) -> MapDiff {
+    let mut diff = MapDiff::default();
+
+    for (key, value) in from {
+        match to.get(key) {
+            None => {
+                diff.removed.insert(key.clone(), value.clone());
+            }
+            Some(new) if new != value => {
+                diff.changed.insert(
+                    key.clone(),
+                    ValueChange {
+                        from: Some(value.clone()),
+                        to: Some(new.clone()),
+                    },
+                );
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (key, value) in to {
+        if !from.contains_key(key) {
+            diff.added.insert(key.clone(), value.clone());
+        }
+    }
+
+    diff
+}
+
+fn value_change<T>(from: &Option<T>, to: &Option<T>) -> Option<ValueChange<T>>
+where
+    T: Clone + PartialEq,
+{
+    (from != to).then(|| ValueChange {
+        from: from.clone(),
+        to: to.clone(),
+    })
+}
+
+/// Copies of a package at several places show as their versions joined, e.g. `1.0, 2.0`.
+fn package_versions(inventory: &Inventory) -> BTreeMap<String, String> {
+    let mut versions: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    for package in &inventory.packages {
+        let kind = match package.package_type {
+            PackageType::Deb => "deb",
+            PackageType::Apk => "apk",
+            PackageType::Rpm => "rpm",
+            PackageType::Python => "python",
+            PackageType::Go => "go",
+            PackageType::Npm => "npm",
+        };
+        versions
+            .entry(format!("{}/{}", kind, package.name))
+            .or_default()
+            .insert(&package.version);
+    }
+
+    versions
+        .into_iter()
+        .map(|(key, versions)| (key, versions.into_iter().collect::<Vec<_>>().join(", ")))
+        .collect()
+}
+
+/// Steps are compared as multisets of `created_by`, so a step repeated twice and kept once counts as removed.
+fn diff_history(from: &ImageConfigResponse, to: &ImageConfigResponse) -> HistoryDiff {
+    let steps = |config: &ImageConfigResponse| -> Vec<String> {
+        config
+            .history
+            .iter()
+            .map(|h| h.created_by.clone().unwrap_or_default())
+            .collect()
+    };
+    let (from, to) = (steps(from), steps(to));
+
+    HistoryDiff {
+        added: subtract(&to, &from),
+        removed: subtract(&from, &to),
+    }
+}
+
+fn subtract(items: &[String], other: &[String]) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for item in other {
+        *counts.entry(item).or_default() += 1;
+    }
+
+    items
+        .iter()
+        .filter(|item| match counts.get_mut(item.as_str()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry_api::types::{OCIImageConfigV1, Platform};
+    use crate::types::Package;
+    use serde_json::{json, Value};
+
+    fn image(platform: Option<&str>, layers: &[(&str, u64)], config: Value) -> ResolvedImage {
+        let manifest = PlatformManifest {
+            digest: format!("sha256:{}", layers.len()),
+            platform: platform.map(|architecture| Platform {
+                architecture: architecture.to_string(),
+                os: "linux".to_string(),
+                os_version: None,
+                os_features: None,
+                variant: None,
+                features: None,
+            }),
+            config: OCIImageConfigV1 {
+                digest: "sha256:config".to_string(),
+                size: 0,
+            },
+            layers: layers
+                .iter()
+                .map(|(digest, size)| Layer {
+                    media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+                    size: *size,
+                    digest: digest.to_string(),
+                })
+                .collect(),
+        };
+        let mut config_json = json!({"architecture": platform.unwrap_or("amd64"), "os": "linux"});
+        config_json
+            .as_object_mut()
+            .unwrap()
+            .extend(config.as_object().unwrap().clone());
+
+        (manifest, serde_json::from_value(config_json).unwrap())
+    }
+
+    fn inventory(packages: &[(PackageType, &str, &str)]) -> Inventory {
+        Inventory {
+            os: None,
+            packages: packages
+                .iter()
+                .map(|(package_type, name, version)| Package {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    package_type: *package_type,
+                    arch: None,
+                    license: None,
+                    source: None,
+                    location: String::new(),
+                    layer: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn digests(layers: &[LayerRef]) -> Vec<&str> {
+        layers.iter().map(|layer| layer.digest.as_str()).collect()
+    }
+
+    #[test]
+    fn compares_layers_and_sizes() {
+        let from = image(None, &[("base", 100), ("app-v1", 20)], json!({}));
+        let to = image(None, &[("base", 100), ("deps", 50), ("app-v2", 25)], json!({}));
+
+        let diff = diff_images(&from, &to, None);
+        assert_eq!(digests(&diff.shared_layers), vec!["base"]);
+        assert_eq!(digests(&diff.removed_layers), vec!["app-v1"]);
+        assert_eq!(digests(&diff.added_layers), vec!["deps", "app-v2"]);
+        assert_eq!((diff.from_size, diff.to_size, diff.size_delta), (120, 175, 55));
+    }
+
+    #[test]
+    fn compares_config_fields_and_history() {
+        let from = image(
+            None,
+            &[],
+            json!({
+                "config": {
+                    "Env": ["PATH=/bin", "MODE=dev", "DEBUG"],
+                    "Labels": {"team": "payments"},
+                    "Cmd": ["serve"],
+                    "Entrypoint": ["/app"],
+                },
+                "history": [{"created_by": "COPY . /app"}, {"created_by": "RUN make"}, {"created_by": "RUN make"}],
+            }),
+        );
+        let to = image(
+            None,
+            &[],
+            json!({
+                "config": {
+                    "Env": ["PATH=/bin", "MODE=prod"],
+                    "Labels": {"team": "payments", "version": "1.5.0"},
+                    "Cmd": ["serve", "--port=80"],
+                    "Entrypoint": ["/app"],
+                },
+                "history": [{"created_by": "COPY . /app"}, {"created_by": "RUN make"}, {"created_by": "USER app"}],
+            }),
+        );
+
+        let diff = diff_images(&from, &to, None);
+        assert_eq!(diff.env.removed, BTreeMap::from([("DEBUG".to_string(), String::new())]));
+        assert!(diff.env.added.is_empty());
+        assert_eq!(diff.env.changed["MODE"].to.as_deref(), Some("prod"));
+        assert_eq!(diff.labels.added["version"], "1.5.0");
+        assert!(diff.labels.changed.is_empty());
+        assert_eq!(diff.cmd.unwrap().to.unwrap(), vec!["serve", "--port=80"]);
+        assert!(diff.entrypoint.is_none());
+        assert_eq!(diff.history.added, vec!["USER app"]);
+        assert_eq!(diff.history.removed, vec!["RUN make"]);
+        assert!(diff.packages.is_none());
+    }
+
+    #[test]
+    fn compares_packages_of_extracted_inventories() {
+        let image = image(None, &[], json!({}));
+        let from = inventory(&[
+            (PackageType::Deb, "openssl", "3.0.11"),
+            (PackageType::Deb, "curl", "7.88"),
+            (PackageType::Npm, "lodash", "4.17.20"),
+            (PackageType::Npm, "lodash", "4.17.21"),
+        ]);
+        let to = inventory(&[
+            (PackageType::Deb, "openssl", "3.0.13"),
+            (PackageType::Python, "curl", "1.0"),
+            (PackageType::Npm, "lodash", "4.17.21"),
+        ]);
+
+        let packages = diff_images(&image, &image, Some((&from, &to))).packages.unwrap();
+        assert_eq!(
+            packages.removed,
+            BTreeMap::from([("deb/curl".to_string(), "7.88".to_string())])
+        );
+        assert_eq!(
+            packages.added,
+            BTreeMap::from([("python/curl".to_string(), "1.0".to_string())])
+        );
+        assert_eq!(
+            packages.changed.keys().collect::<Vec<_>>(),
+            vec!["deb/openssl", "npm/lodash"]
+        );
+        assert_eq!(packages.changed["npm/lodash"].from.as_deref(), Some("4.17.20, 4.17.21"));
+    }
+
+    #[test]
+    fn pairs_platforms() {
+        let from = [
+            image(Some("amd64"), &[("a", 1)], json!({})),
+            image(Some("arm64"), &[("b", 1)], json!({})),
+        ];
+        let to = [
+            image(Some("arm64"), &[("c", 1)], json!({})),
+            image(Some("s390x"), &[("d", 1)], json!({})),
+        ];
+
+        let pairs = pair_platforms(&from, &to);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(
+            (platform_key(pairs[0].0), platform_key(pairs[0].1)),
+            ("linux/arm64".to_string(), "linux/arm64".to_string())
+        );
+
+        // Single-platform images are compared whatever their platform
+        assert_eq!(pair_platforms(&from[..1], &to[1..]).len(), 1);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::types::{DockerfileInstruction, ImageLayer};
+
+const SHELL_PREFIX: &str = "/bin/sh -c ";
+const NOP_PREFIX: &str = "#(nop)";
+const BUILDKIT_SUFFIX: &str = "# buildkit";
+
+const INSTRUCTIONS: [&str; 17] = [
+    "ADD",
+    "ARG",
+    "CMD",
+    "COPY",
+    "ENTRYPOINT",
+    "ENV",
+    "EXPOSE",
+    "HEALTHCHECK",
+    "LABEL",
+    "MAINTAINER",
+    "ONBUILD",
+    "RUN",
+    "SHELL",
+    "STOPSIGNAL",
+    "USER",
+    "VOLUME",
+    "WORKDIR",
+];
+
+pub fn instructions(layers: &[ImageLayer]) -> Vec<DockerfileInstruction> {
+    layers
+        .iter()
+        .map(|layer| DockerfileInstruction {
+            instruction: layer.created_by.as_deref().and_then(parse_created_by),
+            created_by: layer.created_by.clone(),
+            digest: layer.digest.clone(),
+            size: layer.size,
+            empty_layer: layer.empty_layer,
+        })
+        .collect()
+}
+
+pub fn render(instructions: &[DockerfileInstruction]) -> String {
+    let mut lines = vec!["# Reconstructed from image history, base image FROM lines are not recorded".to_string()];
+
+    for item in instructions {
+        let Some(instruction) = &item.instruction else {
+            lines.push(format!("# {} layer without history", format_size(item.size)));
+            continue;
+        };
+
+        if !item.empty_layer {
+            lines.push(format!("# {}", format_size(item.size)));
+        }
+        lines.push(instruction.clone());
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Turns a `created_by` history entry into a Dockerfile instruction.
+///
+/// Handles the classic builder format (`/bin/sh -c #(nop)  ENV A=b`, `/bin/sh -c apt-get ...`,
+/// `|2 A=1 B=2 /bin/sh -c ...` with build args) and the BuildKit one (`RUN /bin/sh -c ... # buildkit`).
+pub fn parse_created_by(created_by: &str) -> Option<String> {
+    let mut line = created_by.trim();
+    if line.is_empty() {
+        return None;
+    }
+
+    let buildkit = line.ends_with(BUILDKIT_SUFFIX);
+    if buildkit {
+        line = line.trim_end_matches(BUILDKIT_SUFFIX).trim_end();
+    }
+    if let Some(run) = line.strip_prefix("RUN ") {
+        line = run.trim_start();
+    }
+
+    let line = strip_build_args(line);
+    let instruction = match line.strip_prefix(SHELL_PREFIX) {
+        Some(command) => match command.trim_start().strip_prefix(NOP_PREFIX) {
+            Some(nop) => nop.trim().to_string(),
+            None => format!("RUN {}", command.trim()),
+        },
+        None if starts_with_instruction(line) => line.to_string(),
+        None => format!("RUN {}", line),
+    };
+
+    Some(normalize(&instruction))
+}
+
+fn strip_build_args(line: &str) -> &str {
+    // `|<count> KEY=value ... <command>`, the count says how many args precede the command
+    let Some(rest) = line.strip_prefix('|') else {
+        return line;
+    };
+    let Some((count, mut rest)) = rest.split_once(' ') else {
+        return line;
+    };
+    let Ok(count) = count.parse::<usize>() else {
+        return line;
+    };
+
+    for _ in 0..count {
+        rest = rest.split_once(' ').map_or("", |(_, r)| r);
+    }
+
+    rest.trim_start()
+}
+
+fn starts_with_instruction(line: &str) -> bool {
+    let keyword = line.split_whitespace().next().unwrap_or_default();
+
+    INSTRUCTIONS.contains(&keyword)
+}
+
+fn normalize(instruction: &str) -> String {
+    let (keyword, args) = instruction.split_once(' ').unwrap_or((instruction, ""));
+    let args = args.trim();
+
+    match keyword {
+        // Classic builder prints `ADD file:<hash> in /dest`
+        "ADD" | "COPY" => format!("{} {}", keyword, args.replacen(" in ", " ", 1)),
+        // Classic builder prints exec form arrays Go-style: `["nginx" "-g" "daemon off;"]`
+        "CMD" | "ENTRYPOINT" | "SHELL" if args.starts_with('[') => {
+            format!("{} {}", keyword, args.replace("\" \"", "\", \""))
+        }
+        // `EXPOSE map[80/tcp:{} 443/tcp:{}]`
+        "EXPOSE" if args.starts_with("map[") => {
+            let ports = args
+                .trim_start_matches("map[")
+                .trim_end_matches(']')
+                .split_whitespace()
+                .map(|p| p.trim_end_matches(":{}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("EXPOSE {}", ports)
+        }
+        "RUN" => format!("RUN {}", args.replace(" && ", " \\\n    && ")),
+        _ => instruction.to_string(),
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_history_entries() {
+        let cases = [
+            ("/bin/sh -c #(nop)  ENV PATH=/usr/local/bin", "ENV PATH=/usr/local/bin"),
+            ("/bin/sh -c #(nop) WORKDIR /app", "WORKDIR /app"),
+            ("/bin/sh -c #(nop) ADD file:4b0d5e1c in / ", "ADD file:4b0d5e1c /"),
+            (
+                r#"/bin/sh -c #(nop)  CMD ["nginx" "-g" "daemon off;"]"#,
+                r#"CMD ["nginx", "-g", "daemon off;"]"#,
+            ),
+            (
+                "/bin/sh -c #(nop)  EXPOSE map[80/tcp:{} 443/tcp:{}]",
+                "EXPOSE 80/tcp 443/tcp",
+            ),
+            ("/bin/sh -c apk add --no-cache curl", "RUN apk add --no-cache curl"),
+            (
+                "/bin/sh -c apt-get update && apt-get install -y curl",
+                "RUN apt-get update \\\n    && apt-get install -y curl",
+            ),
+            ("|2 VERSION=1.2 TARGET=prod /bin/sh -c make install", "RUN make install"),
+            ("RUN /bin/sh -c go build ./... # buildkit", "RUN go build ./..."),
+            ("COPY app /app # buildkit", "COPY app /app"),
+            ("USER nobody", "USER nobody"),
+            ("make install", "RUN make install"),
+        ];
+
+        for (created_by, instruction) in cases {
+            assert_eq!(
+                parse_created_by(created_by).as_deref(),
+                Some(instruction),
+                "{}",
+                created_by
+            );
+        }
+        assert_eq!(parse_created_by("  "), None);
+    }
+
+    #[test]
+    fn renders_sizes_for_layers_with_content_only() {
+        let layer = |created_by: Option<&str>, size, empty_layer| ImageLayer {
+            created_by: created_by.map(str::to_string),
+            size,
+            empty_layer,
+            ..ImageLayer::default()
+        };
+        let layers = [
+            layer(None, 2_500_000, false),
+            layer(Some("/bin/sh -c #(nop)  ENV A=b"), 0, true),
+            layer(Some("/bin/sh -c apk add curl"), 1_200, false),
+        ];
+
+        assert_eq!(
+            render(&instructions(&layers)),
+            "# Reconstructed from image history, base image FROM lines are not recorded\n\
+             # 2.5 MB layer without history\n\
+             ENV A=b\n\
+             # 1.2 KB\n\
+             RUN apk add curl\n"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::registry_api::types::{ClientError, Layer};
+use crate::registry_api::RegistryClient;
+use crate::types::{FileEntry, FileKind, FileNode};
+use flate2::read::GzDecoder;
+use rocket::futures::stream::{self, StreamExt};
+use rocket::futures::TryStreamExt;
+use rocket::tokio::task;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::ops::Bound;
+use tar::{Archive, EntryType};
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+/// Layers of an image downloaded at the same time.
+const INDEX_CONCURRENCY: usize = 4;
+
+/// Receives the entries of a layer in archive order, along with a reader over their content.
+pub trait EntryVisitor: Send + 'static {
+    fn visit(&mut self, entry: FileEntry, content: &mut dyn Read) -> io::Result<()>;
+}
+
+impl EntryVisitor for Vec<FileEntry> {
+    fn visit(&mut self, entry: FileEntry, _: &mut dyn Read) -> io::Result<()> {
+        self.push(entry);
+        Ok(())
+    }
+}
+
+/// Streams the layer blob into `visitor`, decompressing it on the fly.
+pub async fn walk_layer<V>(
+    client: &RegistryClient,
+    image: &str,
+    layer: &Layer,
+    mut visitor: V,
+) -> Result<V, ClientError>
+where
+    V: EntryVisitor,
+{
+    let stream = client.get_blob_stream(image, &layer.digest).await?;
+    let reader = SyncIoBridge::new(StreamReader::new(Box::pin(stream.map_err(io::Error::other))));
+    let compression = layer.compression();
+
+    let result = task::spawn_blocking(move || {
+        match compression {
+            "gzip" => walk_entries(GzDecoder::new(reader), &mut visitor),
+            "zstd" => zstd::Decoder::new(reader).and_then(|r| walk_entries(r, &mut visitor)),
+            _ => walk_entries(reader, &mut visitor),
+        }
+        .map(|_| visitor)
+    })
+    .await
+    .unwrap_or_else(|e| Err(io::Error::other(e)));
+
+    result.map_err(|e| {
+        error!("Can't read layer {}: {:?}", layer.digest, e);
+        ClientError::Decode {
+            message: e.to_string(),
+            excerpt: layer.digest.clone(),
+        }
+    })
+}
+
+pub async fn index_layer(client: &RegistryClient, image: &str, layer: &Layer) -> Result<Vec<FileEntry>, ClientError> {
+    walk_layer(client, image, layer, Vec::new()).await
+}
+
+/// Indexes every layer and stacks them into the filesystem a container would see.
+pub async fn merged_filesystem(
+    client: &RegistryClient,
+    image: &str,
+    layers: &[Layer],
+) -> Result<Vec<FileEntry>, ClientError> {
+    // Collected first, a lazy `map` over borrowed layers trips up the Send check of route futures
+    let futures: Vec<_> = layers.iter().map(|layer| index_layer(client, image, layer)).collect();
+    let indexed: Vec<Vec<FileEntry>> = stream::iter(futures).buffered(INDEX_CONCURRENCY).try_collect().await?;
+
+    Ok(merge_layers(layers.iter().map(|l| l.digest.clone()).zip(indexed)))
+}
+
+fn walk_entries(reader: impl Read, visitor: &mut impl EntryVisitor) -> io::Result<()> {
+    let mut archive = Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = normalize_path(&entry.path()?.to_string_lossy());
+        if path.is_empty() || path == "." {
+            continue;
+        }
+
+        let header = entry.header();
+        let name = path.rsplit('/').next().unwrap_or_default();
+
+        let file = FileEntry {
+            kind: file_kind(header.entry_type()),
+            size: entry.size(),
+            mode: header.mode()?,
+            uid: header.uid()?,
+            gid: header.gid()?,
+            link_target: entry.link_name()?.map(|l| l.to_string_lossy().into_owned()),
+            whiteout: name.starts_with(WHITEOUT_PREFIX),
+            layer: None,
+            path,
+        };
+        visitor.visit(file, &mut entry)?;
+    }
+
+    Ok(())
+}
+
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn file_kind(entry_type: EntryType) -> FileKind {
+    match entry_type {
+        EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => FileKind::File,
+        EntryType::Directory => FileKind::Directory,
+        EntryType::Symlink => FileKind::Symlink,
+        EntryType::Link => FileKind::Hardlink,
+        _ => FileKind::Other,
+    }
+}
+
+/// Applies layers in order the way overlay filesystems do: `.wh.<name>` deletes `<name>` from the
+/// lower layers and `.wh..wh..opq` hides everything they put into its directory.
+pub fn merge_layers(layers: impl IntoIterator<Item = (String, Vec<FileEntry>)>) -> Vec<FileEntry> {
+    let mut files: BTreeMap<String, FileEntry> = BTreeMap::new();
+
+    for (digest, entries) in layers {
+        let (whiteouts, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.whiteout);
+
+        for whiteout in whiteouts {
+            let (dir, name) = whiteout.path.rsplit_once('/').unwrap_or(("", &whiteout.path));
+            if name == OPAQUE_WHITEOUT {
+                remove_children(&mut files, dir);
+                continue;
+            }
+
+            let target = name.trim_start_matches(WHITEOUT_PREFIX);
+            let target = if dir.is_empty() {
+                target.to_string()
+            } else {
+                format!("{}/{}", dir, target)
+            };
+            files.remove(&target);
+            remove_children(&mut files, &target);
+        }
+
+        for entry in entries {
+            // A file replacing a directory hides whatever the lower layers had inside it
+            if entry.kind != FileKind::Directory {
+                remove_children(&mut files, &entry.path);
+            }
+            files.insert(
+                entry.path.clone(),
+                FileEntry {
+                    layer: Some(digest.clone()),
+                    ..entry
+                },
+            );
+        }
+    }
+
+    files.into_values().collect()
+}
+
+fn remove_children(files: &mut BTreeMap<String, FileEntry>, dir: &str) {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    let children: Vec<String> = files
+        .range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+        .take_while(|(path, _)| path.starts_with(&prefix))
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    for path in children {
+        files.remove(&path);
+    }
+}
+
+pub fn build_tree(entries: Vec<FileEntry>) -> Vec<FileNode> {
+    #[derive(Default)]
+    struct Node {
+        entry: Option<FileEntry>,
+        children: BTreeMap<String, Node>,
+    }
+
+    fn into_nodes(children: BTreeMap<String, Node>, prefix: &str) -> Vec<FileNode> {
+        children
+            .into_iter()
+            .map(|(name, node)| {
+                let path = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}/{}", prefix, name)
+                };
+
+                FileNode {
+                    children: into_nodes(node.children, &path),
+                    // Parent directories aren't always archived on their own
+                    entry: node.entry.unwrap_or(FileEntry {
+                        path,
+                        ..Default::default()
+                    }),
+                    name,
+                }
+            })
+            .collect()
+    }
+
+    let mut root = Node::default();
+    for entry in entries {
+        let node = entry.path.split('/').fold(&mut root, |node, part| {
+            node.children.entry(part.to_string()).or_default()
+        });
+        node.entry = Some(entry);
+    }
+
+    into_nodes(root.children, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, kind: FileKind) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            kind,
+            whiteout: path.rsplit('/').next().unwrap_or_default().starts_with(WHITEOUT_PREFIX),
+            ..Default::default()
+        }
+    }
+
+    fn file(path: &str) -> FileEntry {
+        entry(path, FileKind::File)
+    }
+
+    fn dir(path: &str) -> FileEntry {
+        entry(path, FileKind::Directory)
+    }
+
+    fn merged(layers: Vec<Vec<FileEntry>>) -> Vec<(String, String)> {
+        let layers = layers.into_iter().enumerate().map(|(i, e)| (format!("l{}", i), e));
+        merge_layers(layers)
+            .into_iter()
+            .map(|e| (e.path, e.layer.unwrap_or_default()))
+            .collect()
+    }
+
+    fn paths(files: &[(String, String)]) -> Vec<&str> {
+        files.iter().map(|(path, _)| path.as_str()).collect()
+    }
+
+    #[test]
+    fn whiteouts_delete_files_and_directories() {
+        let files = merged(vec![
+            vec![
+                dir("etc"),
+                file("etc/a"),
+                file("etc/b"),
+                dir("var"),
+                file("var/log"),
+                file("top"),
+            ],
+            vec![file("etc/.wh.a"), file(".wh.var"), file(".wh.top")],
+        ]);
+
+        assert_eq!(paths(&files), vec!["etc", "etc/b"]);
+    }
+
+    #[test]
+    fn opaque_whiteouts_hide_lower_directory_contents() {
+        let files = merged(vec![
+            vec![
+                dir("etc"),
+                file("etc/a"),
+                dir("etc/sub"),
+                file("etc/sub/c"),
+                file("etcetera"),
+            ],
+            vec![dir("etc"), file("etc/.wh..wh..opq"), file("etc/new")],
+        ]);
+
+        assert_eq!(paths(&files), vec!["etc", "etc/new", "etcetera"]);
+        assert_eq!(files[1].1, "l1");
+        assert_eq!(files[2].1, "l0");
+    }
+
+    #[test]
+    fn files_come_back_after_a_whiteout() {
+        let files = merged(vec![
+            vec![dir("etc"), file("etc/a")],
+            vec![file("etc/.wh.a")],
+            vec![file("etc/a")],
+        ]);
+        assert_eq!(
+            files,
+            vec![
+                ("etc".to_string(), "l0".to_string()),
+                ("etc/a".to_string(), "l2".to_string())
+            ]
+        );
+
+        // The whiteout applies to lower layers only, not to what its own layer adds
+        let files = merged(vec![
+            vec![dir("opt"), file("opt/x")],
+            vec![file("opt/.wh.x"), file("opt/x")],
+        ]);
+        assert_eq!(files[1], ("opt/x".to_string(), "l1".to_string()));
+    }
+
+    #[test]
+    fn files_replacing_directories_hide_their_contents() {
+        let files = merged(vec![
+            vec![dir("app"), file("app/main")],
+            vec![entry("app", FileKind::Symlink)],
+        ]);
+
+        assert_eq!(paths(&files), vec!["app"]);
+    }
+}
@@ -1,50 +1,168 @@
 #[macro_use]
 extern crate rocket;
 
-use crate::registry_api::{Config as RegistryConfig, RegistryClient};
-use crate::types::Config as AppConfig;
+use crate::settings::Settings;
 use dotenv::dotenv;
-use envconfig::Envconfig;
 use rocket::fs::FileServer;
+use std::env;
+use std::path::PathBuf;
+use std::process;
 
+mod auth;
+mod bulk;
+mod copy;
+mod diff;
+mod dockerfile;
+mod layers;
 mod manager;
+mod registries;
 mod registry_api;
+mod replication;
+mod retention;
 mod routes;
+mod sbom;
+mod settings;
+mod store;
+#[cfg(test)]
+mod testing;
 mod types;
+mod util;
+mod vulnerabilities;
 
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
+async fn main() -> Result<(), Box<rocket::Error>> {
     dotenv().ok();
     pretty_env_logger::init_timed();
 
-    let config = AppConfig::init_from_env().expect("Can't load config from environment");
-    let registry_config = RegistryConfig {
-        base_uri: config.host.clone(),
-        is_secured: !config.unsecured,
-        http_basic_user: config.http_basic_user.clone(),
-        http_basic_pass: config.http_basic_pass.clone(),
+    let settings = match Settings::load(config_file()) {
+        Ok(settings) => settings,
+        Err(errors) => {
+            for e in errors {
+                error!("Invalid configuration: {}", e);
+            }
+            process::exit(1);
+        }
     };
+    settings.watch();
 
-    let _rocket = rocket::build()
-        .manage(config.clone())
-        .manage(RegistryClient::new(&registry_config))
+    let config = settings.config();
+    // Starting without the rules or policies of a file that can't be read would lose them on the next save
+    let replication = match replication::Replication::new(settings.registries.clone(), config.replication_file.clone())
+    {
+        Ok(replication) => replication,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let retention = match retention::Retention::new(settings.registries.clone(), config.retention_file.clone()) {
+        Ok(retention) => retention,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
+    replication.start_scheduler();
+    retention.start_scheduler();
+    let authentication = match auth::Authentication::new(&config) {
+        Ok(authentication) => authentication,
+        Err(e) => {
+            error!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+    if !authentication.enabled() {
+        warn!("No users file, OpenID provider or proxy header is configured, everyone can use harbui");
+    } else if settings.access().is_empty() {
+        warn!("No [[access]] grants in the config file, users won't see any repository");
+    }
+
+    let mut figment = rocket::Config::figment();
+    if let Some(secret_key) = &config.secret_key {
+        figment = figment.merge(("secret_key", secret_key));
+    }
+
+    let _rocket = rocket::custom(figment)
+        .attach(routes::RequestIdFairing)
+        .attach(routes::RegistryScopeFairing)
+        .manage(settings.registries.clone())
+        .manage(settings)
+        .manage(sbom::InventoryCache::default())
+        .manage(vulnerabilities::AdvisoryDatabase::new(config.advisories_dir.clone()))
+        .manage(replication)
+        .manage(retention)
+        .manage(authentication)
         .mount(
             "/api",
             routes![
+                routes::session::get_login_methods,
+                routes::session::login,
+                routes::session::oidc_login,
+                routes::session::oidc_callback,
+                routes::session::logout,
+                routes::session::get_session,
+                routes::tokens::get_tokens,
+                routes::tokens::create_token,
+                routes::tokens::revoke_token,
+                routes::tokens::revoke_user_tokens,
                 routes::api::get_repositories,
                 routes::api::get_images_by_tag,
+                routes::api::get_image_config,
+                routes::api::get_image_dockerfile,
+                routes::api::get_image_diff,
+                routes::api::get_layer_tree,
+                routes::api::get_image_filesystem,
+                routes::api::get_image_sbom,
+                routes::api::get_image_vulnerabilities,
                 routes::api::get_tags,
                 routes::api::get_config,
+                routes::api::get_registries,
                 routes::api::count_users,
+                routes::api::get_namespaces,
                 routes::api::count_repositories,
+                routes::api::get_delete_impact,
                 routes::api::delete_image,
+                routes::api::bulk_delete,
+                routes::api::retag_image,
+                routes::api::promote_image,
+                routes::api::get_replication_rules,
+                routes::api::create_replication_rule,
+                routes::api::update_replication_rule,
+                routes::api::delete_replication_rule,
+                routes::api::run_replication_rule,
+                routes::api::get_replication_jobs,
+                routes::api::get_replication_job,
+                routes::api::get_retention_policies,
+                routes::api::create_retention_policy,
+                routes::api::update_retention_policy,
+                routes::api::delete_retention_policy,
+                routes::api::run_retention_policy,
+                routes::api::dry_run_retention_policy,
+                routes::api::get_retention_runs,
+                routes::api::get_retention_run,
             ],
         )
         .mount("/image", routes![routes::image])
         .mount("/", FileServer::from("public"))
         .register("/", catchers![routes::error_handler])
         .launch()
-        .await?;
+        .await
+        .map_err(Box::new)?;
 
     Ok(())
 }
+
+/// `--config <path>` or `HARBUI_CONFIG`.
+fn config_file() -> Option<PathBuf> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    env::var_os("HARBUI_CONFIG").map(PathBuf::from)
+}
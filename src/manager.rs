@@ -1,72 +1,270 @@
-use crate::registry_api::types::{ImageConfigResponse, Manifest, OCIImageManifestV1Short, RegistryAnswer};
+use crate::registry_api::types::{
+    ClientError, History, ImageConfigResponse, Layer, Manifest, OCIImageConfigV1, OCIImageManifestV1Short, Platform,
+};
 use crate::registry_api::RegistryClient;
-use crate::types::ImageManifest;
+use crate::routes::is_valid_repository_pattern;
+use crate::types::{ImageLayer, ImageManifest, NamespaceNode};
+use crate::util::matches;
+use crate::vulnerabilities::Scanner;
 use rocket::futures::future::join_all;
-use rocket::State;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 
-pub async fn get_manifests_from_list<'a, I>(
-    client: &State<RegistryClient>,
-    manifests_iter: I,
+/// Manifest lookups at the same time when walking every tag of a repository.
+const TAG_LOOKUP_CONCURRENCY: usize = 8;
+
+pub struct PlatformManifest {
+    pub digest: String,
+    pub platform: Option<Platform>,
+    pub config: OCIImageConfigV1,
+    pub layers: Vec<Layer>,
+}
+
+impl PlatformManifest {
+    pub fn get_total_size(&self) -> u64 {
+        self.layers.iter().map(|i| i.size).sum()
+    }
+}
+
+/// Severity counts are attached when a scanner is given and the image was scanned before,
+/// scanning is left to the vulnerabilities route not to download every layer here.
+pub async fn get_manifests(
+    client: &RegistryClient,
     image: &str,
-) -> Vec<ImageManifest>
-where
-    I: Iterator<Item = &'a OCIImageManifestV1Short>,
-{
-    let futures = manifests_iter.filter_map(|item| {
-        if item.platform.os != "unknown" {
-            return Some(client.get_manifest(image, &item.digest));
-        }
+    reference: &str,
+    scanner: Option<&Scanner<'_>>,
+) -> Result<Vec<ImageManifest>, ClientError> {
+    let configs = resolve_configs(client, image, reference, None).await?;
 
-        None
-    });
+    let mut manifests = Vec::new();
+    for (manifest, config) in configs {
+        let vulnerabilities = scanner.and_then(|scanner| scanner.cached_counts(&manifest));
 
-    let ans: Vec<RegistryAnswer<Manifest>> = join_all(futures).await.into_iter().filter_map(|ans| ans.ok()).collect();
+        manifests.push(ImageManifest {
+            total_size: manifest.get_total_size(),
+            author: config.author.unwrap_or_default(),
+            layers: correlate_layers(&manifest.layers, &config.history),
+            digest: manifest.digest,
+            os: config.os,
+            architecture: config.architecture,
+            vulnerabilities,
+        });
+    }
 
-    let mut configs: HashMap<String, (String, u64)> = HashMap::new();
+    Ok(manifests)
+}
 
-    ans.into_iter().for_each(|item| {
-        let manifest_digest = item.digest.unwrap();
-        match item.content {
-            Manifest::OCIImageManifestV1(c) => {
-                let config_digest = c.config.digest.clone();
-                configs.insert(config_digest, (manifest_digest, c.get_total_size()));
-            }
-            Manifest::DockerDistributionManifestV2(c) => {
-                let config_digest = c.config.digest.clone();
-                configs.insert(config_digest, (manifest_digest, c.get_total_size()));
+/// Pairs manifest layers with the history entries that produced them.
+/// Empty-layer history entries (ENV, CMD, ...) are kept with a zero size so the build steps stay in order.
+pub fn correlate_layers(layers: &[Layer], history: &[History]) -> Vec<ImageLayer> {
+    let mut layers = layers.iter();
+    let mut result: Vec<ImageLayer> = history
+        .iter()
+        .map(|entry| {
+            let layer = if entry.empty_layer { None } else { layers.next() };
+
+            ImageLayer {
+                created: entry.created.clone(),
+                created_by: entry.created_by.clone(),
+                comment: entry.comment.clone(),
+                empty_layer: entry.empty_layer,
+                ..layer.map(image_layer).unwrap_or(ImageLayer {
+                    distributable: true,
+                    ..Default::default()
+                })
             }
-            _ => {}
+        })
+        .collect();
+
+    // Images built without history (or with a truncated one) still list every layer
+    result.extend(layers.map(image_layer));
+
+    result
+}
+
+fn image_layer(layer: &Layer) -> ImageLayer {
+    ImageLayer {
+        digest: Some(layer.digest.clone()),
+        media_type: Some(layer.media_type.clone()),
+        compression: Some(layer.compression().to_string()),
+        distributable: layer.is_distributable(),
+        size: layer.size,
+        ..Default::default()
+    }
+}
+
+/// Resolves a tag or digest to the image manifests behind it, descending into
+/// indexes and manifest lists. Attestation entries (`unknown/unknown`) are skipped.
+pub async fn resolve_manifests(
+    client: &RegistryClient,
+    image: &str,
+    reference: &str,
+) -> Result<Vec<PlatformManifest>, ClientError> {
+    let root = client.get_manifest(image, reference).await?;
+    let children = match &root.content {
+        Manifest::OCIImageIndexV1(m) => &m.manifests,
+        Manifest::DockerDistributionManifestListV2(m) => &m.manifests,
+        Manifest::OCIImageManifestV1(m) => {
+            return Ok(vec![PlatformManifest {
+                digest: root.digest.unwrap_or_default(),
+                platform: None,
+                config: m.config.clone(),
+                layers: m.layers.clone(),
+            }]);
+        }
+        Manifest::DockerDistributionManifestV2(m) => {
+            return Ok(vec![PlatformManifest {
+                digest: root.digest.unwrap_or_default(),
+                platform: None,
+                config: m.config.clone(),
+                layers: m.layers.clone(),
+            }]);
         }
-    });
+    };
+
+    let children: Vec<&OCIImageManifestV1Short> = children.iter().filter(|m| m.platform.os != "unknown").collect();
+    let futures = children.iter().map(|item| client.get_manifest(image, &item.digest));
+    let answers = join_all(futures).await.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+    Ok(children
+        .into_iter()
+        .zip(answers)
+        .filter_map(|(item, ans)| {
+            let (config, layers) = match ans.content {
+                Manifest::OCIImageManifestV1(m) => (m.config, m.layers),
+                Manifest::DockerDistributionManifestV2(m) => (m.config, m.layers),
+                _ => return None,
+            };
 
-    get_manifests(client, &configs, image).await
+            Some(PlatformManifest {
+                digest: item.digest.clone(),
+                platform: Some(item.platform.clone()),
+                config,
+                layers,
+            })
+        })
+        .collect())
 }
 
-pub async fn get_manifests(
-    client: &State<RegistryClient>,
-    configs: &HashMap<String, (String, u64)>,
+pub async fn resolve_configs(
+    client: &RegistryClient,
     image: &str,
-) -> Vec<ImageManifest> {
-    let config_futures = configs.keys().map(|s| client.get_config(image, s));
-    let configs_ans: Vec<RegistryAnswer<ImageConfigResponse>> = join_all(config_futures)
-        .await
+    reference: &str,
+    platform: Option<&str>,
+) -> Result<Vec<(PlatformManifest, ImageConfigResponse)>, ClientError> {
+    let manifests = resolve_manifests(client, image, reference).await?;
+    let futures = manifests.iter().map(|m| client.get_config(image, &m.config.digest));
+    let configs = join_all(futures).await.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+    Ok(manifests
         .into_iter()
-        .filter_map(|ans| ans.ok())
-        .collect();
+        .zip(configs)
+        .map(|(mut manifest, config)| {
+            manifest.platform.get_or_insert_with(|| config.content.platform());
+            (manifest, config.content)
+        })
+        .filter(|(manifest, _)| match (platform, &manifest.platform) {
+            (Some(filter), Some(p)) => p.matches(filter),
+            _ => true,
+        })
+        .collect())
+}
+
+/// The newest creation date among the platforms of an image. Registries don't record push
+/// dates, so this is the closest there is.
+pub async fn image_created(
+    client: &RegistryClient,
+    image: &str,
+    reference: &str,
+) -> Result<Option<OffsetDateTime>, ClientError> {
+    Ok(resolve_configs(client, image, reference, None)
+        .await?
+        .into_iter()
+        .filter_map(|(_, config)| OffsetDateTime::parse(config.created.as_deref()?, &Rfc3339).ok())
+        .max())
+}
+
+/// Repositories matching `*` and `?` patterns. Patterns without wildcards are used as is,
+/// so registries without a catalog (e.g. Docker Hub) work with explicit repositories.
+/// Patterns that aren't repository names are ignored.
+pub async fn matching_repositories(client: &RegistryClient, patterns: &[String]) -> Result<Vec<String>, ClientError> {
+    let (patterns, invalid): (Vec<String>, Vec<String>) = patterns
+        .iter()
+        .cloned()
+        .partition(|pattern| is_valid_repository_pattern(pattern));
+    for pattern in invalid {
+        warn!("Ignoring repository pattern {:?}, it isn't a repository name", pattern);
+    }
+    if !patterns.iter().any(|pattern| pattern.contains(['*', '?'])) {
+        return Ok(patterns);
+    }
 
-    configs_ans
+    Ok(client
+        .get_catalog()
+        .await?
+        .content
+        .repositories
         .into_iter()
-        .map(|ans| {
-            let manifest_digest = configs.get(&ans.digest.unwrap()).unwrap();
-
-            ImageManifest {
-                digest: manifest_digest.0.clone(),
-                author: ans.content.author.unwrap_or_default(),
-                os: ans.content.os,
-                architecture: ans.content.architecture,
-                total_size: manifest_digest.1,
+        .filter(|repository| patterns.iter().any(|pattern| matches(pattern, repository)))
+        .collect())
+}
+
+/// Every tag of the repository with the digest it points at.
+pub async fn tag_digests(client: &RegistryClient, repository: &str) -> Result<Vec<(String, String)>, ClientError> {
+    let tags = client.get_tags(repository).await?.content.tags.unwrap_or_default();
+    let mut digests = Vec::new();
+    for chunk in tags.chunks(TAG_LOOKUP_CONCURRENCY) {
+        let answers = join_all(chunk.iter().map(|tag| client.head_manifest(repository, tag))).await;
+        for (tag, digest) in chunk.iter().zip(answers) {
+            // Tags deleted since the listing have no digest
+            if let Some(digest) = digest? {
+                digests.push((tag.clone(), digest));
             }
-        })
-        .collect()
+        }
+    }
+
+    Ok(digests)
+}
+
+pub fn build_namespace_tree(repositories: &[String]) -> Vec<NamespaceNode> {
+    #[derive(Default)]
+    struct Node {
+        is_repository: bool,
+        children: BTreeMap<String, Node>,
+    }
+
+    fn into_nodes(children: BTreeMap<String, Node>, prefix: &str) -> Vec<NamespaceNode> {
+        children
+            .into_iter()
+            .map(|(name, node)| {
+                let path = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}/{}", prefix, name)
+                };
+                let children = into_nodes(node.children, &path);
+                let repositories_count =
+                    usize::from(node.is_repository) + children.iter().map(|c| c.repositories_count).sum::<usize>();
+
+                NamespaceNode {
+                    name,
+                    path,
+                    is_repository: node.is_repository,
+                    repositories_count,
+                    children,
+                }
+            })
+            .collect()
+    }
+
+    let mut root = Node::default();
+    for repository in repositories {
+        let node = repository.split('/').fold(&mut root, |node, part| {
+            node.children.entry(part.to_string()).or_default()
+        });
+        node.is_repository = true;
+    }
+
+    into_nodes(root.children, "")
 }
@@ -0,0 +1,161 @@
+use crate::registry_api::{Config as RegistryConfig, RegistryClient};
+use crate::types::{Config, Operation, Permissions};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Id of the registry configured by `REGISTRY_HOST`.
+pub const DEFAULT_REGISTRY: &str = "default";
+
+pub struct RegistryEntry {
+    pub id: String,
+    pub name: String,
+    pub host: String,
+    pub permissions: Permissions,
+    pub client: RegistryClient,
+}
+
+/// Every registry harbui talks to: the one from `REGISTRY_HOST` first, then the ones listed in
+/// `HARBUI_REGISTRIES`, each configured by `HARBUI_REGISTRY_<ID>_*` variables. Clones share the
+/// list, so a configuration reload reaches every holder.
+#[derive(Clone)]
+pub struct Registries {
+    entries: Arc<RwLock<Arc<Vec<Arc<RegistryEntry>>>>>,
+}
+
+impl Registries {
+    pub fn new(entries: Vec<RegistryEntry>) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(Arc::new(entries.into_iter().map(Arc::new).collect()))),
+        }
+    }
+
+    /// Builds the entries from configuration variables, reporting every problem at once.
+    pub fn build(config: &Config, vars: &HashMap<String, String>) -> Result<Vec<RegistryEntry>, Vec<String>> {
+        let mut entries = vec![RegistryEntry {
+            id: DEFAULT_REGISTRY.to_string(),
+            name: config.registry_name.clone().unwrap_or_else(|| config.host.clone()),
+            host: config.host.clone(),
+            permissions: Permissions {
+                delete: config.deleting_allowed,
+                retag: config.retagging_allowed.unwrap_or(config.copying_allowed),
+                push: config.pushing_allowed.unwrap_or(config.copying_allowed),
+                garbage_collect: config.garbage_collecting_allowed,
+            },
+            client: RegistryClient::new(&RegistryConfig {
+                base_uri: config.host.clone(),
+                is_secured: !config.unsecured,
+                http_basic_user: config.http_basic_user.clone(),
+                http_basic_pass: config.http_basic_pass.clone(),
+            }),
+        }];
+        let mut errors = Vec::new();
+
+        let ids = config.registries.as_deref().unwrap_or_default().split(',');
+        for id in ids.map(str::trim).filter(|id| !id.is_empty()) {
+            if !is_valid_id(id) {
+                errors.push(format!(
+                    "Invalid registry id {:?}, use lowercase letters, digits and '-'",
+                    id
+                ));
+                continue;
+            }
+            if entries.iter().any(|entry| entry.id == id) {
+                errors.push(format!("Registry {} is configured twice", id));
+                continue;
+            }
+
+            let prefix = registry_prefix(id);
+            let var = |name: &str| {
+                vars.get(&format!("{}{}", prefix, name))
+                    .filter(|value| !value.is_empty())
+            };
+            let mut flag = |name: &str, default: bool| match var(name).map(String::as_str) {
+                None => default,
+                Some("false") => false,
+                Some("true") => true,
+                Some(value) => {
+                    errors.push(format!("{}{} must be true or false, not {:?}", prefix, name, value));
+                    false
+                }
+            };
+            // Like the default registry, retagging and pushing follow HARBUI_COPYING_ALLOWED unless set
+            let permissions = Permissions {
+                delete: flag(Operation::Delete.switch(), false),
+                retag: flag(Operation::Retag.switch(), config.copying_allowed),
+                push: flag(Operation::Push.switch(), config.copying_allowed),
+                garbage_collect: flag(Operation::GarbageCollect.switch(), false),
+            };
+            let unsecured = flag("UNSECURED", false);
+            let Some(host) = var("HOST").cloned() else {
+                errors.push(format!("Registry {} has no host, set {}HOST", id, prefix));
+                continue;
+            };
+            if let Err(e) = validate_host(&host) {
+                errors.push(format!("{}HOST: {}", prefix, e));
+            }
+
+            entries.push(RegistryEntry {
+                id: id.to_string(),
+                name: var("NAME").cloned().unwrap_or_else(|| id.to_string()),
+                permissions,
+                client: RegistryClient::new(&RegistryConfig {
+                    base_uri: host.clone(),
+                    is_secured: !unsecured,
+                    http_basic_user: var("HTTP_BASIC_USER").cloned(),
+                    http_basic_pass: var("HTTP_BASIC_PASSWORD").cloned(),
+                }),
+                host,
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(entries)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn replace(&self, entries: Vec<RegistryEntry>) {
+        let entries = Arc::new(entries.into_iter().map(Arc::new).collect());
+        *self.entries.write().unwrap_or_else(|e| e.into_inner()) = entries;
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<RegistryEntry>> {
+        self.all().iter().find(|entry| entry.id == id).cloned()
+    }
+
+    pub fn default_registry(&self) -> Arc<RegistryEntry> {
+        self.all()[0].clone()
+    }
+
+    pub fn all(&self) -> Arc<Vec<Arc<RegistryEntry>>> {
+        self.entries.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// `HARBUI_REGISTRY_<ID>_`, with the id upper-cased and `-` replaced by `_`.
+pub fn registry_prefix(id: &str) -> String {
+    format!("HARBUI_REGISTRY_{}_", id.to_uppercase().replace('-', "_"))
+}
+
+/// Registry hosts are `host[:port]`, the scheme comes from the `UNSECURED` switch.
+pub fn validate_host(host: &str) -> Result<(), String> {
+    if host.contains("://") {
+        return Err(format!(
+            "{:?} must not contain a scheme, use the UNSECURED switch for http",
+            host
+        ));
+    }
+    if host.contains('/') || host.chars().any(char::is_whitespace) {
+        return Err(format!("{:?} must be host[:port] without a path", host));
+    }
+
+    Ok(())
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
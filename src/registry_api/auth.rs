@@ -0,0 +1,187 @@
+use reqwest::header::HeaderValue;
+use reqwest::{Method, Url};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Tokens are refreshed a bit before the server-side expiry to avoid races with in-flight requests.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(5);
+/// Lifetime assumed by the distribution spec when the token server doesn't send `expires_in`.
+const DEFAULT_EXPIRES_IN: u64 = 60;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BearerChallenge {
+    pub realm: String,
+    pub service: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl BearerChallenge {
+    /// Parses `WWW-Authenticate: Bearer realm="...",service="...",scope="..."`.
+    /// Returns `None` for any other auth scheme.
+    pub fn parse(header: &HeaderValue) -> Option<Self> {
+        let value = header.to_str().ok()?.trim();
+        let (scheme, params) = value.split_once(' ')?;
+
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+
+        let params = parse_params(params);
+
+        Some(Self {
+            realm: params.get("realm")?.clone(),
+            service: params.get("service").cloned(),
+            scope: params.get("scope").cloned(),
+        })
+    }
+
+    pub fn cache_key(&self) -> String {
+        self.scope.clone().unwrap_or_default()
+    }
+}
+
+fn parse_params(input: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() {
+            break;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+        } else {
+            value = chars.by_ref().take_while(|c| *c != ',').collect();
+        }
+
+        params.insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    params
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TokenResponse {
+    pub token: Option<String>,
+    pub access_token: Option<String>,
+    pub expires_in: Option<u64>,
+}
+
+impl TokenResponse {
+    /// Docker's token spec names the field `token`, OAuth2-flavoured servers use `access_token`.
+    pub fn into_token(self) -> Option<BearerToken> {
+        let value = self.token.or(self.access_token)?;
+        let expires_in = Duration::from_secs(self.expires_in.unwrap_or(DEFAULT_EXPIRES_IN));
+
+        Some(BearerToken {
+            value,
+            expires_at: Instant::now() + expires_in.saturating_sub(EXPIRY_MARGIN),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BearerToken {
+    pub value: String,
+    expires_at: Instant,
+}
+
+impl BearerToken {
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TokenCache {
+    tokens: Arc<RwLock<HashMap<String, BearerToken>>>,
+}
+
+impl TokenCache {
+    pub fn get(&self, scope: &str) -> Option<BearerToken> {
+        let tokens = self.tokens.read().ok()?;
+
+        tokens.get(scope).filter(|t| !t.is_expired()).cloned()
+    }
+
+    pub fn insert(&self, scope: String, token: BearerToken) {
+        if let Ok(mut tokens) = self.tokens.write() {
+            tokens.retain(|_, t| !t.is_expired());
+            tokens.insert(scope, token);
+        }
+    }
+}
+
+/// Guesses the scope the registry will ask for, so a cached token can be sent upfront
+/// instead of waiting for a 401 challenge on every request.
+pub fn scope_hint(method: &Method, url: &Url) -> Option<String> {
+    let path = url.path().strip_prefix("/v2/")?;
+
+    if path == "_catalog" {
+        return Some("registry:catalog:*".to_string());
+    }
+
+    let name = ["/manifests/", "/blobs/", "/tags/"]
+        .iter()
+        .filter_map(|marker| path.rfind(marker).map(|idx| &path[..idx]))
+        .max_by_key(|name| name.len())?;
+
+    let action = match *method {
+        Method::GET | Method::HEAD => "pull",
+        Method::DELETE => "delete",
+        _ => "pull,push",
+    };
+
+    Some(format!("repository:{}:{}", name, action))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bearer_challenges() {
+        let header = HeaderValue::from_static(
+            r#"Bearer realm="https://auth.example.com/token",service="registry",scope="repository:a/b:pull,push""#,
+        );
+
+        assert_eq!(
+            BearerChallenge::parse(&header),
+            Some(BearerChallenge {
+                realm: "https://auth.example.com/token".to_string(),
+                service: Some("registry".to_string()),
+                scope: Some("repository:a/b:pull,push".to_string()),
+            })
+        );
+        assert_eq!(
+            BearerChallenge::parse(&HeaderValue::from_static(r#"Basic realm="x""#)),
+            None
+        );
+    }
+
+    #[test]
+    fn hints_scopes_of_nested_repositories() {
+        let url = Url::parse("http://registry/v2/team/app/manifests/latest").unwrap();
+
+        assert_eq!(
+            scope_hint(&Method::DELETE, &url),
+            Some("repository:team/app:delete".to_string())
+        );
+    }
+}
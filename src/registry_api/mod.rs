@@ -1,8 +1,15 @@
+use crate::registry_api::auth::{scope_hint, BearerChallenge, BearerToken, TokenCache, TokenResponse};
 use crate::registry_api::types::*;
-use reqwest::header::ACCEPT;
-use reqwest::RequestBuilder;
+use bytes::Bytes;
+use reqwest::header::{ACCEPT, CONTENT_RANGE, CONTENT_TYPE, LINK, LOCATION, RANGE, WWW_AUTHENTICATE};
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use rocket::futures::{stream, Stream, TryStreamExt};
+use rocket::http::{Status, StatusClass};
 use serde::de::DeserializeOwned;
+use std::collections::HashSet;
+use std::error::Error;
 
+mod auth;
 pub mod types;
 
 #[derive(Clone, Debug)]
@@ -18,6 +25,7 @@ pub struct RegistryClient {
     client: reqwest::Client,
     url: String,
     basic_auth: Option<BasicAuth>,
+    tokens: TokenCache,
 }
 
 #[derive(Clone, Debug)]
@@ -48,35 +56,64 @@ impl RegistryClient {
             client,
             url,
             basic_auth,
+            tokens: TokenCache::default(),
         }
     }
 
     pub async fn get_catalog(&self) -> RegistryResponse<CatalogResponse> {
-        let request = self.client.get(format!("{}/v2/_catalog", self.url));
+        self.collect_pages(self.catalog_pages(None)).await
+    }
+
+    pub async fn get_catalog_page(&self, n: Option<usize>, last: Option<&str>) -> RegistryResponse<CatalogResponse> {
+        let request = self
+            .client
+            .get(format!("{}/v2/_catalog", self.url))
+            .query(&page_query(n, last));
 
         self.send::<CatalogResponse>(request).await
     }
 
+    pub fn catalog_pages(&self, n: Option<usize>) -> impl Stream<Item = RegistryResponse<CatalogResponse>> + '_ {
+        let first = self
+            .client
+            .get(format!("{}/v2/_catalog", self.url))
+            .query(&page_query(n, None));
+
+        self.pages(first)
+    }
+
     pub async fn get_tags(&self, image: &str) -> RegistryResponse<TagsResponse> {
-        let request = self.client.get(format!("{}/v2/{}/tags/list", self.url, image));
+        self.collect_pages(self.tags_pages(image, None)).await
+    }
+
+    pub async fn get_tags_page(
+        &self,
+        image: &str,
+        n: Option<usize>,
+        last: Option<&str>,
+    ) -> RegistryResponse<TagsResponse> {
+        let request = self
+            .client
+            .get(format!("{}/v2/{}/tags/list", self.url, image))
+            .query(&page_query(n, last));
 
         self.send::<TagsResponse>(request).await
     }
 
+    pub fn tags_pages(&self, image: &str, n: Option<usize>) -> impl Stream<Item = RegistryResponse<TagsResponse>> + '_ {
+        let first = self
+            .client
+            .get(format!("{}/v2/{}/tags/list", self.url, image))
+            .query(&page_query(n, None));
+
+        self.pages(first)
+    }
+
     pub async fn get_manifest(&self, name: &str, reference: &str) -> RegistryResponse<Manifest> {
         let request = self
             .client
             .get(format!("{}/v2/{}/manifests/{}", self.url, name, reference))
-            .header(
-                ACCEPT,
-                [
-                    MediaType::OCIImageIndexV1.to_string(),
-                    MediaType::OCIImageManifestV1.to_string(),
-                    MediaType::DockerDistributionManifestV2.to_string(),
-                    MediaType::DockerDistributionManifestListV2.to_string(),
-                ]
-                .join(", "),
-            );
+            .header(ACCEPT, manifest_media_types());
 
         self.send::<Manifest>(request).await
     }
@@ -85,79 +122,644 @@ impl RegistryClient {
         let request = self
             .client
             .delete(format!("{}/v2/{}/manifests/{}", self.url, name, reference))
-            .header(
-                ACCEPT,
-                [
-                    MediaType::OCIImageIndexV1.to_string(),
-                    MediaType::OCIImageManifestV1.to_string(),
-                    MediaType::DockerDistributionManifestV2.to_string(),
-                    MediaType::DockerDistributionManifestListV2.to_string(),
-                ]
-                .join(", "),
-            );
+            .header(ACCEPT, manifest_media_types());
 
         self.send::<()>(request).await
     }
 
+    /// Removes the tag only, leaving the manifest and its other tags, as distribution spec 1.1
+    /// allows. `Ok(false)` when the registry can only delete manifests by digest.
+    pub async fn delete_tag(&self, name: &str, tag: &str) -> Result<bool, ClientError> {
+        match self.delete_manifest(name, tag).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_unsupported() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     pub async fn get_config(&self, name: &str, digest: &str) -> RegistryResponse<ImageConfigResponse> {
         let request = self.client.get(format!("{}/v2/{}/blobs/{}", self.url, name, digest));
 
         self.send::<ImageConfigResponse>(request).await
     }
 
+    pub async fn get_blob_stream(
+        &self,
+        name: &str,
+        digest: &str,
+    ) -> Result<impl Stream<Item = reqwest::Result<Bytes>>, ClientError> {
+        self.get_blob_stream_at(name, digest, 0).await
+    }
+
+    /// Streams the blob from `offset` on. Registries ignoring the `Range` header answer
+    /// with the whole blob, the prefix is dropped here then.
+    pub async fn get_blob_stream_at(
+        &self,
+        name: &str,
+        digest: &str,
+        offset: u64,
+    ) -> Result<impl Stream<Item = reqwest::Result<Bytes>>, ClientError> {
+        let mut request = self.client.get(format!("{}/v2/{}/blobs/{}", self.url, name, digest));
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let res = self.send_raw(request).await?;
+
+        let mut skip = if res.status() == StatusCode::PARTIAL_CONTENT {
+            0
+        } else {
+            offset
+        };
+        Ok(res.bytes_stream().map_ok(move |bytes| {
+            let skipped = skip.min(bytes.len() as u64);
+            skip -= skipped;
+            bytes.slice(skipped as usize..)
+        }))
+    }
+
+    /// Digest of the manifest, or `None` when there is no such manifest.
+    pub async fn head_manifest(&self, name: &str, reference: &str) -> Result<Option<String>, ClientError> {
+        let request = self
+            .client
+            .head(format!("{}/v2/{}/manifests/{}", self.url, name, reference))
+            .header(ACCEPT, manifest_media_types());
+
+        match self.send_raw(request).await {
+            Ok(res) => Ok(res
+                .headers()
+                .get("docker-content-digest")
+                .and_then(|h| h.to_str().ok())
+                .map(String::from)),
+            Err(e) if e.is_not_found() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn blob_exists(&self, name: &str, digest: &str) -> Result<bool, ClientError> {
+        let request = self.client.head(format!("{}/v2/{}/blobs/{}", self.url, name, digest));
+
+        match self.send_raw(request).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_not_found() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn start_upload(&self, name: &str) -> Result<UploadSession, ClientError> {
+        let request = self.client.post(format!("{}/v2/{}/blobs/uploads/", self.url, name));
+
+        upload_session(&self.send_raw(request).await?, 0)
+    }
+
+    /// Asks the registry to link a blob from another repository. Returns `None` once mounted,
+    /// or the upload session the registry opened instead (e.g. when `from` isn't readable).
+    pub async fn mount_blob(&self, name: &str, digest: &str, from: &str) -> Result<Option<UploadSession>, ClientError> {
+        let request = self
+            .client
+            .post(format!("{}/v2/{}/blobs/uploads/", self.url, name))
+            .query(&[("mount", digest), ("from", from)]);
+        let res = self.send_raw(request).await?;
+
+        if res.status() == StatusCode::CREATED {
+            return Ok(None);
+        }
+
+        upload_session(&res, 0).map(Some)
+    }
+
+    pub async fn upload_chunk(&self, session: &UploadSession, chunk: Bytes) -> Result<UploadSession, ClientError> {
+        let end = session.offset + chunk.len() as u64;
+        let request = self
+            .client
+            .patch(&session.location)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header(CONTENT_RANGE, format!("{}-{}", session.offset, end.saturating_sub(1)))
+            .body(chunk);
+
+        upload_session(&self.send_raw(request).await?, end)
+    }
+
+    /// Closes the upload with the remaining data, the registry checks it against `digest`.
+    pub async fn complete_upload(&self, session: &UploadSession, digest: &str, last: Bytes) -> Result<(), ClientError> {
+        let request = self
+            .client
+            .put(&session.location)
+            .query(&[("digest", digest)])
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(last);
+
+        self.send_raw(request).await.map(|_| ())
+    }
+
+    /// Where an interrupted upload stands, to resume it from the accepted offset.
+    pub async fn upload_status(&self, session: &UploadSession) -> Result<UploadSession, ClientError> {
+        let request = self.client.get(&session.location);
+
+        upload_session(&self.send_raw(request).await?, 0)
+    }
+
+    pub async fn get_raw_manifest(&self, name: &str, reference: &str) -> RegistryResponse<RawManifest> {
+        let request = self
+            .client
+            .get(format!("{}/v2/{}/manifests/{}", self.url, name, reference))
+            .header(ACCEPT, manifest_media_types());
+        let res = self.send_raw(request).await?;
+
+        let status = res.status().as_u16();
+        let digest = res
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|h| h.to_str().ok())
+            .map(String::from);
+        let content = res.bytes().await.map_err(|e| classify_transport_error(&e))?;
+
+        match serde_json::from_slice::<Manifest>(&content) {
+            Ok(manifest) => Ok(RegistryAnswer::new(status, RawManifest { manifest, content }, digest)),
+            Err(e) => Err(ClientError::Decode {
+                message: e.to_string(),
+                excerpt: excerpt(&content),
+            }),
+        }
+    }
+
+    pub async fn put_manifest(
+        &self,
+        name: &str,
+        reference: &str,
+        media_type: &MediaType,
+        content: Bytes,
+    ) -> RegistryResponse<()> {
+        let request = self
+            .client
+            .put(format!("{}/v2/{}/manifests/{}", self.url, name, reference))
+            .header(CONTENT_TYPE, media_type.to_string())
+            .body(content);
+
+        self.send::<()>(request).await
+    }
+
     async fn send<T>(&self, request: RequestBuilder) -> RegistryResponse<T>
     where
         T: DeserializeOwned,
     {
-        let mut req = request;
-        if let Some(basic_auth) = self.basic_auth.clone() {
-            req = req.basic_auth(basic_auth.http_basic_user, basic_auth.http_basic_pass);
+        let res = self.send_raw(request).await?;
+
+        let status = res.status().as_u16();
+        let digest = res
+            .headers()
+            .get("docker-content-digest")
+            .map(|h| String::from(h.to_str().unwrap_or_default()));
+        let next = res
+            .headers()
+            .get(LINK)
+            .and_then(|h| h.to_str().ok())
+            .and_then(parse_next_link)
+            .and_then(|link| res.url().join(&link).ok())
+            .filter(|url| self.is_same_origin(url))
+            .map(String::from);
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .map(String::from)
+            .unwrap_or_default();
+
+        let body = match res.bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Can't read response body: {:?}", e);
+                return Err(classify_transport_error(&e));
+            }
+        };
+
+        // Empty bodies (e.g. `202 Accepted` on DELETE) are decoded as `null`, which fits `()`.
+        let json: &[u8] = if body.is_empty() { b"null" } else { &body };
+
+        match serde_json::from_slice::<T>(json) {
+            Ok(content) => Ok(RegistryAnswer {
+                next,
+                ..RegistryAnswer::new(status, content, digest)
+            }),
+            Err(e) if is_unsupported_media_type(&content_type) => {
+                error!("Unsupported media type {}: {:?}", content_type, e);
+                Err(ClientError::UnsupportedMediaType(content_type))
+            }
+            Err(e) => {
+                error!("Can't parse response: {:?}", e);
+                Err(ClientError::Decode {
+                    message: e.to_string(),
+                    excerpt: excerpt(&body),
+                })
+            }
+        }
+    }
+
+    /// Sends the request and returns the response as is when it's successful,
+    /// so callers can stream the body instead of decoding it.
+    async fn send_raw(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let res = match self.execute(request).await {
+            Ok(res) => res,
+            Err(e) => {
+                error!("Request error occurred {:?}", e);
+                return Err(classify_transport_error(&e));
+            }
+        };
+
+        let status = Status::new(res.status().as_u16());
+        if status.class() == StatusClass::Success {
+            return Ok(res);
+        }
+
+        let body = match res.bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Can't read response body: {:?}", e);
+                return Err(classify_transport_error(&e));
+            }
+        };
+        let errors = || serde_json::from_slice::<RegistryErrors>(&body).unwrap_or_default();
+
+        match status.code {
+            401 | 403 => Err(ClientError::Auth {
+                status,
+                errors: errors(),
+            }),
+            404 => Err(ClientError::NotFound(errors())),
+            _ if status.class() == StatusClass::ClientError => Err(ClientError::Registry {
+                status,
+                errors: errors(),
+            }),
+            _ if status.class() == StatusClass::ServerError => {
+                error!("Server error: {:?}. Server answer: {:?}", status, excerpt(&body));
+                Err(ClientError::Upstream {
+                    status,
+                    excerpt: excerpt(&body),
+                })
+            }
+            _ => {
+                error!("Unknown error: {:?}. Server answer: {:?}", status, excerpt(&body));
+                Err(ClientError::UnexpectedStatus {
+                    status,
+                    excerpt: excerpt(&body),
+                })
+            }
         }
+    }
 
-        match req.send().await {
-            Ok(res) => {
-                let digest = res
-                    .headers()
-                    .get("docker-content-digest")
-                    .map(|h| String::from(h.to_str().unwrap_or_default()));
-
-                if res.status().is_success() {
-                    let status = res.status().as_u16();
-                    match res.json::<T>().await {
-                        Ok(content) => Ok(RegistryAnswer::new(status, content, digest)),
-                        Err(e) => {
-                            error!("Can't parse response: {:?}", e);
-                            Err(RegistryErrors::custom("Parse error"))
-                        }
-                    }
-                } else if res.status().is_client_error() {
-                    match res.json::<RegistryErrors>().await {
-                        Ok(content) => Err(content),
-                        Err(e) => {
-                            error!("Can't parse error response: {:?}", e);
-                            Err(RegistryErrors::custom("Parse error"))
-                        }
-                    }
-                } else if res.status().is_server_error() {
-                    error!(
-                        "Server error: {:?}. Server answer: {:?}",
-                        res.status(),
-                        res.text().await
-                    );
-                    Err(RegistryErrors::custom("Server error"))
-                } else {
-                    error!(
-                        "Unknown error: {:?}. Server answer: {:?}",
-                        res.status(),
-                        res.text().await
-                    );
-                    Err(RegistryErrors::custom("Unknown error"))
+    fn pages<T>(&self, first: RequestBuilder) -> impl Stream<Item = RegistryResponse<T>> + '_
+    where
+        T: DeserializeOwned,
+    {
+        let first_url = first
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .map(|request| request.url().to_string());
+        let seen: HashSet<String> = first_url.into_iter().collect();
+
+        stream::unfold((Some(first), seen), move |(request, mut seen)| async move {
+            let mut answer = self.send::<T>(request?).await;
+            // A registry linking back to a page already read would be followed forever
+            if let Ok(a) = &mut answer {
+                if a.next.as_ref().is_some_and(|url| !seen.insert(url.clone())) {
+                    warn!("Registry links back to page {:?}, stopping there", a.next);
+                    a.next = None;
                 }
             }
+            let next = match &answer {
+                Ok(a) => a.next.as_ref().map(|url| self.client.get(url)),
+                Err(_) => None,
+            };
+
+            Some((answer, (next, seen)))
+        })
+    }
+
+    async fn collect_pages<T>(&self, pages: impl Stream<Item = RegistryResponse<T>>) -> RegistryResponse<T>
+    where
+        T: Paginated,
+    {
+        let mut pages = Box::pin(pages);
+        let mut answer = match pages.try_next().await? {
+            Some(first) => first,
+            None => unreachable!("pages stream always yields the first page"),
+        };
+
+        while let Some(page) = pages.try_next().await? {
+            answer.content.merge(page.content);
+            answer.next = page.next;
+        }
+
+        Ok(answer)
+    }
+
+    async fn execute(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let hint = scope_hint(request.method(), request.url());
+        let retry = request.try_clone();
+
+        let cached = hint.as_deref().and_then(|scope| self.tokens.get(scope));
+        let res = self
+            .authorize(RequestBuilder::from_parts(client.clone(), request), cached)
+            .send()
+            .await?;
+
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+
+        let challenge = res.headers().get(WWW_AUTHENTICATE).and_then(BearerChallenge::parse);
+        let (Some(challenge), Some(retry)) = (challenge, retry) else {
+            return Ok(res);
+        };
+
+        let token = match self.fetch_token(&challenge).await {
+            Ok(Some(token)) => token,
+            Ok(None) => {
+                error!("Token server {} answered without a token", challenge.realm);
+                return Ok(res);
+            }
             Err(e) => {
-                error!("Request error occurred {:?}", e);
-                Err(RegistryErrors::custom("Unknown error"))
+                error!("Can't fetch token from {}: {:?}", challenge.realm, e);
+                return Ok(res);
             }
+        };
+
+        if let Some(hint) = hint.filter(|hint| *hint != challenge.cache_key()) {
+            self.tokens.insert(hint, token.clone());
         }
+        self.tokens.insert(challenge.cache_key(), token.clone());
+
+        self.authorize(RequestBuilder::from_parts(client, retry), Some(token))
+            .send()
+            .await
+    }
+
+    async fn fetch_token(&self, challenge: &BearerChallenge) -> reqwest::Result<Option<BearerToken>> {
+        let mut query = Vec::new();
+        if let Some(service) = &challenge.service {
+            query.push(("service", service.as_str()));
+        }
+        if let Some(scope) = &challenge.scope {
+            query.extend(scope.split(' ').map(|s| ("scope", s)));
+        }
+
+        let mut req = self.client.get(&challenge.realm).query(&query);
+        if let Some(basic_auth) = self.basic_auth.clone() {
+            req = req.basic_auth(basic_auth.http_basic_user, basic_auth.http_basic_pass);
+        }
+
+        let res = req.send().await?.error_for_status()?;
+
+        Ok(res.json::<TokenResponse>().await?.into_token())
+    }
+
+    /// Credentials go along with every request, so links elsewhere are never followed.
+    fn is_same_origin(&self, url: &Url) -> bool {
+        let same = Url::parse(&self.url).is_ok_and(|base| base.origin() == url.origin());
+        if !same {
+            warn!("Registry links to {}, outside of {}, not following it", url, self.url);
+        }
+        same
+    }
+
+    fn authorize(&self, request: RequestBuilder, token: Option<BearerToken>) -> RequestBuilder {
+        if let Some(token) = token {
+            request.bearer_auth(token.value)
+        } else if let Some(basic_auth) = self.basic_auth.clone() {
+            request.basic_auth(basic_auth.http_basic_user, basic_auth.http_basic_pass)
+        } else {
+            request
+        }
+    }
+}
+
+fn manifest_media_types() -> String {
+    [
+        MediaType::OCIImageIndexV1.to_string(),
+        MediaType::OCIImageManifestV1.to_string(),
+        MediaType::DockerDistributionManifestV2.to_string(),
+        MediaType::DockerDistributionManifestListV2.to_string(),
+    ]
+    .join(", ")
+}
+
+/// Reads the next upload location, relative to the request URL, and the accepted range (`0-<last byte>`).
+/// Registries answer `0-0` for empty uploads as well, which is read as nothing accepted yet.
+fn upload_session(res: &Response, offset: u64) -> Result<UploadSession, ClientError> {
+    let location = res
+        .headers()
+        .get(LOCATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|location| res.url().join(location).ok())
+        .ok_or_else(|| ClientError::UnexpectedStatus {
+            status: Status::new(res.status().as_u16()),
+            excerpt: "upload answer without a Location header".to_string(),
+        })?;
+    let offset = res
+        .headers()
+        .get(RANGE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|range| range.rsplit('-').next()?.parse::<u64>().ok())
+        .map_or(offset, |last| if last == 0 { 0 } else { last + 1 });
+
+    Ok(UploadSession {
+        location: location.to_string(),
+        offset,
+    })
+}
+
+fn page_query(n: Option<usize>, last: Option<&str>) -> Vec<(&'static str, String)> {
+    let mut query = Vec::new();
+    if let Some(n) = n {
+        query.push(("n", n.to_string()));
+    }
+    if let Some(last) = last {
+        query.push(("last", last.to_string()));
+    }
+
+    query
+}
+
+fn parse_next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        let is_next = params
+            .split(';')
+            .any(|p| p.trim().replace('"', "").eq_ignore_ascii_case("rel=next"));
+
+        is_next.then(|| url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+    })
+}
+
+const EXCERPT_LENGTH: usize = 256;
+
+fn excerpt(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).chars().take(EXCERPT_LENGTH).collect()
+}
+
+fn is_unsupported_media_type(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    media_type.starts_with("application/vnd.")
+        && ![
+            MediaType::OCIImageIndexV1,
+            MediaType::OCIImageManifestV1,
+            MediaType::OCIImageConfigV1,
+            MediaType::DockerDistributionManifestV2,
+            MediaType::DockerDistributionManifestListV2,
+            MediaType::DockerContainerImageV1,
+        ]
+        .iter()
+        .any(|m| m.to_string() == media_type)
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        classify_transport_error(&e)
+    }
+}
+
+fn classify_transport_error(e: &reqwest::Error) -> ClientError {
+    if e.is_timeout() {
+        return ClientError::Timeout;
+    }
+
+    let mut source = e.source();
+    while let Some(err) = source {
+        let message = err.to_string().to_lowercase();
+        if message.contains("certificate") || message.contains("tls") || message.contains("ssl") {
+            return ClientError::Tls(err.to_string());
+        }
+        source = err.source();
+    }
+
+    ClientError::Transport(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{serve, Reply};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A registry behind a token server, both on the same stub, accepting `user:secret`.
+    async fn token_registry(token_requests: Arc<AtomicUsize>) -> String {
+        serve(move |req| {
+            if req.path.starts_with("/token?") {
+                token_requests.fetch_add(1, Ordering::SeqCst);
+                // user:secret
+                if req.header("authorization") != Some("Basic dXNlcjpzZWNyZXQ=") {
+                    return Reply::status(401);
+                }
+                assert_eq!(req.method, "GET");
+                assert!(req.path.contains("service=registry"));
+                assert!(req.path.contains("scope=registry%3Acatalog%3A*"));
+                return Reply::json(200, &json!({"token": "t0k3n", "expires_in": 300}));
+            }
+
+            if req.header("authorization") != Some("Bearer t0k3n") {
+                let challenge = format!(
+                    r#"Bearer realm="{}/token",service="registry",scope="registry:catalog:*""#,
+                    req.origin()
+                );
+                return Reply::json(401, &json!({"errors": [{"code": "UNAUTHORIZED", "message": "no"}]}))
+                    .header("WWW-Authenticate", &challenge);
+            }
+            Reply::json(200, &json!({"repositories": ["library/alpine"]}))
+        })
+        .await
+    }
+
+    fn client(address: String, user: &str, password: &str) -> RegistryClient {
+        RegistryClient::new(&Config {
+            base_uri: address,
+            is_secured: false,
+            http_basic_user: Some(user.to_string()),
+            http_basic_pass: Some(password.to_string()),
+        })
+    }
+
+    #[rocket::async_test]
+    async fn fetches_and_caches_bearer_tokens() {
+        let token_requests = Arc::new(AtomicUsize::new(0));
+        let client = client(token_registry(token_requests.clone()).await, "user", "secret");
+
+        let catalog = client.get_catalog().await.unwrap();
+        assert_eq!(catalog.content.repositories, vec!["library/alpine"]);
+        client.get_catalog().await.unwrap();
+
+        assert_eq!(token_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[rocket::async_test]
+    async fn stops_at_links_to_pages_already_read() {
+        let address = serve(|req| {
+            let (tags, next) = match req.path.as_str() {
+                "/v2/app/tags/list" => (["1"], "/v2/app/tags/list?last=1"),
+                _ => (["2"], "/v2/app/tags/list"),
+            };
+            Reply::json(200, &json!({"name": "app", "tags": tags})).header("Link", &format!("<{}>; rel=\"next\"", next))
+        })
+        .await;
+        let client = client(address, "user", "secret");
+
+        let tags = client.get_tags("app").await.unwrap();
+        assert_eq!(tags.content.tags, Some(vec!["1".to_string(), "2".to_string()]));
+        assert_eq!(tags.next, None);
+    }
+
+    #[rocket::async_test]
+    async fn wrong_credentials_are_an_auth_error() {
+        let token_requests = Arc::new(AtomicUsize::new(0));
+        let client = client(token_registry(token_requests.clone()).await, "user", "wrong");
+
+        let error = client.get_catalog().await.unwrap_err();
+        assert!(matches!(error, ClientError::Auth { .. }), "{:?}", error);
+    }
+
+    #[rocket::async_test]
+    async fn deletes_tags_alone_only_where_supported() {
+        let address = serve(|req| {
+            let error = |code: &str| json!({"errors": [{"code": code, "message": "no"}]});
+            match req.path.as_str() {
+                "/v2/old/manifests/1" => Reply::status(405),
+                "/v2/strict/manifests/1" => Reply::json(400, &error("UNSUPPORTED")),
+                "/v2/BAD/manifests/1" => Reply::json(400, &error("NAME_INVALID")),
+                _ => Reply::status(202),
+            }
+        })
+        .await;
+        let client = client(address, "user", "secret");
+
+        assert!(client.delete_tag("app", "1").await.unwrap());
+        assert!(!client.delete_tag("old", "1").await.unwrap());
+        assert!(!client.delete_tag("strict", "1").await.unwrap());
+        assert!(client.delete_tag("BAD", "1").await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn follows_links_to_the_registry_only() {
+        let elsewhere = Arc::new(AtomicUsize::new(0));
+        let requests = elsewhere.clone();
+        let other = serve(move |_| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            Reply::json(200, &json!({"name": "app", "tags": ["stolen"]}))
+        })
+        .await;
+        let address = serve(move |_| {
+            Reply::json(200, &json!({"name": "app", "tags": ["1"]})).header(
+                "Link",
+                &format!("<http://{}/v2/app/tags/list?last=1>; rel=\"next\"", other),
+            )
+        })
+        .await;
+        let client = client(address, "user", "secret");
+
+        let tags = client.get_tags("app").await.unwrap();
+        assert_eq!(tags.content.tags, Some(vec!["1".to_string()]));
+        assert_eq!(elsewhere.load(Ordering::SeqCst), 0);
     }
 }
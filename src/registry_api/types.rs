@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use rocket::http::Status;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -17,6 +18,17 @@ pub enum Manifest {
     DockerDistributionManifestListV2(DockerDistributionManifestListV2),
 }
 
+impl Manifest {
+    pub fn media_type(&self) -> MediaType {
+        match self {
+            Manifest::OCIImageIndexV1(_) => MediaType::OCIImageIndexV1,
+            Manifest::OCIImageManifestV1(_) => MediaType::OCIImageManifestV1,
+            Manifest::DockerDistributionManifestV2(_) => MediaType::DockerDistributionManifestV2,
+            Manifest::DockerDistributionManifestListV2(_) => MediaType::DockerDistributionManifestListV2,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct OCIImageIndexV1 {
     #[serde(rename = "schemaVersion")]
@@ -45,18 +57,6 @@ pub struct DockerDistributionManifestV2 {
     pub layers: Vec<Layer>,
 }
 
-impl DockerDistributionManifestV2 {
-    pub fn get_total_size(&self) -> u64 {
-        self.layers.iter().map(|i| i.size).sum()
-    }
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct DockerContainerImageV1 {
-    pub digest: String,
-    pub size: u64,
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct OCIImageManifestV1Short {
     pub digest: String,
@@ -73,12 +73,6 @@ pub struct OCIImageManifestV1 {
     pub layers: Vec<Layer>,
 }
 
-impl OCIImageManifestV1 {
-    pub fn get_total_size(&self) -> u64 {
-        self.layers.iter().map(|i| i.size).sum()
-    }
-}
-
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Layer {
     #[serde(rename = "mediaType")]
@@ -87,6 +81,24 @@ pub struct Layer {
     pub digest: String,
 }
 
+impl Layer {
+    /// `gzip`, `zstd` or `none`, derived from the `+gzip`/`.gzip` style suffix of the media type.
+    pub fn compression(&self) -> &'static str {
+        if self.media_type.ends_with("gzip") {
+            "gzip"
+        } else if self.media_type.ends_with("zstd") {
+            "zstd"
+        } else {
+            "none"
+        }
+    }
+
+    /// Foreign (Docker) and non-distributable (OCI) layers are usually not stored in the registry.
+    pub fn is_distributable(&self) -> bool {
+        !self.media_type.contains(".foreign.") && !self.media_type.contains(".nondistributable.")
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct CatalogResponse {
     pub repositories: Vec<String>,
@@ -98,7 +110,25 @@ pub struct TagsResponse {
     pub tags: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+pub trait Paginated {
+    fn merge(&mut self, page: Self);
+}
+
+impl Paginated for CatalogResponse {
+    fn merge(&mut self, page: Self) {
+        self.repositories.extend(page.repositories);
+    }
+}
+
+impl Paginated for TagsResponse {
+    fn merge(&mut self, page: Self) {
+        if let Some(tags) = page.tags {
+            self.tags.get_or_insert_with(Vec::new).extend(tags);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Platform {
     pub architecture: String,
     pub os: String,
@@ -110,6 +140,30 @@ pub struct Platform {
     pub features: Option<Vec<String>>,
 }
 
+impl Platform {
+    /// Matches `os/architecture[/variant]`, e.g. `linux/arm64/v8`.
+    pub fn matches(&self, platform: &str) -> bool {
+        let mut parts = platform.split('/');
+
+        parts.next() == Some(self.os.as_str())
+            && parts.next() == Some(self.architecture.as_str())
+            && parts
+                .next()
+                .is_none_or(|variant| self.variant.as_deref() == Some(variant))
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum MediaType {
     #[serde(rename = "application/vnd.oci.image.index.v1+json")]
@@ -148,18 +202,66 @@ impl Display for MediaType {
 pub struct ImageConfigResponse {
     pub architecture: String,
     pub os: String,
+    pub variant: Option<String>,
     pub author: Option<String>,
+    pub created: Option<String>,
+    #[serde(default)]
     pub config: ImageConfig,
+    pub rootfs: Option<RootFs>,
+    #[serde(default)]
+    pub history: Vec<History>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl ImageConfigResponse {
+    pub fn platform(&self) -> Platform {
+        Platform {
+            architecture: self.architecture.clone(),
+            os: self.os.clone(),
+            os_version: None,
+            os_features: None,
+            variant: self.variant.clone(),
+            features: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct ImageConfig {
+    #[serde(rename = "User")]
+    pub user: Option<String>,
+    #[serde(rename = "ExposedPorts")]
+    pub exposed_ports: Option<HashMap<String, serde_json::Value>>,
     #[serde(rename = "Env")]
     pub env: Option<Vec<String>>,
+    #[serde(rename = "Entrypoint")]
+    pub entrypoint: Option<Vec<String>>,
     #[serde(rename = "Cmd")]
     pub cmd: Option<Vec<String>>,
+    #[serde(rename = "Volumes")]
+    pub volumes: Option<HashMap<String, serde_json::Value>>,
+    #[serde(rename = "WorkingDir")]
+    pub working_dir: Option<String>,
     #[serde(rename = "Labels")]
     pub labels: Option<HashMap<String, String>>,
+    #[serde(rename = "StopSignal")]
+    pub stop_signal: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RootFs {
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    pub diff_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct History {
+    pub created: Option<String>,
+    pub created_by: Option<String>,
+    pub author: Option<String>,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub empty_layer: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -188,20 +290,14 @@ pub enum ErrorCode {
 pub struct RegistryError {
     pub code: ErrorCode,
     pub message: String,
-    pub detail: HashMap<String, String>,
+    pub detail: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct RegistryErrors {
-    #[serde(skip_deserializing, default = "default_message")]
-    pub message: String,
     pub errors: Vec<RegistryError>,
 }
 
-fn default_message() -> String {
-    "Registry answer error".to_string()
-}
-
 impl Display for RegistryErrors {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let errors = self
@@ -215,19 +311,83 @@ impl Display for RegistryErrors {
     }
 }
 
-impl RegistryErrors {
-    pub fn custom(message: &str) -> Self {
-        Self {
-            message: message.to_string(),
-            errors: Vec::new(),
+#[derive(Clone, Debug)]
+pub enum ClientError {
+    Transport(String),
+    Timeout,
+    Tls(String),
+    Auth { status: Status, errors: RegistryErrors },
+    NotFound(RegistryErrors),
+    Registry { status: Status, errors: RegistryErrors },
+    Upstream { status: Status, excerpt: String },
+    UnexpectedStatus { status: Status, excerpt: String },
+    Decode { message: String, excerpt: String },
+    UnsupportedMediaType(String),
+}
+
+impl ClientError {
+    pub fn errors(&self) -> &[RegistryError] {
+        match self {
+            ClientError::Auth { errors, .. } | ClientError::NotFound(errors) | ClientError::Registry { errors, .. } => {
+                &errors.errors
+            }
+            _ => &[],
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ClientError::NotFound(_))
+    }
+
+    /// The registry doesn't implement the request, e.g. deleting a tag on registries older
+    /// than distribution spec 1.1. Other client errors, an invalid name for one, are real ones.
+    pub fn is_unsupported(&self) -> bool {
+        match self {
+            ClientError::Registry { status, errors } => {
+                status.code == 405 || errors.errors.iter().any(|e| matches!(e.code, ErrorCode::Unsupported))
+            }
+            _ => false,
+        }
+    }
+
+    /// Failures that may go away on their own, worth another attempt.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Transport(_) | ClientError::Timeout | ClientError::Upstream { .. }
+        )
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "Registry unreachable: {}", e),
+            ClientError::Timeout => write!(f, "Registry request timed out"),
+            ClientError::Tls(e) => write!(f, "TLS error: {}", e),
+            ClientError::Auth { status, errors } => write!(f, "Registry refused credentials ({}): {}", status, errors),
+            ClientError::NotFound(errors) => write!(f, "Not found: {}", errors),
+            ClientError::Registry { status, errors } => write!(f, "Registry error ({}): {}", status, errors),
+            ClientError::Upstream { status, excerpt } => write!(f, "Registry server error ({}): {}", status, excerpt),
+            ClientError::UnexpectedStatus { status, excerpt } => {
+                write!(f, "Unexpected registry answer ({}): {}", status, excerpt)
+            }
+            ClientError::Decode { message, excerpt } => {
+                write!(f, "Can't decode registry answer: {} in {}", message, excerpt)
+            }
+            ClientError::UnsupportedMediaType(media_type) => write!(f, "Unsupported media type: {}", media_type),
         }
     }
 }
 
+impl std::error::Error for ClientError {}
+
 #[derive(Clone, Debug, Default)]
 pub struct RegistryAnswer<T> {
     pub digest: Option<String>,
+    pub next: Option<String>,
     pub content: T,
+    #[allow(dead_code)]
     pub status: Status,
 }
 
@@ -237,8 +397,24 @@ impl<T> RegistryAnswer<T> {
             status: Status::new(status),
             content,
             digest,
+            next: None,
         }
     }
 }
 
-pub type RegistryResponse<T> = Result<RegistryAnswer<T>, RegistryErrors>;
+/// A manifest as the registry stored it. Copies must keep these exact bytes,
+/// re-serializing would change the digest.
+#[derive(Clone, Debug)]
+pub struct RawManifest {
+    pub manifest: Manifest,
+    pub content: Bytes,
+}
+
+/// An upload in progress: where to send the next chunk and how much was accepted so far.
+#[derive(Clone, Debug)]
+pub struct UploadSession {
+    pub location: String,
+    pub offset: u64,
+}
+
+pub type RegistryResponse<T> = Result<RegistryAnswer<T>, ClientError>;
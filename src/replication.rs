@@ -0,0 +1,597 @@
+use crate::copy::Copier;
+use crate::manager::matching_repositories;
+use crate::registries::{Registries, DEFAULT_REGISTRY};
+use crate::registry_api::types::ClientError;
+use crate::registry_api::{Config as RegistryConfig, RegistryClient};
+use crate::store::{JsonStore, StoreError};
+use crate::types::{JobStatus, JobTrigger, ReplicationDirection, ReplicationJob, ReplicationProgress, ReplicationRule};
+use crate::util::{interval_elapsed, matches, now};
+use rocket::tokio;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// Finished jobs beyond that are forgotten, oldest first.
+const MAX_KEPT_JOBS: usize = 100;
+const MAX_JOB_ERRORS: usize = 100;
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum ReplicationError {
+    RuleNotFound(String),
+    AlreadyRunning(String),
+    Storage(String),
+}
+
+impl From<StoreError> for ReplicationError {
+    fn from(e: StoreError) -> Self {
+        ReplicationError::Storage(e.to_string())
+    }
+}
+
+impl fmt::Display for ReplicationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplicationError::RuleNotFound(id) => write!(f, "No replication rule {}", id),
+            ReplicationError::AlreadyRunning(id) => write!(f, "Replication rule {} is already running", id),
+            ReplicationError::Storage(e) => write!(f, "Can't save replication rules: {}", e),
+        }
+    }
+}
+
+/// Replication rules between the configured registry and remote ones. Rules are kept in
+/// `HARBUI_REPLICATION_FILE` when set and only in memory otherwise, jobs are never persisted.
+pub struct Replication {
+    rules: Arc<JsonStore<ReplicationRule>>,
+    jobs: Jobs,
+}
+
+impl Replication {
+    pub fn new(registries: Registries, file: Option<String>) -> Result<Self, String> {
+        Ok(Self {
+            rules: Arc::new(JsonStore::open("replication rules", file)?),
+            jobs: Jobs {
+                registries,
+                jobs: Arc::new(JsonStore::in_memory()),
+            },
+        })
+    }
+
+    /// Runs rules with an interval in the background, an interval after their last job
+    /// started. Rules that never ran start right away.
+    pub fn start_scheduler(&self) {
+        let rules = self.rules.clone();
+        let jobs = self.jobs.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                let now = OffsetDateTime::now_utc();
+                let due: Vec<ReplicationRule> = rules.read().iter().filter(|rule| is_due(rule, now)).cloned().collect();
+
+                for rule in due {
+                    if let Err(e) = mark_started(&rules, &rule.id) {
+                        error!("Skipping scheduled replication {}: {}", rule.name, e);
+                        continue;
+                    }
+                    if let Err(e) = jobs.start(rule, JobTrigger::Scheduled) {
+                        info!("Skipping scheduled replication: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn rules(&self) -> Vec<ReplicationRule> {
+        self.rules.read().iter().map(redacted).collect()
+    }
+
+    pub fn create_rule(&self, mut rule: ReplicationRule) -> Result<ReplicationRule, ReplicationError> {
+        rule.id = uuid::Uuid::new_v4().to_string();
+        rule.last_run_at = None;
+
+        self.rules.update(|rules| {
+            rules.push(rule.clone());
+            Ok::<_, ReplicationError>(())
+        })?;
+
+        Ok(redacted(&rule))
+    }
+
+    pub fn update_rule(&self, id: &str, mut rule: ReplicationRule) -> Result<ReplicationRule, ReplicationError> {
+        self.rules.update(|rules| {
+            let current = rules
+                .iter_mut()
+                .find(|rule| rule.id == id)
+                .ok_or_else(|| ReplicationError::RuleNotFound(id.to_string()))?;
+
+            rule.id = id.to_string();
+            rule.last_run_at = current.last_run_at.take();
+            if rule.remote.password.is_none() {
+                rule.remote.password = current.remote.password.take();
+            }
+            *current = rule.clone();
+            Ok(redacted(&rule))
+        })
+    }
+
+    pub fn delete_rule(&self, id: &str) -> Result<(), ReplicationError> {
+        self.rules.update(|rules| {
+            let count = rules.len();
+            rules.retain(|rule| rule.id != id);
+            if rules.len() == count {
+                return Err(ReplicationError::RuleNotFound(id.to_string()));
+            }
+            Ok(())
+        })
+    }
+
+    pub fn run(&self, id: &str) -> Result<ReplicationJob, ReplicationError> {
+        let rule = self
+            .rules
+            .read()
+            .iter()
+            .find(|rule| rule.id == id)
+            .cloned()
+            .ok_or_else(|| ReplicationError::RuleNotFound(id.to_string()))?;
+        mark_started(&self.rules, id)?;
+
+        self.jobs.start(rule, JobTrigger::Manual)
+    }
+
+    /// Newest first.
+    pub fn jobs(&self) -> Vec<ReplicationJob> {
+        self.jobs.jobs.read().iter().rev().cloned().collect()
+    }
+
+    pub fn job(&self, id: &str) -> Option<ReplicationJob> {
+        self.jobs.jobs.read().iter().find(|job| job.id == id).cloned()
+    }
+}
+
+fn redacted(rule: &ReplicationRule) -> ReplicationRule {
+    let mut rule = rule.clone();
+    rule.remote.password = None;
+    rule
+}
+
+fn is_due(rule: &ReplicationRule, now: OffsetDateTime) -> bool {
+    let interval = rule.interval_minutes.filter(|_| rule.enabled);
+
+    interval_elapsed(interval, rule.last_run_at.as_deref(), now)
+}
+
+fn mark_started(rules: &JsonStore<ReplicationRule>, id: &str) -> Result<(), ReplicationError> {
+    rules.update(|rules| {
+        let rule = rules
+            .iter_mut()
+            .find(|rule| rule.id == id)
+            .ok_or_else(|| ReplicationError::RuleNotFound(id.to_string()))?;
+        rule.last_run_at = Some(now());
+        Ok(())
+    })
+}
+
+#[derive(Clone)]
+struct Jobs {
+    registries: Registries,
+    jobs: Arc<JsonStore<ReplicationJob>>,
+}
+
+impl Jobs {
+    /// Starts the rule in the background, unless it is already running.
+    fn start(&self, rule: ReplicationRule, trigger: JobTrigger) -> Result<ReplicationJob, ReplicationError> {
+        let job = ReplicationJob {
+            id: uuid::Uuid::new_v4().to_string(),
+            rule_id: rule.id.clone(),
+            rule_name: rule.name.clone(),
+            trigger,
+            status: JobStatus::Running,
+            started_at: now(),
+            finished_at: None,
+            current: None,
+            progress: ReplicationProgress::default(),
+            errors: Vec::new(),
+        };
+
+        self.jobs.update(|jobs| {
+            if jobs
+                .iter()
+                .any(|job| job.rule_id == rule.id && job.status == JobStatus::Running)
+            {
+                return Err(ReplicationError::AlreadyRunning(rule.id.clone()));
+            }
+
+            jobs.push(job.clone());
+            let finished = jobs.iter().filter(|job| job.status != JobStatus::Running).count();
+            let mut excess = finished.saturating_sub(MAX_KEPT_JOBS);
+            jobs.retain(|job| {
+                let drop = excess > 0 && job.status != JobStatus::Running;
+                excess -= usize::from(drop);
+                !drop
+            });
+            Ok(())
+        })?;
+
+        let jobs = self.clone();
+        let id = job.id.clone();
+        tokio::spawn(async move { jobs.execute(&id, rule).await });
+
+        Ok(job)
+    }
+
+    async fn execute(&self, id: &str, rule: ReplicationRule) {
+        let registry = rule.registry.as_deref().unwrap_or(DEFAULT_REGISTRY);
+        let Some(local) = self.registries.get(registry) else {
+            self.update(id, |job| {
+                job.errors.push(format!("No registry {}", registry));
+                job.status = JobStatus::Failed;
+                job.finished_at = Some(now());
+            });
+            return;
+        };
+        // Pushing may have been turned off since the rule was saved
+        if !local.permissions.push {
+            self.update(id, |job| {
+                job.errors
+                    .push(format!("Pushing images is disabled for registry {}", registry));
+                job.status = JobStatus::Failed;
+                job.finished_at = Some(now());
+            });
+            return;
+        }
+        let remote = RegistryClient::new(&RegistryConfig {
+            base_uri: rule.remote.host.clone(),
+            is_secured: !rule.remote.unsecured,
+            http_basic_user: rule.remote.username.clone(),
+            http_basic_pass: rule.remote.password.clone(),
+        });
+        let (source, target) = match rule.direction {
+            ReplicationDirection::Push => (&local.client, &remote),
+            ReplicationDirection::Pull => (&remote, &local.client),
+        };
+        let copier = Copier::between(source, target);
+        info!("Starting replication {} ({})", rule.name, id);
+
+        let images = match list_images(source, &rule).await {
+            Ok(images) => images,
+            Err(e) => {
+                error!("Can't list images for replication {}: {}", rule.name, e);
+                self.update(id, |job| {
+                    job.errors.push(format!("Can't list images: {}", e));
+                    job.status = JobStatus::Failed;
+                    job.finished_at = Some(now());
+                });
+                return;
+            }
+        };
+
+        self.update(id, |job| {
+            job.progress.repositories = images.len();
+            job.progress.images_total = images.iter().map(|(_, tags)| tags.len()).sum();
+        });
+
+        for (repository, tags) in &images {
+            let destination = match &rule.destination_namespace {
+                Some(namespace) => format!("{}/{}", namespace.trim_end_matches('/'), repository),
+                None => repository.clone(),
+            };
+
+            for tag in tags {
+                self.update(id, |job| job.current = Some(format!("{}:{}", repository, tag)));
+
+                // Up to date when the destination tag already points at the same manifest
+                let result = match (
+                    source.head_manifest(repository, tag).await,
+                    target.head_manifest(&destination, tag).await,
+                ) {
+                    (Ok(Some(digest)), Ok(Some(current))) if digest == current => Ok(None),
+                    _ => copier.copy(repository, tag, &destination, tag).await.map(Some),
+                };
+
+                self.update(id, |job| match result {
+                    Ok(None) => job.progress.images_up_to_date += 1,
+                    Ok(Some(report)) => {
+                        job.progress.images_copied += 1;
+                        job.progress.manifests += report.manifests;
+                        job.progress.blobs_copied += report.blobs_copied;
+                        job.progress.blobs_skipped += report.blobs_skipped;
+                        job.progress.bytes_copied += report.bytes_copied;
+                    }
+                    Err(e) => {
+                        warn!("Can't replicate {}:{} to {}: {}", repository, tag, destination, e);
+                        job.progress.images_failed += 1;
+                        if job.errors.len() < MAX_JOB_ERRORS {
+                            job.errors.push(format!("{}:{}: {}", repository, tag, e));
+                        }
+                    }
+                });
+            }
+        }
+
+        self.update(id, |job| {
+            job.status = finished_status(&job.progress);
+            job.current = None;
+            job.finished_at = Some(now());
+            info!("Replication {} ({}) finished: {:?}", rule.name, id, job.status);
+        });
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut ReplicationJob)) {
+        self.jobs.update_unsaved(|jobs| {
+            if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                change(job);
+            }
+        });
+    }
+}
+
+fn finished_status(progress: &ReplicationProgress) -> JobStatus {
+    match (progress.images_failed, progress.images_total) {
+        (0, _) => JobStatus::Succeeded,
+        (failed, total) if failed == total => JobStatus::Failed,
+        _ => JobStatus::Partial,
+    }
+}
+
+/// Repositories and tags of the source matching the rule.
+async fn list_images(
+    source: &RegistryClient,
+    rule: &ReplicationRule,
+) -> Result<Vec<(String, Vec<String>)>, ClientError> {
+    let repositories = matching_repositories(source, &rule.repositories).await?;
+
+    let mut images = Vec::new();
+    for repository in repositories {
+        let tags = match source.get_tags(&repository).await {
+            Ok(answer) => answer.content.tags.unwrap_or_default(),
+            Err(e) if e.is_not_found() => continue,
+            Err(e) => return Err(e),
+        };
+        let tags: Vec<String> = tags
+            .into_iter()
+            .filter(|tag| rule.tags.is_empty() || rule.tags.iter().any(|pattern| matches(pattern, tag)))
+            .collect();
+
+        if !tags.is_empty() {
+            images.push((repository, tags));
+        }
+    }
+
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registries::RegistryEntry;
+    use crate::testing::{registry_client, serve, serve_registry, sha256_digest, FakeRegistry, Reply};
+    use crate::types::{Permissions, RemoteRegistry};
+    use serde_json::json;
+    use time::format_description::well_known::Rfc3339;
+
+    fn rule(repositories: &[&str], tags: &[&str]) -> ReplicationRule {
+        ReplicationRule {
+            id: String::new(),
+            name: "mirror".to_string(),
+            registry: None,
+            remote: RemoteRegistry {
+                host: "remote.example.com".to_string(),
+                unsecured: false,
+                username: None,
+                password: Some("secret".to_string()),
+            },
+            direction: ReplicationDirection::Push,
+            repositories: repositories.iter().map(|pattern| pattern.to_string()).collect(),
+            tags: tags.iter().map(|pattern| pattern.to_string()).collect(),
+            destination_namespace: None,
+            interval_minutes: None,
+            enabled: true,
+            last_run_at: None,
+        }
+    }
+
+    async fn finished(replication: &Replication, id: &str) -> ReplicationJob {
+        for _ in 0..100 {
+            let job = replication.job(id).unwrap();
+            if job.status != JobStatus::Running {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Job {} still running", id);
+    }
+
+    #[rocket::async_test]
+    async fn lists_the_images_rules_match() {
+        let address = serve(|req| match req.path.as_str() {
+            "/v2/_catalog" => Reply::json(
+                200,
+                &json!({"repositories": ["team/app", "team/tools/lint", "other/app", "team/empty"]}),
+            ),
+            "/v2/team/app/tags/list" => Reply::json(200, &json!({"name": "team/app", "tags": ["v1.0", "v1.1", "dev"]})),
+            "/v2/team/tools/lint/tags/list" => Reply::json(200, &json!({"name": "team/tools/lint", "tags": ["v1.2"]})),
+            "/v2/team/empty/tags/list" => Reply::json(200, &json!({"name": "team/empty", "tags": ["dev"]})),
+            _ => Reply::json(404, &json!({"errors": [{"code": "NAME_UNKNOWN", "message": "no"}]})),
+        })
+        .await;
+        let source = RegistryClient::new(&RegistryConfig {
+            base_uri: address,
+            is_secured: false,
+            http_basic_user: None,
+            http_basic_pass: None,
+        });
+
+        let images = list_images(&source, &rule(&["team/*"], &["v1.*"])).await.unwrap();
+        assert_eq!(
+            images,
+            vec![
+                ("team/app".to_string(), vec!["v1.0".to_string(), "v1.1".to_string()]),
+                ("team/tools/lint".to_string(), vec!["v1.2".to_string()]),
+            ]
+        );
+
+        // Repositories named without patterns aren't looked up in the catalog, missing ones are skipped
+        let images = list_images(&source, &rule(&["other/app", "gone"], &[])).await.unwrap();
+        assert!(images.is_empty());
+    }
+
+    #[test]
+    fn reports_how_jobs_finished() {
+        let progress = |images_total, images_failed| ReplicationProgress {
+            images_total,
+            images_failed,
+            ..ReplicationProgress::default()
+        };
+
+        assert_eq!(finished_status(&progress(3, 0)), JobStatus::Succeeded);
+        assert_eq!(finished_status(&progress(0, 0)), JobStatus::Succeeded);
+        assert_eq!(finished_status(&progress(3, 1)), JobStatus::Partial);
+        assert_eq!(finished_status(&progress(3, 3)), JobStatus::Failed);
+    }
+
+    #[rocket::async_test]
+    async fn fails_jobs_without_their_registry_and_runs_rules_once_at_a_time() {
+        let replication = Replication::new(Registries::new(Vec::new()), None).unwrap();
+        let created = replication
+            .create_rule(ReplicationRule {
+                registry: Some("missing".to_string()),
+                ..rule(&["team/*"], &[])
+            })
+            .unwrap();
+        assert_eq!(created.remote.password, None);
+
+        let started = replication.run(&created.id).unwrap();
+        assert_eq!(started.status, JobStatus::Running);
+        let job = finished(&replication, &started.id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.errors, vec!["No registry missing"]);
+        assert!(job.finished_at.is_some());
+
+        replication.jobs.jobs.update_unsaved(|jobs| {
+            jobs.push(ReplicationJob {
+                id: "running".to_string(),
+                status: JobStatus::Running,
+                ..job
+            })
+        });
+        assert!(matches!(
+            replication.run(&created.id),
+            Err(ReplicationError::AlreadyRunning(_))
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn schedules_from_the_last_job_start() {
+        let now = OffsetDateTime::parse("2026-10-18T12:00:00Z", &Rfc3339).unwrap();
+        let scheduled = |last_run_at: Option<&str>| ReplicationRule {
+            interval_minutes: Some(60),
+            last_run_at: last_run_at.map(str::to_string),
+            ..rule(&["team/*"], &[])
+        };
+
+        assert!(is_due(&scheduled(None), now));
+        assert!(!is_due(&scheduled(Some("2026-10-18T11:30:00Z")), now));
+        assert!(is_due(&scheduled(Some("2026-10-18T11:00:00Z")), now));
+        assert!(!is_due(&rule(&["team/*"], &[]), now));
+        let disabled = ReplicationRule {
+            enabled: false,
+            ..scheduled(None)
+        };
+        assert!(!is_due(&disabled, now));
+
+        // Starts are saved with the rule, so that a restart doesn't run it again early
+        let replication = Replication::new(Registries::new(Vec::new()), None).unwrap();
+        let created = replication
+            .create_rule(scheduled(Some("2026-10-18T11:00:00Z")))
+            .unwrap();
+        assert_eq!(created.last_run_at, None);
+        replication.run(&created.id).unwrap();
+        let started = replication.rules()[0].last_run_at.clone().unwrap();
+        replication.update_rule(&created.id, scheduled(None)).unwrap();
+        assert_eq!(replication.rules()[0].last_run_at.as_deref(), Some(started.as_str()));
+        assert!(matches!(
+            mark_started(&replication.rules, "missing"),
+            Err(ReplicationError::RuleNotFound(_))
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn copies_images_resuming_uploads_and_refusing_altered_content() {
+        let mut local = FakeRegistry::default();
+        let copied = local.push("app", "1.0", b"first layer");
+        local.push("app", "altered", b"second layer");
+        local.blobs.insert(
+            format!("app@{}", sha256_digest(b"second layer")),
+            b"altered layer".to_vec(),
+        );
+        local.push("app", "same", b"shared layer");
+        let (local, _) = serve_registry(local).await;
+
+        let mut remote = FakeRegistry {
+            failing_uploads: vec![1],
+            ..FakeRegistry::default()
+        };
+        remote.push("app", "same", b"shared layer");
+        let (remote, remote_registry) = serve_registry(remote).await;
+
+        let registries = Registries::new(vec![RegistryEntry {
+            id: DEFAULT_REGISTRY.to_string(),
+            name: "local".to_string(),
+            host: local.clone(),
+            permissions: Permissions {
+                delete: false,
+                retag: false,
+                push: true,
+                garbage_collect: false,
+            },
+            client: registry_client(local),
+        }]);
+        let replication = Replication::new(registries, None).unwrap();
+        let mut pushed = rule(&["app"], &[]);
+        pushed.remote = RemoteRegistry {
+            host: remote,
+            unsecured: true,
+            username: None,
+            password: None,
+        };
+        let created = replication.create_rule(pushed).unwrap();
+
+        let job = finished(&replication, &replication.run(&created.id).unwrap().id).await;
+        assert_eq!(job.status, JobStatus::Partial);
+        let progress = &job.progress;
+        assert_eq!(
+            (
+                progress.images_total,
+                progress.images_copied,
+                progress.images_up_to_date,
+                progress.images_failed
+            ),
+            (3, 1, 1, 1)
+        );
+        // The config blob is already there, the layer upload fails once and resumes
+        assert_eq!((progress.blobs_copied, progress.blobs_skipped), (1, 1));
+        assert_eq!(job.errors.len(), 1);
+        assert!(job.errors[0].starts_with("app:altered: ") && job.errors[0].contains("doesn't match"));
+
+        let remote_registry = remote_registry.lock().unwrap();
+        assert!(remote_registry.manifests.contains_key(&format!("app:{}", copied)));
+        assert!(!remote_registry.manifests.contains_key("app:altered"));
+        let uploads: Vec<&String> = remote_registry
+            .log
+            .iter()
+            .filter(|l| l.contains("/blobs/uploads/0"))
+            .collect();
+        assert_eq!(
+            uploads,
+            vec![
+                "PUT /v2/app/blobs/uploads/0",
+                "GET /v2/app/blobs/uploads/0",
+                "PUT /v2/app/blobs/uploads/0",
+            ]
+        );
+    }
+}
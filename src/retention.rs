@@ -0,0 +1,758 @@
+use crate::manager::{image_created, matching_repositories};
+use crate::registries::{Registries, DEFAULT_REGISTRY};
+use crate::registry_api::types::{ClientError, Manifest};
+use crate::registry_api::RegistryClient;
+use crate::store::{JsonStore, StoreError};
+use crate::types::{JobStatus, JobTrigger, RetentionAction, RetentionDecision, RetentionPolicy, RetentionRun};
+use crate::util::{interval_elapsed, now};
+use regex::Regex;
+use rocket::futures::stream::{self, StreamExt};
+use rocket::tokio;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Finished runs beyond that are forgotten, oldest first.
+const MAX_KEPT_RUNS: usize = 50;
+const MAX_RUN_ERRORS: usize = 100;
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Tags of a repository inspected at the same time.
+const INSPECT_CONCURRENCY: usize = 8;
+
+#[derive(Debug)]
+pub enum RetentionError {
+    PolicyNotFound(String),
+    AlreadyRunning(String),
+    Storage(String),
+}
+
+impl From<StoreError> for RetentionError {
+    fn from(e: StoreError) -> Self {
+        RetentionError::Storage(e.to_string())
+    }
+}
+
+impl fmt::Display for RetentionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetentionError::PolicyNotFound(id) => write!(f, "No retention policy {}", id),
+            RetentionError::AlreadyRunning(id) => write!(f, "Retention policy {} is already running", id),
+            RetentionError::Storage(e) => write!(f, "Can't save retention policies: {}", e),
+        }
+    }
+}
+
+/// Tag retention policies. Policies are kept in `HARBUI_RETENTION_FILE` when set and only in
+/// memory otherwise, runs and their reports are never persisted.
+pub struct Retention {
+    policies: Arc<JsonStore<RetentionPolicy>>,
+    runs: Runs,
+}
+
+impl Retention {
+    pub fn new(registries: Registries, file: Option<String>) -> Result<Self, String> {
+        Ok(Self {
+            policies: Arc::new(JsonStore::open("retention policies", file)?),
+            runs: Runs {
+                registries,
+                runs: Arc::new(JsonStore::in_memory()),
+            },
+        })
+    }
+
+    /// Runs policies with an interval in the background, an interval after their last run
+    /// started. Policies that never ran start right away.
+    pub fn start_scheduler(&self) {
+        let policies = self.policies.clone();
+        let runs = self.runs.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                let now = OffsetDateTime::now_utc();
+                let due: Vec<RetentionPolicy> = policies
+                    .read()
+                    .iter()
+                    .filter(|policy| is_due(policy, now))
+                    .cloned()
+                    .collect();
+
+                for policy in due {
+                    // Recorded before deleting anything, a crash during the run must not repeat it on restart
+                    if let Err(e) = mark_started(&policies, &policy.id) {
+                        error!("Skipping scheduled retention {}: {}", policy.name, e);
+                        continue;
+                    }
+                    let dry_run = policy.dry_run;
+                    if let Err(e) = runs.start(policy, JobTrigger::Scheduled, dry_run) {
+                        info!("Skipping scheduled retention: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn policies(&self) -> Vec<RetentionPolicy> {
+        self.policies.read().clone()
+    }
+
+    pub fn policy(&self, id: &str) -> Option<RetentionPolicy> {
+        self.policies.read().iter().find(|policy| policy.id == id).cloned()
+    }
+
+    pub fn create_policy(&self, mut policy: RetentionPolicy) -> Result<RetentionPolicy, RetentionError> {
+        policy.id = uuid::Uuid::new_v4().to_string();
+        policy.last_run_at = None;
+
+        self.policies.update(|policies| {
+            policies.push(policy.clone());
+            Ok::<_, RetentionError>(())
+        })?;
+
+        Ok(policy)
+    }
+
+    pub fn update_policy(&self, id: &str, mut policy: RetentionPolicy) -> Result<RetentionPolicy, RetentionError> {
+        self.policies.update(|policies| {
+            let current = policies
+                .iter_mut()
+                .find(|policy| policy.id == id)
+                .ok_or_else(|| RetentionError::PolicyNotFound(id.to_string()))?;
+
+            policy.id = id.to_string();
+            policy.last_run_at = current.last_run_at.take();
+            *current = policy.clone();
+            Ok(policy)
+        })
+    }
+
+    pub fn delete_policy(&self, id: &str) -> Result<(), RetentionError> {
+        self.policies.update(|policies| {
+            let count = policies.len();
+            policies.retain(|policy| policy.id != id);
+            if policies.len() == count {
+                return Err(RetentionError::PolicyNotFound(id.to_string()));
+            }
+            Ok(())
+        })
+    }
+
+    /// Deletes tags unless the policy is still a dry run.
+    pub fn run(&self, id: &str) -> Result<RetentionRun, RetentionError> {
+        let policy = self
+            .policy(id)
+            .ok_or_else(|| RetentionError::PolicyNotFound(id.to_string()))?;
+        let dry_run = policy.dry_run;
+        mark_started(&self.policies, id)?;
+
+        self.runs.start(policy, JobTrigger::Manual, dry_run)
+    }
+
+    /// Reports what a run would delete, whatever the policy says.
+    pub fn dry_run(&self, id: &str) -> Result<RetentionRun, RetentionError> {
+        let policy = self
+            .policy(id)
+            .ok_or_else(|| RetentionError::PolicyNotFound(id.to_string()))?;
+
+        self.runs.start(policy, JobTrigger::Manual, true)
+    }
+
+    /// Newest first.
+    pub fn runs(&self) -> Vec<RetentionRun> {
+        self.runs.runs.read().iter().rev().cloned().collect()
+    }
+
+    pub fn run_report(&self, id: &str) -> Option<RetentionRun> {
+        self.runs.runs.read().iter().find(|run| run.id == id).cloned()
+    }
+}
+
+fn is_due(policy: &RetentionPolicy, now: OffsetDateTime) -> bool {
+    let interval = policy.interval_minutes.filter(|_| policy.enabled);
+
+    interval_elapsed(interval, policy.last_run_at.as_deref(), now)
+}
+
+fn mark_started(policies: &JsonStore<RetentionPolicy>, id: &str) -> Result<(), RetentionError> {
+    policies.update(|policies| {
+        let policy = policies
+            .iter_mut()
+            .find(|policy| policy.id == id)
+            .ok_or_else(|| RetentionError::PolicyNotFound(id.to_string()))?;
+        policy.last_run_at = Some(now());
+        Ok(())
+    })
+}
+
+#[derive(Clone)]
+struct Runs {
+    registries: Registries,
+    runs: Arc<JsonStore<RetentionRun>>,
+}
+
+impl Runs {
+    /// Starts the policy in the background, unless it is already running.
+    fn start(
+        &self,
+        policy: RetentionPolicy,
+        trigger: JobTrigger,
+        dry_run: bool,
+    ) -> Result<RetentionRun, RetentionError> {
+        let run = RetentionRun {
+            id: uuid::Uuid::new_v4().to_string(),
+            policy_id: policy.id.clone(),
+            policy_name: policy.name.clone(),
+            registry: policy.registry.clone().unwrap_or_else(|| DEFAULT_REGISTRY.to_string()),
+            trigger,
+            dry_run,
+            status: JobStatus::Running,
+            started_at: now(),
+            finished_at: None,
+            repositories: 0,
+            tags_evaluated: 0,
+            tags_to_delete: 0,
+            tags_deleted: 0,
+            decisions: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        self.runs.update(|runs| {
+            if runs
+                .iter()
+                .any(|run| run.policy_id == policy.id && run.status == JobStatus::Running)
+            {
+                return Err(RetentionError::AlreadyRunning(policy.id.clone()));
+            }
+
+            runs.push(run.clone());
+            let finished = runs.iter().filter(|run| run.status != JobStatus::Running).count();
+            let mut excess = finished.saturating_sub(MAX_KEPT_RUNS);
+            runs.retain(|run| {
+                let drop = excess > 0 && run.status != JobStatus::Running;
+                excess -= usize::from(drop);
+                !drop
+            });
+            Ok(())
+        })?;
+
+        let runs = self.clone();
+        let id = run.id.clone();
+        tokio::spawn(async move { runs.execute(&id, policy, dry_run).await });
+
+        Ok(run)
+    }
+
+    async fn execute(&self, id: &str, policy: RetentionPolicy, dry_run: bool) {
+        let fail = |message: String| {
+            error!("Retention {} ({}) failed: {}", policy.name, id, message);
+            self.update(id, |run| {
+                run.errors.push(message);
+                run.status = JobStatus::Failed;
+                run.finished_at = Some(now());
+            });
+        };
+
+        let registry_id = policy.registry.as_deref().unwrap_or(DEFAULT_REGISTRY);
+        let Some(registry) = self.registries.get(registry_id) else {
+            return fail(format!("No registry {}", registry_id));
+        };
+        // Deleting may have been turned off since the policy was saved
+        if !dry_run && !registry.permissions.delete {
+            return fail(format!("Deleting images is disabled for registry {}", registry_id));
+        }
+        let keep_tags = match policy
+            .keep_tags
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(keep_tags) => keep_tags,
+            Err(e) => return fail(format!("Invalid tag pattern: {}", e)),
+        };
+        let client = &registry.client;
+        info!("Starting retention {} ({}), dry run: {}", policy.name, id, dry_run);
+
+        let repositories = match matching_repositories(client, &policy.repositories).await {
+            Ok(repositories) => repositories,
+            Err(e) => return fail(format!("Can't list repositories: {}", e)),
+        };
+        self.update(id, |run| run.repositories = repositories.len());
+
+        let cutoff = policy
+            .older_than_days
+            .map(|days| OffsetDateTime::now_utc() - time::Duration::days(days as i64));
+
+        for repository in &repositories {
+            let tags = match client.get_tags(repository).await {
+                Ok(answer) => answer.content.tags.unwrap_or_default(),
+                Err(e) if e.is_not_found() => continue,
+                Err(e) => {
+                    self.error(id, format!("{}: can't list tags: {}", repository, e));
+                    continue;
+                }
+            };
+            let images: Vec<TagImage> = stream::iter(tags)
+                .map(|tag| inspect(client, repository, tag))
+                .buffered(INSPECT_CONCURRENCY)
+                .collect()
+                .await;
+            let decisions = decide(&policy, &keep_tags, cutoff, repository, images);
+
+            let mut deleted = 0;
+            if !dry_run {
+                let digests: BTreeSet<&str> = decisions
+                    .iter()
+                    .filter(|decision| decision.action == RetentionAction::Delete)
+                    .filter_map(|decision| decision.digest.as_deref())
+                    .collect();
+
+                // Deleting a digest removes every tag pointing at it, all of them are candidates here
+                for digest in digests {
+                    match client.delete_manifest(repository, digest).await {
+                        Ok(_) => {
+                            deleted += decisions
+                                .iter()
+                                .filter(|decision| decision.digest.as_deref() == Some(digest))
+                                .count();
+                            info!("Retention {} deleted {}@{}", policy.name, repository, digest);
+                        }
+                        Err(e) => self.error(id, format!("{}@{}: can't delete: {}", repository, digest, e)),
+                    }
+                }
+            }
+
+            self.update(id, |run| {
+                run.tags_evaluated += decisions.len();
+                run.tags_to_delete += decisions
+                    .iter()
+                    .filter(|decision| decision.action == RetentionAction::Delete)
+                    .count();
+                run.tags_deleted += deleted;
+                run.decisions.extend(decisions);
+            });
+        }
+
+        self.update(id, |run| {
+            run.status = if run.errors.is_empty() {
+                JobStatus::Succeeded
+            } else {
+                JobStatus::Partial
+            };
+            run.finished_at = Some(now());
+            info!(
+                "Retention {} ({}) finished: {:?}, {} of {} tags to delete, {} deleted",
+                policy.name, id, run.status, run.tags_to_delete, run.tags_evaluated, run.tags_deleted
+            );
+        });
+    }
+
+    fn error(&self, id: &str, message: String) {
+        warn!("Retention {}: {}", id, message);
+        self.update(id, |run| {
+            if run.errors.len() < MAX_RUN_ERRORS {
+                run.errors.push(message);
+            }
+        });
+    }
+
+    fn update(&self, id: &str, change: impl FnOnce(&mut RetentionRun)) {
+        self.runs.update_unsaved(|runs| {
+            if let Some(run) = runs.iter_mut().find(|run| run.id == id) {
+                change(run);
+            }
+        });
+    }
+}
+
+struct TagImage {
+    tag: String,
+    image: Result<Image, ClientError>,
+}
+
+struct Image {
+    digest: Option<String>,
+    /// The newest creation date among the platforms
+    created: Option<OffsetDateTime>,
+    /// Manifests of an index
+    children: Vec<String>,
+}
+
+async fn inspect(client: &RegistryClient, repository: &str, tag: String) -> TagImage {
+    let image = async {
+        let manifest = client.get_manifest(repository, &tag).await?;
+        let children = match &manifest.content {
+            Manifest::OCIImageIndexV1(m) => m.manifests.iter().map(|m| m.digest.clone()).collect(),
+            Manifest::DockerDistributionManifestListV2(m) => m.manifests.iter().map(|m| m.digest.clone()).collect(),
+            _ => Vec::new(),
+        };
+        let reference = manifest.digest.as_deref().unwrap_or(&tag);
+        let created = image_created(client, repository, reference).await?;
+
+        Ok(Image {
+            digest: manifest.digest,
+            created,
+            children,
+        })
+    }
+    .await;
+
+    TagImage { tag, image }
+}
+
+/// Decides every tag of a repository, newest first.
+fn decide(
+    policy: &RetentionPolicy,
+    keep_tags: &[Regex],
+    cutoff: Option<OffsetDateTime>,
+    repository: &str,
+    mut images: Vec<TagImage>,
+) -> Vec<RetentionDecision> {
+    let created = |image: &TagImage| image.image.as_ref().ok().and_then(|image| image.created);
+    images.sort_by(|a, b| created(b).cmp(&created(a)).then_with(|| a.tag.cmp(&b.tag)));
+
+    let mut rank = 0;
+    let mut decisions: Vec<RetentionDecision> = images
+        .iter()
+        .map(|tag_image| {
+            let (digest, action, reason) = match &tag_image.image {
+                Err(e) => (None, RetentionAction::Keep, format!("Can't inspect the image: {}", e)),
+                Ok(image) => {
+                    let newest = image.created.is_some() && policy.keep_last.is_some_and(|n| rank < n);
+                    rank += usize::from(image.created.is_some());
+                    let (action, reason) = keep_or_delete(policy, keep_tags, cutoff, &tag_image.tag, image, newest);
+                    (image.digest.clone(), action, reason)
+                }
+            };
+
+            RetentionDecision {
+                repository: repository.to_string(),
+                tag: tag_image.tag.clone(),
+                digest,
+                created: created(tag_image).and_then(|created| created.format(&Rfc3339).ok()),
+                action,
+                reason,
+            }
+        })
+        .collect();
+
+    // Any tag may point at the digest of one that couldn't be inspected, deleting it would take that one along
+    let unknown = images
+        .iter()
+        .find(|tag_image| !tag_image.image.as_ref().is_ok_and(|image| image.digest.is_some()));
+    if let Some(unknown) = unknown {
+        for decision in decisions.iter_mut().filter(|d| d.action == RetentionAction::Delete) {
+            decision.action = RetentionAction::Keep;
+            decision.reason = format!("Tag {} couldn't be inspected, it may share this digest", unknown.tag);
+        }
+        return decisions;
+    }
+
+    // A kept tag keeps its digest and the manifests of its index, and so every tag pointing at them
+    let children: HashMap<&str, &[String]> = images
+        .iter()
+        .filter_map(|tag_image| {
+            let image = tag_image.image.as_ref().ok()?;
+            Some((image.digest.as_deref()?, image.children.as_slice()))
+        })
+        .collect();
+    loop {
+        let mut kept: HashMap<String, String> = HashMap::new();
+        for decision in decisions.iter().filter(|d| d.action == RetentionAction::Keep) {
+            let Some(digest) = decision.digest.as_deref() else {
+                continue;
+            };
+            kept.entry(digest.to_string()).or_insert_with(|| decision.tag.clone());
+            for child in children.get(digest).copied().unwrap_or_default() {
+                kept.entry(child.clone()).or_insert_with(|| decision.tag.clone());
+            }
+        }
+
+        let mut changed = false;
+        for decision in decisions.iter_mut().filter(|d| d.action == RetentionAction::Delete) {
+            if let Some(tag) = decision.digest.as_ref().and_then(|digest| kept.get(digest)) {
+                decision.action = RetentionAction::Keep;
+                decision.reason = format!("Its digest is also referenced by kept tag {}", tag);
+                changed = true;
+            }
+        }
+        if !changed {
+            return decisions;
+        }
+    }
+}
+
+fn keep_or_delete(
+    policy: &RetentionPolicy,
+    keep_tags: &[Regex],
+    cutoff: Option<OffsetDateTime>,
+    tag: &str,
+    image: &Image,
+    newest: bool,
+) -> (RetentionAction, String) {
+    let keep = |reason: String| (RetentionAction::Keep, reason);
+
+    if let Some(pattern) = keep_tags.iter().find(|pattern| pattern.is_match(tag)) {
+        return keep(format!("Matches {}", pattern.as_str()));
+    }
+    if image.digest.is_none() {
+        return keep("The registry didn't return its digest".to_string());
+    }
+    let Some(created) = image.created else {
+        return keep("Creation date unknown".to_string());
+    };
+    if let (true, Some(n)) = (newest, policy.keep_last) {
+        return keep(format!("Among the {} newest", n));
+    }
+    if let (Some(cutoff), Some(days)) = (cutoff, policy.older_than_days) {
+        if created >= cutoff {
+            return keep(format!("Younger than {} days", days));
+        }
+    }
+
+    let reason = match (policy.keep_last, policy.older_than_days) {
+        (Some(n), Some(days)) => format!("Not among the {} newest and older than {} days", n, days),
+        (Some(n), None) => format!("Not among the {} newest", n),
+        (None, Some(days)) => format!("Older than {} days", days),
+        // Rejected when the policy is saved
+        (None, None) => return keep("The policy has no deletion rule".to_string()),
+    };
+    (RetentionAction::Delete, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RetentionAction::{Delete, Keep};
+
+    fn policy(interval_minutes: Option<u64>, last_run_at: Option<&str>) -> RetentionPolicy {
+        RetentionPolicy {
+            id: "p".to_string(),
+            name: "cleanup".to_string(),
+            registry: None,
+            repositories: vec!["*".to_string()],
+            keep_last: Some(3),
+            keep_tags: Vec::new(),
+            older_than_days: None,
+            interval_minutes,
+            dry_run: false,
+            enabled: true,
+            last_run_at: last_run_at.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn schedules_from_the_last_run() {
+        let now = OffsetDateTime::parse("2026-10-18T12:00:00Z", &Rfc3339).unwrap();
+
+        assert!(is_due(&policy(Some(60), None), now));
+        assert!(!is_due(&policy(Some(60), Some("2026-10-18T11:30:00Z")), now));
+        assert!(is_due(&policy(Some(60), Some("2026-10-18T11:00:00Z")), now));
+        assert!(!is_due(&policy(None, None), now));
+
+        let disabled = RetentionPolicy {
+            enabled: false,
+            ..policy(Some(60), None)
+        };
+        assert!(!is_due(&disabled, now));
+    }
+
+    #[test]
+    fn records_run_starts() {
+        let policies = JsonStore::open("retention policies", None).unwrap();
+        policies
+            .update(|policies| {
+                policies.push(policy(Some(60), None));
+                Ok::<_, RetentionError>(())
+            })
+            .unwrap();
+
+        mark_started(&policies, "p").unwrap();
+        let started = policies.read()[0].last_run_at.clone().unwrap();
+        assert!(!is_due(
+            &policies.read()[0],
+            OffsetDateTime::parse(&started, &Rfc3339).unwrap()
+        ));
+        assert!(mark_started(&policies, "missing").is_err());
+    }
+
+    /// `(tag, digest, days before now it was created, index children)`, `None` digests fail inspection.
+    type Tag<'a> = (&'a str, Option<&'a str>, Option<i64>, &'a [&'a str]);
+    /// Tags and the expected actions, newest first.
+    type Case<'a> = (&'a [Tag<'a>], Vec<(&'a str, RetentionAction)>);
+
+    fn decided(policy: &RetentionPolicy, keep_tags: &[&str], tags: &[Tag]) -> Vec<(String, RetentionAction, String)> {
+        let now = OffsetDateTime::parse("2026-10-18T12:00:00Z", &Rfc3339).unwrap();
+        let keep_tags: Vec<Regex> = keep_tags.iter().map(|pattern| Regex::new(pattern).unwrap()).collect();
+        let cutoff = policy
+            .older_than_days
+            .map(|days| now - time::Duration::days(days as i64));
+        let images = tags
+            .iter()
+            .map(|(tag, digest, age, children)| TagImage {
+                tag: tag.to_string(),
+                image: match digest {
+                    Some(digest) => Ok(Image {
+                        digest: Some(digest.to_string()),
+                        created: age.map(|days| now - time::Duration::days(days)),
+                        children: children.iter().map(|child| child.to_string()).collect(),
+                    }),
+                    None => Err(ClientError::Timeout),
+                },
+            })
+            .collect();
+
+        decide(policy, &keep_tags, cutoff, "app", images)
+            .into_iter()
+            .map(|decision| (decision.tag, decision.action, decision.reason))
+            .collect()
+    }
+
+    fn actions(decisions: &[(String, RetentionAction, String)]) -> Vec<(&str, RetentionAction)> {
+        decisions
+            .iter()
+            .map(|(tag, action, _)| (tag.as_str(), *action))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_tags_and_the_ones_matching_patterns() {
+        let keep_last = RetentionPolicy {
+            keep_last: Some(2),
+            ..policy(None, None)
+        };
+        let tags: &[Tag] = &[
+            ("old", Some("sha256:1"), Some(30), &[]),
+            ("v1.0", Some("sha256:2"), Some(20), &[]),
+            ("new", Some("sha256:3"), Some(1), &[]),
+            ("undated", Some("sha256:4"), None, &[]),
+            ("newer", Some("sha256:5"), Some(0), &[]),
+            ("older", Some("sha256:6"), Some(10), &[]),
+        ];
+
+        let decisions = decided(&keep_last, &["^v"], tags);
+        assert_eq!(
+            actions(&decisions),
+            vec![
+                ("newer", Keep),
+                ("new", Keep),
+                ("older", Delete),
+                ("v1.0", Keep),
+                ("old", Delete),
+                ("undated", Keep),
+            ]
+        );
+        assert_eq!(decisions[0].2, "Among the 2 newest");
+        assert_eq!(decisions[3].2, "Matches ^v");
+        assert_eq!(decisions[4].2, "Not among the 2 newest");
+        assert_eq!(decisions[5].2, "Creation date unknown");
+    }
+
+    #[test]
+    fn deletes_tags_older_than_the_maximum_age() {
+        let tags: &[Tag] = &[
+            ("young", Some("sha256:1"), Some(5), &[]),
+            ("old", Some("sha256:2"), Some(20), &[]),
+            ("oldest", Some("sha256:3"), Some(40), &[]),
+        ];
+        let cases = [
+            (None, vec![("young", Keep), ("old", Delete), ("oldest", Delete)]),
+            (Some(2), vec![("young", Keep), ("old", Keep), ("oldest", Delete)]),
+        ];
+
+        for (keep_last, expected) in cases {
+            let policy = RetentionPolicy {
+                keep_last,
+                older_than_days: Some(10),
+                ..policy(None, None)
+            };
+            let decisions = decided(&policy, &[], tags);
+            assert_eq!(actions(&decisions), expected, "keep_last {:?}", keep_last);
+        }
+
+        let policy = RetentionPolicy {
+            keep_last: None,
+            older_than_days: Some(10),
+            ..policy(None, None)
+        };
+        let decisions = decided(&policy, &[], tags);
+        assert_eq!(decisions[0].2, "Younger than 10 days");
+        assert_eq!(decisions[1].2, "Older than 10 days");
+    }
+
+    #[test]
+    fn keeps_tags_sharing_digests_with_kept_ones() {
+        let keep_latest = RetentionPolicy {
+            keep_last: Some(1),
+            ..policy(None, None)
+        };
+        let cases: [Case; 3] = [
+            (
+                &[
+                    ("latest", Some("sha256:a"), Some(1), &[]),
+                    ("v1", Some("sha256:a"), Some(5), &[]),
+                    ("v0", Some("sha256:b"), Some(9), &[]),
+                ],
+                vec![("latest", Keep), ("v1", Keep), ("v0", Delete)],
+            ),
+            // Platform manifests of a kept index stay, along with tags pointing at them
+            (
+                &[
+                    (
+                        "multi",
+                        Some("sha256:index"),
+                        Some(1),
+                        &["sha256:amd64", "sha256:arm64"],
+                    ),
+                    ("amd64", Some("sha256:amd64"), Some(5), &[]),
+                    ("other", Some("sha256:other"), Some(9), &[]),
+                ],
+                vec![("multi", Keep), ("amd64", Keep), ("other", Delete)],
+            ),
+            // Kept because of its digest, the index keeps its children too
+            (
+                &[
+                    ("latest", Some("sha256:index"), Some(1), &[]),
+                    ("nested", Some("sha256:index"), Some(3), &["sha256:child"]),
+                    ("child", Some("sha256:child"), Some(9), &[]),
+                ],
+                vec![("latest", Keep), ("nested", Keep), ("child", Keep)],
+            ),
+        ];
+
+        for (tags, expected) in &cases {
+            assert_eq!(&actions(&decided(&keep_latest, &[], tags)), expected);
+        }
+        let decisions = decided(&keep_latest, &[], cases[0].0);
+        assert_eq!(decisions[1].2, "Its digest is also referenced by kept tag latest");
+    }
+
+    #[test]
+    fn deletes_nothing_when_a_tag_can_not_be_inspected() {
+        let keep_latest = RetentionPolicy {
+            keep_last: Some(1),
+            ..policy(None, None)
+        };
+        let tags: &[Tag] = &[
+            ("latest", Some("sha256:a"), Some(1), &[]),
+            ("broken", None, None, &[]),
+            ("old", Some("sha256:b"), Some(9), &[]),
+        ];
+
+        let decisions = decided(&keep_latest, &[], tags);
+        assert!(decisions.iter().all(|(_, action, _)| *action == Keep));
+        let reasons: Vec<&str> = decisions.iter().map(|(_, _, reason)| reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                "Among the 1 newest",
+                "Tag broken couldn't be inspected, it may share this digest",
+                "Can't inspect the image: Registry request timed out",
+            ]
+        );
+    }
+}
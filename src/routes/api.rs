@@ -1,139 +1,973 @@
-use crate::manager::{get_manifests, get_manifests_from_list};
-use crate::registry_api::{types::Manifest, RegistryClient};
-use crate::routes::types::{ApiAnswer, ApiError, ApiResponse, ConfigResponse, CountResponse, ImageManifestResponse};
-use crate::types::{Config, ImageTags};
-use itertools::Itertools;
-use rocket::{futures::future::join_all, State};
-use std::collections::HashMap;
+use crate::auth::Role;
+use crate::bulk::{self, Selection};
+use crate::copy::Copier;
+use crate::diff::{self, ResolvedImage};
+use crate::dockerfile;
+use crate::layers;
+use crate::manager::{
+    build_namespace_tree, correlate_layers, get_manifests, resolve_configs, resolve_manifests, tag_digests,
+};
+use crate::registries::{validate_host, Registries, RegistryEntry};
+use crate::registry_api::RegistryClient;
+use crate::replication::Replication;
+use crate::retention::Retention;
+use crate::routes::paths::{
+    is_valid_digest, is_valid_repository_name, is_valid_repository_pattern, is_valid_tag, ImageConfigPath,
+    ImageDockerfilePath, ImageFilesystemPath, ImageImpactPath, ImagePath, ImagePromotePath, ImageRetagPath,
+    ImageSbomPath, ImageVulnerabilitiesPath, LayerTreePath, RepositoryPath,
+};
+use crate::routes::permission::{ensure_allowed, Allowed, Deleting, Retagging};
+use crate::routes::registry::Registry;
+use crate::routes::types::{
+    ApiAnswer, ApiError, ApiErrorKind, ApiResponse, BulkDeleteRequest, ConfigResponse, CopyResponse, CountResponse,
+    DeleteImpactResponse, DeleteResponse, DiffPlatforms, DockerfileResponse, FilesystemResponse, ImageConfigDetails,
+    ImageConfigsResponse, ImageDiffResponse, ImageManifestResponse, LayerTreeResponse, PlatformDockerfile,
+    PlatformFilesystem, PlatformSbom, PlatformVulnerabilities, PromoteRequest, RegistriesResponse, RegistrySummary,
+    RetagRequest, SbomResponse, VulnerabilityReportResponse,
+};
+use crate::routes::user::User;
+use crate::sbom::{self, InventoryCache, SbomFormat, SbomSubject};
+use crate::settings::Settings;
+use crate::types::SeverityCounts;
+use crate::types::{
+    BulkDeleteReport, ImageTags, NamespaceNode, Operation, ReplicationJob, ReplicationRule, RetentionPolicy,
+    RetentionRun,
+};
+use crate::vulnerabilities::{AdvisoryDatabase, Scanner};
+use regex::Regex;
+use rocket::futures::future::{join_all, try_join};
+use rocket::http::{RawStr, Status};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+/// Images a single bulk delete may touch, filters included.
+const MAX_BULK_IMAGES: usize = 1000;
 
 #[get("/count/users")]
-pub async fn count_users(client: &State<RegistryClient>) -> ApiResponse<CountResponse> {
-    let repositories = match client.get_catalog().await {
-        Ok(r) => r.content.repositories,
-        Err(_) => Vec::new(),
-    };
+pub async fn count_users(user: User, registry: Registry) -> ApiResponse<CountResponse> {
+    let client = &registry.client;
+    let repositories = user.visible(&registry.id, client.get_catalog().await?.content.repositories);
 
-    let users: Vec<String> = repositories
-        .iter()
-        .map(|s| s.split('/').next().unwrap().to_string())
-        .unique()
-        .collect::<Vec<String>>();
+    let users = build_namespace_tree(&repositories)
+        .into_iter()
+        .filter(|node| !node.children.is_empty())
+        .count();
+
+    ApiAnswer::success(CountResponse { count: users })
+}
 
-    ApiAnswer::success(CountResponse { count: users.len() })
+#[get("/namespaces")]
+pub async fn get_namespaces(user: User, registry: Registry) -> ApiResponse<Vec<NamespaceNode>> {
+    let client = &registry.client;
+    let repositories = user.visible(&registry.id, client.get_catalog().await?.content.repositories);
+
+    ApiAnswer::success(build_namespace_tree(&repositories))
 }
 
 #[get("/count/repositories")]
-pub async fn count_repositories(client: &State<RegistryClient>) -> ApiResponse<CountResponse> {
-    let repos = match client.get_catalog().await {
-        Ok(r) => r.content.repositories,
-        Err(_) => Vec::new(),
-    };
+pub async fn count_repositories(user: User, registry: Registry) -> ApiResponse<CountResponse> {
+    let client = &registry.client;
+    let repos = user.visible(&registry.id, client.get_catalog().await?.content.repositories);
 
     ApiAnswer::success(CountResponse { count: repos.len() })
 }
 
-#[get("/repositories")]
-pub async fn get_repositories(client: &State<RegistryClient>) -> ApiResponse<Vec<ImageTags>> {
-    let repos = match client.get_catalog().await {
-        Ok(r) => r.content.repositories,
-        Err(_) => Vec::new(),
+#[get("/repositories?<n>&<last>")]
+pub async fn get_repositories(
+    user: User,
+    registry: Registry,
+    n: Option<usize>,
+    last: Option<&str>,
+) -> ApiResponse<Vec<ImageTags>> {
+    let client = &registry.client;
+    let catalog = if n.is_some() || last.is_some() {
+        client.get_catalog_page(n, last).await?
+    } else {
+        client.get_catalog().await?
     };
+    // Pages continue after the last repository listed, whether the user may see it or not
+    let next = catalog.next.and(catalog.content.repositories.last().cloned());
+    let repos = user.visible(&registry.id, catalog.content.repositories);
     let futures = repos.iter().map(|item| client.get_tags(item));
-    let image_tags: Vec<ImageTags> = join_all(futures)
-        .await
-        .into_iter()
-        .filter(|ans| ans.is_ok())
-        .map(|ans| {
-            let item = ans.unwrap().content;
-            ImageTags {
-                image: item.name,
-                tags: item.tags.unwrap_or(vec!["Tags not found :(".to_owned()]),
-            }
-        })
-        .collect();
 
-    ApiAnswer::success(image_tags)
-}
+    let mut image_tags: Vec<ImageTags> = Vec::new();
+    for ans in join_all(futures).await {
+        let item = match ans {
+            Ok(ans) => ans.content,
+            // The repository may have been removed since the catalog was listed
+            Err(e) if e.is_not_found() => continue,
+            Err(e) => return Err(ApiError::from(e)),
+        };
+
+        image_tags.push(ImageTags {
+            image: item.name,
+            tags: item.tags.unwrap_or(vec!["Tags not found :(".to_owned()]),
+        });
+    }
+    let link = next.map(|last| {
+        format!(
+            "{}{}",
+            registry.api_base(),
+            uri!(get_repositories(n, Some(last.as_str())))
+        )
+    });
 
-#[get("/<user>/<name>/tags")]
-pub async fn get_tags(client: &State<RegistryClient>, user: &str, name: &str) -> ApiResponse<Vec<String>> {
-    let image = format!("{}/{}", user, name);
+    ApiAnswer::paginated(image_tags, link)
+}
 
-    if let Ok(ans) = client.get_tags(&image).await {
-        ApiAnswer::success(ans.content.tags.unwrap_or_default())
+#[get("/<path..>?<n>&<last>", rank = 1)]
+pub async fn get_tags(
+    user: User,
+    registry: Registry,
+    path: RepositoryPath,
+    n: Option<usize>,
+    last: Option<&str>,
+) -> ApiResponse<Vec<String>> {
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let client = &registry.client;
+    let ans = if n.is_some() || last.is_some() {
+        client.get_tags_page(&path.repository, n, last).await?
     } else {
-        ApiAnswer::success(Vec::new())
-    }
+        client.get_tags(&path.repository).await?
+    };
+
+    let tags = ans.content.tags.unwrap_or_default();
+    let link = ans.next.and(tags.last()).map(|last| {
+        let n = n.map(|n| format!("n={}&", n)).unwrap_or_default();
+        format!(
+            "{}/{}/tags?{}last={}",
+            registry.api_base(),
+            path.repository,
+            n,
+            RawStr::new(last).percent_encode()
+        )
+    });
+
+    ApiAnswer::paginated(tags, link)
 }
 
 #[get("/config")]
-pub async fn get_config(state: &State<Config>) -> ApiResponse<ConfigResponse> {
+pub async fn get_config(
+    _user: User,
+    settings: &State<Settings>,
+    registries: &State<Registries>,
+    registry: Registry,
+) -> ApiResponse<ConfigResponse> {
     ApiAnswer::success(ConfigResponse {
-        registry_domain: state.host.clone(),
-        version: state.version.clone(),
+        registry_domain: registry.host.clone(),
+        registry_id: registry.id.clone(),
+        version: settings.config().version.clone(),
+        operations: registry.permissions,
+        registries: registries.all().iter().map(|entry| registry_info(entry)).collect(),
+    })
+}
+
+#[get("/registries")]
+pub async fn get_registries(user: User, registries: &State<Registries>) -> ApiResponse<RegistriesResponse> {
+    let user = &user;
+    let summaries = join_all(registries.all().iter().map(|entry| async move {
+        match entry.client.get_catalog().await {
+            Ok(catalog) => {
+                let repositories = user.visible(&entry.id, catalog.content.repositories);
+                let users = build_namespace_tree(&repositories)
+                    .into_iter()
+                    .filter(|node| !node.children.is_empty())
+                    .count();
+
+                RegistrySummary {
+                    repositories_count: Some(repositories.len()),
+                    users_count: Some(users),
+                    error: None,
+                    ..registry_info(entry)
+                }
+            }
+            // One registry being down must not hide the others
+            Err(e) => RegistrySummary {
+                error: Some(e.to_string()),
+                ..registry_info(entry)
+            },
+        }
+    }))
+    .await;
+
+    ApiAnswer::success(RegistriesResponse {
+        repositories_count: summaries.iter().filter_map(|s| s.repositories_count).sum(),
+        users_count: summaries.iter().filter_map(|s| s.users_count).sum(),
+        registries: summaries,
     })
 }
 
-#[get("/<user>/<name>/<tag>")]
+fn registry_info(entry: &RegistryEntry) -> RegistrySummary {
+    RegistrySummary {
+        id: entry.id.clone(),
+        name: entry.name.clone(),
+        domain: entry.host.clone(),
+        deleting_allowed: entry.permissions.delete,
+        operations: entry.permissions,
+        repositories_count: None,
+        users_count: None,
+        error: None,
+    }
+}
+
+#[get("/<path..>", rank = 2)]
 pub async fn get_images_by_tag(
-    client: &State<RegistryClient>,
-    user: &str,
-    name: &str,
-    tag: &str,
+    user: User,
+    registry: Registry,
+    inventories: &State<InventoryCache>,
+    database: &State<AdvisoryDatabase>,
+    path: ImagePath,
 ) -> ApiResponse<ImageManifestResponse> {
-    let image = format!("{}/{}", user, name);
-    let image_manifest = match client.get_manifest(&image, tag).await {
-        Ok(c) => c,
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let client = &registry.client;
+    let scanner = database.advisories().await.map(|advisories| Scanner {
+        inventories,
+        advisories,
+    });
+
+    let manifests = match get_manifests(client, &path.repository, &path.reference, scanner.as_ref()).await {
+        Ok(m) => m,
         Err(e) => {
             error!("Caught error {:?}", e);
-            return Err(ApiError::unprocessable(&e.to_string()));
+            return Err(ApiError::from(e));
         }
     };
 
-    let manifests = match &image_manifest.content {
-        Manifest::OCIImageIndexV1(m) => get_manifests_from_list(client, m.manifests.iter(), &image).await,
-        Manifest::DockerDistributionManifestListV2(m) => {
-            get_manifests_from_list(client, m.manifests.iter(), &image).await
-        }
-        Manifest::DockerDistributionManifestV2(m) => {
-            let mut configs: HashMap<String, (String, u64)> = HashMap::new();
-            let config_digest = m.config.digest.clone();
-            let manifest_digest = image_manifest.digest.unwrap();
+    ApiAnswer::success(ImageManifestResponse {
+        image: path.repository,
+        tag: path.reference,
+        manifests,
+    })
+}
+
+#[get("/<path..>?<platform>", rank = 3)]
+pub async fn get_image_config(
+    user: User,
+    registry: Registry,
+    path: ImageConfigPath,
+    platform: Option<&str>,
+) -> ApiResponse<ImageConfigsResponse> {
+    let client = &registry.client;
+    let ImageConfigPath(path) = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let configs = resolve_configs(client, &path.repository, &path.reference, platform).await?;
+
+    ApiAnswer::success(ImageConfigsResponse {
+        image: path.repository,
+        reference: path.reference,
+        configs: configs
+            .into_iter()
+            .map(|(manifest, config)| ImageConfigDetails {
+                digest: manifest.digest,
+                config_digest: manifest.config.digest,
+                platform: manifest.platform,
+                config,
+            })
+            .collect(),
+    })
+}
+
+#[get("/<path..>?<platform>", rank = 4)]
+pub async fn get_image_dockerfile(
+    user: User,
+    registry: Registry,
+    path: ImageDockerfilePath,
+    platform: Option<&str>,
+) -> ApiResponse<DockerfileResponse> {
+    let client = &registry.client;
+    let ImageDockerfilePath(path) = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let configs = resolve_configs(client, &path.repository, &path.reference, platform).await?;
+
+    ApiAnswer::success(DockerfileResponse {
+        image: path.repository,
+        reference: path.reference,
+        dockerfiles: configs
+            .into_iter()
+            .map(|(manifest, config)| {
+                let layers = correlate_layers(&manifest.layers, &config.history);
+                let instructions = dockerfile::instructions(&layers);
+
+                PlatformDockerfile {
+                    digest: manifest.digest,
+                    platform: manifest.platform,
+                    dockerfile: dockerfile::render(&instructions),
+                    instructions,
+                }
+            })
+            .collect(),
+    })
+}
+
+#[get("/<path..>", rank = 5)]
+pub async fn get_layer_tree(user: User, registry: Registry, path: LayerTreePath) -> ApiResponse<LayerTreeResponse> {
+    let client = &registry.client;
+    let LayerTreePath { image: path, digest } = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let manifests = resolve_manifests(client, &path.repository, &path.reference).await?;
+    let layer = manifests
+        .iter()
+        .flat_map(|m| &m.layers)
+        .find(|l| l.digest == digest)
+        .cloned()
+        .ok_or_else(|| {
+            ApiError::new(
+                Status::NotFound,
+                ApiErrorKind::NotFound,
+                "Layer is not part of the image",
+            )
+        })?;
 
-            configs.insert(config_digest, (manifest_digest, m.get_total_size()));
-            get_manifests(client, &configs, &image).await
+    let entries = layers::index_layer(client, &path.repository, &layer).await?;
+
+    ApiAnswer::success(LayerTreeResponse {
+        image: path.repository,
+        reference: path.reference,
+        digest: layer.digest,
+        media_type: layer.media_type,
+        files_count: entries.len(),
+        tree: layers::build_tree(entries),
+    })
+}
+
+#[get("/<path..>?<platform>", rank = 6)]
+pub async fn get_image_filesystem(
+    user: User,
+    registry: Registry,
+    path: ImageFilesystemPath,
+    platform: Option<&str>,
+) -> ApiResponse<FilesystemResponse> {
+    let client = &registry.client;
+    let ImageFilesystemPath(path) = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let configs = resolve_configs(client, &path.repository, &path.reference, platform).await?;
+
+    let mut filesystems = Vec::new();
+    for (manifest, _) in configs {
+        let entries = layers::merged_filesystem(client, &path.repository, &manifest.layers).await?;
+
+        filesystems.push(PlatformFilesystem {
+            digest: manifest.digest,
+            platform: manifest.platform,
+            files_count: entries.len(),
+            tree: layers::build_tree(entries),
+        });
+    }
+
+    ApiAnswer::success(FilesystemResponse {
+        image: path.repository,
+        reference: path.reference,
+        filesystems,
+    })
+}
+
+#[get("/<path..>?<platform>&<format>", rank = 7)]
+pub async fn get_image_sbom(
+    user: User,
+    registry: Registry,
+    inventories: &State<InventoryCache>,
+    settings: &State<Settings>,
+    path: ImageSbomPath,
+    platform: Option<&str>,
+    format: Option<SbomFormat>,
+) -> ApiResponse<SbomResponse> {
+    let client = &registry.client;
+    let ImageSbomPath(path) = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let config = settings.config();
+    let format = format.unwrap_or_default();
+    let configs = resolve_configs(client, &path.repository, &path.reference, platform).await?;
+
+    let mut sboms = Vec::new();
+    for (manifest, _) in configs {
+        let inventory = inventories.get(client, &path.repository, &manifest).await?;
+        let subject = SbomSubject {
+            image: &path.repository,
+            digest: &manifest.digest,
+            platform: manifest.platform.as_ref(),
+            tool_version: &config.version,
+        };
+
+        sboms.push(PlatformSbom {
+            packages_count: inventory.packages.len(),
+            sbom: sbom::render(format, &subject, &inventory),
+            digest: manifest.digest,
+            platform: manifest.platform,
+        });
+    }
+
+    ApiAnswer::success(SbomResponse {
+        image: path.repository,
+        reference: path.reference,
+        format,
+        sboms,
+    })
+}
+
+#[get("/<path..>?<platform>", rank = 8)]
+pub async fn get_image_vulnerabilities(
+    user: User,
+    registry: Registry,
+    inventories: &State<InventoryCache>,
+    database: &State<AdvisoryDatabase>,
+    path: ImageVulnerabilitiesPath,
+    platform: Option<&str>,
+) -> ApiResponse<VulnerabilityReportResponse> {
+    let client = &registry.client;
+    let ImageVulnerabilitiesPath(path) = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let advisories = database.advisories().await.ok_or_else(|| {
+        ApiError::new(
+            Status::ServiceUnavailable,
+            ApiErrorKind::NotConfigured,
+            "No advisory database configured, set HARBUI_ADVISORIES_DIR",
+        )
+    })?;
+    let advisories_count = advisories.count();
+    let scanner = Scanner {
+        inventories,
+        advisories,
+    };
+    let configs = resolve_configs(client, &path.repository, &path.reference, platform).await?;
+
+    let mut reports = Vec::new();
+    for (manifest, _) in configs {
+        let vulnerabilities = scanner.scan(client, &path.repository, &manifest).await?;
+
+        reports.push(PlatformVulnerabilities {
+            digest: manifest.digest,
+            platform: manifest.platform,
+            counts: SeverityCounts::of(&vulnerabilities),
+            vulnerabilities,
+        });
+    }
+
+    ApiAnswer::success(VulnerabilityReportResponse {
+        image: path.repository,
+        reference: path.reference,
+        advisories_count,
+        reports,
+    })
+}
+
+#[get("/diff?<from>&<to>&<platforms..>")]
+pub async fn get_image_diff(
+    user: User,
+    registry: Registry,
+    inventories: &State<InventoryCache>,
+    from: ImagePath,
+    to: ImagePath,
+    platforms: DiffPlatforms,
+) -> ApiResponse<ImageDiffResponse> {
+    let from_platform = platforms.from_platform.or(platforms.platform.clone());
+    let to_platform = platforms.to_platform.or(platforms.platform);
+    user.ensure(&registry.id, &from.repository, Role::Viewer)?;
+    user.ensure(&registry.id, &to.repository, Role::Viewer)?;
+    let client = &registry.client;
+    let (from_images, to_images) = try_join(
+        resolve_configs(client, &from.repository, &from.reference, from_platform.as_deref()),
+        resolve_configs(client, &to.repository, &to.reference, to_platform.as_deref()),
+    )
+    .await?;
+
+    let pairs = diff::pair_platforms(&from_images, &to_images);
+    let paired = |image: &ResolvedImage| {
+        pairs
+            .iter()
+            .any(|(f, t)| std::ptr::eq(*f, image) || std::ptr::eq(*t, image))
+    };
+
+    ApiAnswer::success(ImageDiffResponse {
+        from: from.to_string(),
+        to: to.to_string(),
+        platforms: pairs
+            .iter()
+            .map(|(f, t)| {
+                // Extracting inventories downloads every layer, left to the sbom route
+                let from = inventories.cached(&f.0.config.digest);
+                let to = inventories.cached(&t.0.config.digest);
+                diff::diff_images(f, t, from.as_deref().zip(to.as_deref()))
+            })
+            .collect(),
+        only_in_from: from_images
+            .iter()
+            .filter(|i| !paired(i))
+            .map(diff::platform_key)
+            .collect(),
+        only_in_to: to_images
+            .iter()
+            .filter(|i| !paired(i))
+            .map(diff::platform_key)
+            .collect(),
+    })
+}
+
+#[get("/<path..>", rank = 9)]
+pub async fn get_delete_impact(
+    user: User,
+    registry: Registry,
+    path: ImageImpactPath,
+) -> ApiResponse<DeleteImpactResponse> {
+    let ImageImpactPath(path) = path;
+    user.ensure(&registry.id, &path.repository, Role::Viewer)?;
+    let (digest, tags) = tags_sharing_digest(&registry.client, &path).await?;
+    let siblings = tags.iter().filter(|tag| **tag != path.reference).cloned().collect();
+
+    ApiAnswer::success(DeleteImpactResponse {
+        repository: path.repository,
+        reference: path.reference,
+        digest,
+        tags,
+        siblings,
+    })
+}
+
+/// Tags are removed alone where the registry supports it. Otherwise the manifest is deleted,
+/// which takes every tag pointing at it, so that needs `force` when other tags would go too.
+#[delete("/<path..>?<force>")]
+pub async fn delete_image(
+    user: User,
+    registry: Allowed<Deleting>,
+    path: ImagePath,
+    force: Option<bool>,
+) -> ApiResponse<DeleteResponse> {
+    user.ensure(&registry.id, &path.repository, Role::Maintainer)?;
+    let client = &registry.client;
+    let is_tag = !path.reference.contains(':');
+
+    if is_tag {
+        let digest = client
+            .head_manifest(&path.repository, &path.reference)
+            .await?
+            .ok_or_else(|| ApiError::new(Status::NotFound, ApiErrorKind::NotFound, "Image not found"))?;
+        if client.delete_tag(&path.repository, &path.reference).await? {
+            info!("Deleted tag {}", path);
+            return ApiAnswer::success(DeleteResponse {
+                digest,
+                deleted_tags: vec![path.reference],
+                manifest_deleted: false,
+            });
         }
-        Manifest::OCIImageManifestV1(m) => {
-            let mut configs: HashMap<String, (String, u64)> = HashMap::new();
-            let config_digest = m.config.digest.clone();
-            let manifest_digest = image_manifest.digest.unwrap();
+    }
+
+    let (digest, tags) = tags_sharing_digest(client, &path).await?;
+    let siblings: Vec<&String> = tags.iter().filter(|tag| **tag != path.reference).collect();
+    if !siblings.is_empty() && !force.unwrap_or(false) {
+        let siblings = siblings.iter().map(|tag| tag.as_str()).collect::<Vec<_>>().join(", ");
+        return Err(ApiError::new(
+            Status::Conflict,
+            ApiErrorKind::Conflict,
+            &format!(
+                "Deleting {}@{} also removes {}, pass force=true to delete them all",
+                path.repository, digest, siblings
+            ),
+        ));
+    }
+
+    client.delete_manifest(&path.repository, &digest).await?;
+    info!("Deleted {}@{} with tags {:?}", path.repository, digest, tags);
+
+    ApiAnswer::success(DeleteResponse {
+        digest,
+        deleted_tags: tags,
+        manifest_deleted: true,
+    })
+}
+
+/// The digest the image resolves to and every tag of the repository pointing at it.
+async fn tags_sharing_digest(client: &RegistryClient, path: &ImagePath) -> Result<(String, Vec<String>), ApiError> {
+    let digest = client
+        .head_manifest(&path.repository, &path.reference)
+        .await?
+        .ok_or_else(|| ApiError::new(Status::NotFound, ApiErrorKind::NotFound, "Image not found"))?;
+    let tags = tag_digests(client, &path.repository)
+        .await?
+        .into_iter()
+        .filter(|(_, tag_digest)| *tag_digest == digest)
+        .map(|(tag, _)| tag)
+        .collect();
 
-            configs.insert(config_digest, (manifest_digest, m.get_total_size()));
-            get_manifests(client, &configs, &image).await
+    Ok((digest, tags))
+}
+
+/// Images listed and matched by the filter are deleted together, see [`bulk::delete`].
+#[post("/bulk/delete", data = "<request>")]
+pub async fn bulk_delete(
+    user: User,
+    registry: Registry,
+    request: Json<BulkDeleteRequest>,
+) -> ApiResponse<BulkDeleteReport> {
+    let client = &registry.client;
+    let request = request.into_inner();
+    let invalid = |message: &str| ApiError::new(Status::UnprocessableEntity, ApiErrorKind::Unprocessable, message);
+
+    if !request.dry_run {
+        ensure_allowed(&registry, Operation::Delete)?;
+    }
+    if request.images.is_empty() && request.filter.is_none() {
+        return Err(invalid("Give images, a filter or both"));
+    }
+    if request.images.len() > MAX_BULK_IMAGES {
+        return Err(invalid(&format!("At most {} images per request", MAX_BULK_IMAGES)));
+    }
+    if let Some(image) = request.images.iter().find(|image| {
+        !is_valid_repository_name(&image.repository)
+            || !(is_valid_tag(&image.reference) || is_valid_digest(&image.reference))
+    }) {
+        return Err(invalid(&format!(
+            "Invalid image {}:{}",
+            image.repository, image.reference
+        )));
+    }
+    for image in &request.images {
+        user.ensure(&registry.id, &image.repository, Role::Maintainer)?;
+    }
+
+    let mut images: Vec<(String, String)> = request
+        .images
+        .into_iter()
+        .map(|image| (image.repository, image.reference))
+        .collect();
+    if let Some(filter) = request.filter {
+        if filter.repositories.is_empty() {
+            return Err(invalid("The filter needs at least one repository pattern"));
+        }
+        if let Some(pattern) = filter.repositories.iter().find(|p| !is_valid_repository_pattern(p)) {
+            return Err(invalid(&format!("Invalid repository pattern {}", pattern)));
         }
+        let selection = Selection {
+            repositories: filter.repositories,
+            tags: match filter.tags.as_deref().map(Regex::new).transpose() {
+                Ok(tags) => tags,
+                Err(e) => return Err(invalid(&format!("Invalid tag pattern: {}", e))),
+            },
+            created_before: filter
+                .older_than_days
+                .map(|days| OffsetDateTime::now_utc() - time::Duration::days(days as i64)),
+        };
+        // Filters only reach what the user may delete
+        images.extend(
+            selection
+                .images(client)
+                .await?
+                .into_iter()
+                .filter(|(repository, _)| user.allows(&registry.id, repository, Role::Maintainer)),
+        );
+        if images.len() > MAX_BULK_IMAGES {
+            return Err(invalid(&format!(
+                "The request selects {} images, at most {} can be deleted at once",
+                images.len(),
+                MAX_BULK_IMAGES
+            )));
+        }
+    }
+
+    ApiAnswer::success(bulk::delete(client, images, request.dry_run, request.force).await)
+}
+
+#[post("/<path..>", data = "<request>", rank = 2)]
+pub async fn retag_image(
+    user: User,
+    registry: Allowed<Retagging>,
+    path: ImageRetagPath,
+    request: Json<RetagRequest>,
+) -> ApiResponse<CopyResponse> {
+    let ImageRetagPath(source) = path;
+    user.ensure(&registry.id, &source.repository, Role::Developer)?;
+    let target = ImagePath {
+        repository: source.repository.clone(),
+        reference: request.into_inner().tag,
     };
 
-    ApiAnswer::success(ImageManifestResponse {
-        image,
-        tag: tag.to_owned(),
-        manifests,
-    })
+    copy(Copier::within(&registry.client), source, target).await
 }
 
-#[delete("/<user>/<name>/<tag>")]
-pub async fn delete_image(client: &State<RegistryClient>, user: &str, name: &str, tag: &str) -> ApiResponse<String> {
-    let image = format!("{}/{}", user, name);
-    let image_manifest = match client.get_manifest(&image, tag).await {
-        Ok(m) => m,
-        Err(err) => return Err(ApiError::not_found(&err.message)),
+#[post("/<path..>", data = "<request>", rank = 3)]
+pub async fn promote_image(
+    user: User,
+    registries: &State<Registries>,
+    registry: Registry,
+    path: ImagePromotePath,
+    request: Json<PromoteRequest>,
+) -> ApiResponse<CopyResponse> {
+    let ImagePromotePath(source) = path;
+    user.ensure(&registry.id, &source.repository, Role::Viewer)?;
+    let request = request.into_inner();
+    let destination = match &request.registry {
+        Some(id) => registries.get(id).ok_or_else(|| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                ApiErrorKind::Unprocessable,
+                "Unknown target registry",
+            )
+        })?,
+        None => registry.entry(),
+    };
+    ensure_allowed(&destination, Operation::Push)?;
+
+    if !is_valid_repository_name(&request.repository) {
+        return Err(ApiError::new(
+            Status::UnprocessableEntity,
+            ApiErrorKind::Unprocessable,
+            "Invalid target repository",
+        ));
+    }
+    user.ensure(&destination.id, &request.repository, Role::Developer)?;
+
+    let target = ImagePath {
+        repository: request.repository,
+        reference: request.tag.unwrap_or_else(|| source.reference.clone()),
+    };
+    let copier = if destination.id == registry.id {
+        Copier::within(&registry.client)
+    } else {
+        Copier::between(&registry.client, &destination.client)
     };
 
-    if let Err(err) = client
-        .delete_manifest(&image, &image_manifest.digest.unwrap_or_default())
-        .await
-    {
-        return Err(ApiError::unprocessable(&err.message));
+    copy(copier, source, target).await
+}
+
+async fn copy(copier: Copier<'_>, source: ImagePath, target: ImagePath) -> ApiResponse<CopyResponse> {
+    // A source digest may be pushed under its own digest, anything else must be a tag
+    if target.reference != source.reference && !is_valid_tag(&target.reference) {
+        return Err(ApiError::new(
+            Status::UnprocessableEntity,
+            ApiErrorKind::Unprocessable,
+            "Invalid target tag",
+        ));
     }
 
+    let report = copier
+        .copy(
+            &source.repository,
+            &source.reference,
+            &target.repository,
+            &target.reference,
+        )
+        .await?;
+    info!("Copied {} to {}: {:?}", source, target, report);
+
+    ApiAnswer::success(CopyResponse {
+        source: source.to_string(),
+        target: target.to_string(),
+        report,
+    })
+}
+
+#[get("/replication/rules")]
+pub async fn get_replication_rules(user: User, replication: &State<Replication>) -> ApiResponse<Vec<ReplicationRule>> {
+    user.ensure_admin()?;
+    ApiAnswer::success(replication.rules())
+}
+
+#[post("/replication/rules", data = "<rule>")]
+pub async fn create_replication_rule(
+    user: User,
+    registries: &State<Registries>,
+    replication: &State<Replication>,
+    rule: Json<ReplicationRule>,
+) -> ApiResponse<ReplicationRule> {
+    user.ensure_admin()?;
+    let rule = validate_rule(registries, rule.into_inner())?;
+
+    ApiAnswer::success(replication.create_rule(rule)?)
+}
+
+#[put("/replication/rules/<id>", data = "<rule>")]
+pub async fn update_replication_rule(
+    user: User,
+    registries: &State<Registries>,
+    replication: &State<Replication>,
+    id: &str,
+    rule: Json<ReplicationRule>,
+) -> ApiResponse<ReplicationRule> {
+    user.ensure_admin()?;
+    let rule = validate_rule(registries, rule.into_inner())?;
+
+    ApiAnswer::success(replication.update_rule(id, rule)?)
+}
+
+#[delete("/replication/rules/<id>")]
+pub async fn delete_replication_rule(user: User, replication: &State<Replication>, id: &str) -> ApiResponse<String> {
+    user.ensure_admin()?;
+    replication.delete_rule(id)?;
+
     ApiAnswer::success("{}".to_string())
 }
+
+#[post("/replication/rules/<id>/run")]
+pub async fn run_replication_rule(
+    user: User,
+    registries: &State<Registries>,
+    replication: &State<Replication>,
+    id: &str,
+) -> ApiResponse<ReplicationJob> {
+    user.ensure_admin()?;
+    if let Some(rule) = replication.rules().into_iter().find(|rule| rule.id == id) {
+        ensure_allowed(&*local_registry(registries, &rule)?, Operation::Push)?;
+    }
+
+    ApiAnswer::success(replication.run(id)?)
+}
+
+#[get("/replication/jobs")]
+pub async fn get_replication_jobs(user: User, replication: &State<Replication>) -> ApiResponse<Vec<ReplicationJob>> {
+    user.ensure_admin()?;
+    ApiAnswer::success(replication.jobs())
+}
+
+#[get("/replication/jobs/<id>")]
+pub async fn get_replication_job(
+    user: User,
+    replication: &State<Replication>,
+    id: &str,
+) -> ApiResponse<ReplicationJob> {
+    user.ensure_admin()?;
+    let job = replication
+        .job(id)
+        .ok_or_else(|| ApiError::new(Status::NotFound, ApiErrorKind::NotFound, "No such replication job"))?;
+
+    ApiAnswer::success(job)
+}
+
+#[get("/retention/policies")]
+pub async fn get_retention_policies(user: User, retention: &State<Retention>) -> ApiResponse<Vec<RetentionPolicy>> {
+    user.ensure_admin()?;
+    ApiAnswer::success(retention.policies())
+}
+
+#[post("/retention/policies", data = "<policy>")]
+pub async fn create_retention_policy(
+    user: User,
+    registries: &State<Registries>,
+    retention: &State<Retention>,
+    policy: Json<RetentionPolicy>,
+) -> ApiResponse<RetentionPolicy> {
+    user.ensure_admin()?;
+    let policy = validate_policy(registries, policy.into_inner())?;
+
+    ApiAnswer::success(retention.create_policy(policy)?)
+}
+
+#[put("/retention/policies/<id>", data = "<policy>")]
+pub async fn update_retention_policy(
+    user: User,
+    registries: &State<Registries>,
+    retention: &State<Retention>,
+    id: &str,
+    policy: Json<RetentionPolicy>,
+) -> ApiResponse<RetentionPolicy> {
+    user.ensure_admin()?;
+    let policy = validate_policy(registries, policy.into_inner())?;
+
+    ApiAnswer::success(retention.update_policy(id, policy)?)
+}
+
+#[delete("/retention/policies/<id>")]
+pub async fn delete_retention_policy(user: User, retention: &State<Retention>, id: &str) -> ApiResponse<String> {
+    user.ensure_admin()?;
+    retention.delete_policy(id)?;
+
+    ApiAnswer::success("{}".to_string())
+}
+
+#[post("/retention/policies/<id>/run")]
+pub async fn run_retention_policy(user: User, retention: &State<Retention>, id: &str) -> ApiResponse<RetentionRun> {
+    user.ensure_admin()?;
+    ApiAnswer::success(retention.run(id)?)
+}
+
+#[post("/retention/policies/<id>/dry-run")]
+pub async fn dry_run_retention_policy(user: User, retention: &State<Retention>, id: &str) -> ApiResponse<RetentionRun> {
+    user.ensure_admin()?;
+    ApiAnswer::success(retention.dry_run(id)?)
+}
+
+#[get("/retention/runs")]
+pub async fn get_retention_runs(user: User, retention: &State<Retention>) -> ApiResponse<Vec<RetentionRun>> {
+    user.ensure_admin()?;
+    ApiAnswer::success(retention.runs())
+}
+
+#[get("/retention/runs/<id>")]
+pub async fn get_retention_run(user: User, retention: &State<Retention>, id: &str) -> ApiResponse<RetentionRun> {
+    user.ensure_admin()?;
+    let run = retention
+        .run_report(id)
+        .ok_or_else(|| ApiError::new(Status::NotFound, ApiErrorKind::NotFound, "No such retention run"))?;
+
+    ApiAnswer::success(run)
+}
+
+/// Replication writes images, so rules need the push switch of their harbui registry
+/// whichever the direction.
+fn validate_rule(registries: &Registries, rule: ReplicationRule) -> Result<ReplicationRule, ApiError> {
+    let invalid = |message: &str| ApiError::new(Status::UnprocessableEntity, ApiErrorKind::Unprocessable, message);
+    if rule.name.trim().is_empty() {
+        return Err(invalid("Rule name is required"));
+    }
+    ensure_allowed(&*local_registry(registries, &rule)?, Operation::Push)?;
+    if rule.remote.host.trim().is_empty() {
+        return Err(invalid("Remote registry host is required"));
+    }
+    if let Err(e) = validate_host(&rule.remote.host) {
+        return Err(invalid(&format!("Invalid remote registry host: {}", e)));
+    }
+    if rule.repositories.is_empty() {
+        return Err(invalid("At least one repository pattern is required"));
+    }
+    if let Some(namespace) = &rule.destination_namespace {
+        if !is_valid_repository_name(namespace) {
+            return Err(invalid("Invalid destination namespace"));
+        }
+    }
+    if rule.interval_minutes == Some(0) {
+        return Err(invalid("Interval must be at least a minute"));
+    }
+
+    Ok(rule)
+}
+
+fn local_registry(registries: &Registries, rule: &ReplicationRule) -> Result<Arc<RegistryEntry>, ApiError> {
+    match &rule.registry {
+        Some(id) => registries.get(id).ok_or_else(|| {
+            ApiError::new(
+                Status::UnprocessableEntity,
+                ApiErrorKind::Unprocessable,
+                "Unknown registry",
+            )
+        }),
+        None => Ok(registries.default_registry()),
+    }
+}
+
+/// Dry-run policies can be saved anywhere, deleting ones only for registries that allow it.
+fn validate_policy(registries: &Registries, policy: RetentionPolicy) -> Result<RetentionPolicy, ApiError> {
+    let invalid = |message: &str| ApiError::new(Status::UnprocessableEntity, ApiErrorKind::Unprocessable, message);
+    if policy.name.trim().is_empty() {
+        return Err(invalid("Policy name is required"));
+    }
+    let registry = match &policy.registry {
+        Some(id) => registries.get(id).ok_or_else(|| invalid("Unknown registry"))?,
+        None => registries.default_registry(),
+    };
+    if policy.repositories.is_empty() {
+        return Err(invalid("At least one repository pattern is required"));
+    }
+    if policy.keep_last.is_none() && policy.older_than_days.is_none() {
+        return Err(invalid("Set keep_last, older_than_days or both"));
+    }
+    if let Some(pattern) = policy.keep_tags.iter().find(|pattern| Regex::new(pattern).is_err()) {
+        return Err(invalid(&format!("Invalid tag pattern {}", pattern)));
+    }
+    if policy.interval_minutes == Some(0) {
+        return Err(invalid("Interval must be at least a minute"));
+    }
+    if !policy.dry_run {
+        ensure_allowed(&registry, Operation::Delete)?;
+    }
+
+    Ok(policy)
+}
@@ -1,10 +1,24 @@
+use crate::routes::permission::RefusedRequest;
+use crate::routes::types::ApiError;
 use anyhow::Result;
 use rocket::fs::NamedFile;
-use serde_json::json;
+use rocket::http::Status;
+use rocket::Request;
 use std::path::PathBuf;
 
 pub mod api;
+mod paths;
+mod permission;
+mod registry;
+mod request_id;
+pub mod session;
+pub mod tokens;
 mod types;
+mod user;
+
+pub use paths::is_valid_repository_pattern;
+pub use registry::RegistryScopeFairing;
+pub use request_id::RequestIdFairing;
 
 #[get("/<_path..>")]
 pub async fn image(_path: PathBuf) -> Result<NamedFile, std::io::Error> {
@@ -12,6 +26,9 @@ pub async fn image(_path: PathBuf) -> Result<NamedFile, std::io::Error> {
 }
 
 #[catch(default)]
-pub fn error_handler() -> String {
-    json!({ "code": "UNKNOWN", "message": "Unknown error occurred"}).to_string()
+pub fn error_handler(status: Status, req: &Request) -> ApiError {
+    match &req.local_cache(RefusedRequest::default).0 {
+        Some(refused) if refused.status == status => refused.clone(),
+        _ => ApiError::from_status(status),
+    }
 }
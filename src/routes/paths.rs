@@ -0,0 +1,304 @@
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::http::uri::{fmt::Path, Segments};
+use rocket::request::FromSegments;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct InvalidPath;
+
+/// `<repository..>/tags`, e.g. `/api/team/backend/api/tags`.
+#[derive(Clone, Debug)]
+pub struct RepositoryPath {
+    pub repository: String,
+}
+
+impl<'r> FromSegments<'r> for RepositoryPath {
+    type Error = InvalidPath;
+
+    fn from_segments(segments: Segments<'r, Path>) -> Result<Self, Self::Error> {
+        let segments: Vec<&str> = segments.collect();
+        let (last, repository) = segments.split_last().ok_or(InvalidPath)?;
+
+        if *last != "tags" || !is_valid_repository(repository) {
+            return Err(InvalidPath);
+        }
+
+        Ok(Self {
+            repository: repository.join("/"),
+        })
+    }
+}
+
+/// `<repository..>:<tag>` or `<repository..>@<digest>`,
+/// e.g. `/api/team/backend/api:v1` or `/api/alpine@sha256:...`.
+/// Repository components can't contain `:` or `@`, so only the last segment may hold them.
+#[derive(Clone, Debug)]
+pub struct ImagePath {
+    pub repository: String,
+    pub reference: String,
+}
+
+impl ImagePath {
+    fn parse(segments: &[&str]) -> Result<Self, InvalidPath> {
+        let (last, namespace) = segments.split_last().ok_or(InvalidPath)?;
+        let (name, reference) = match last.split_once('@') {
+            Some((name, digest)) if is_valid_digest(digest) => (name, digest),
+            Some(_) => return Err(InvalidPath),
+            None => last.split_once(':').ok_or(InvalidPath)?,
+        };
+
+        let mut repository = namespace.to_vec();
+        repository.push(name);
+
+        // Segments come percent-decoded, an unchecked `%2F` or `%3F` would reach other repositories
+        if !is_valid_repository(&repository) || !(is_valid_tag(reference) || is_valid_digest(reference)) {
+            return Err(InvalidPath);
+        }
+
+        Ok(Self {
+            repository: repository.join("/"),
+            reference: reference.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ImagePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let separator = if self.reference.contains(':') { '@' } else { ':' };
+
+        write!(f, "{}{}{}", self.repository, separator, self.reference)
+    }
+}
+
+impl<'r> FromSegments<'r> for ImagePath {
+    type Error = InvalidPath;
+
+    fn from_segments(segments: Segments<'r, Path>) -> Result<Self, Self::Error> {
+        Self::parse(&segments.collect::<Vec<_>>())
+    }
+}
+
+/// `?from=team/app:v1` style query values, same syntax as the path form.
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for ImagePath {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let segments: Vec<&str> = field.value.split('/').collect();
+
+        ImagePath::parse(&segments).map_err(|_| form::Error::validation("invalid image reference").into())
+    }
+}
+
+/// Declares a guard for `<image path>/<static suffix..>`, e.g. `/api/alpine:3.19/config`.
+macro_rules! image_subpath {
+    ($(#[$meta:meta])* $name:ident, [$($segment:literal),+]) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug)]
+        pub struct $name(pub ImagePath);
+
+        impl<'r> FromSegments<'r> for $name {
+            type Error = InvalidPath;
+
+            fn from_segments(segments: Segments<'r, Path>) -> Result<Self, Self::Error> {
+                let segments: Vec<&str> = segments.collect();
+                let image = segments
+                    .strip_suffix(&[$($segment),+])
+                    .ok_or(InvalidPath)?;
+
+                ImagePath::parse(image).map(Self)
+            }
+        }
+    };
+}
+
+image_subpath!(
+    /// `<image>/config`
+    ImageConfigPath,
+    ["config"]
+);
+
+/// `<image>/layers/<digest>/tree`, e.g. `/api/alpine:3.19/layers/sha256:.../tree`.
+#[derive(Clone, Debug)]
+pub struct LayerTreePath {
+    pub image: ImagePath,
+    pub digest: String,
+}
+
+impl<'r> FromSegments<'r> for LayerTreePath {
+    type Error = InvalidPath;
+
+    fn from_segments(segments: Segments<'r, Path>) -> Result<Self, Self::Error> {
+        let segments: Vec<&str> = segments.collect();
+        let [image @ .., "layers", digest, "tree"] = segments.as_slice() else {
+            return Err(InvalidPath);
+        };
+
+        if !is_valid_digest(digest) {
+            return Err(InvalidPath);
+        }
+
+        Ok(Self {
+            image: ImagePath::parse(image)?,
+            digest: digest.to_string(),
+        })
+    }
+}
+
+/// Repository names as given in request bodies, e.g. `team/backend/api`.
+pub fn is_valid_repository_name(name: &str) -> bool {
+    is_valid_repository(&name.split('/').collect::<Vec<_>>())
+}
+
+/// Repository names where `*` and `?` may stand in for characters, e.g. `team/*`. Patterns
+/// like `team/../other` would pass grants on `team/*` while reaching another namespace.
+pub fn is_valid_repository_pattern(pattern: &str) -> bool {
+    is_valid_repository_name(&pattern.replace(['*', '?'], "x"))
+}
+
+/// `[A-Za-z0-9_][A-Za-z0-9._-]{0,127}`, as the distribution spec defines tags.
+pub fn is_valid_tag(tag: &str) -> bool {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    tag.len() <= 128 && tag.starts_with(is_word) && tag.chars().all(|c| is_word(c) || matches!(c, '.' | '-'))
+}
+
+fn is_valid_repository(components: &[&str]) -> bool {
+    !components.is_empty() && components.iter().all(|c| is_valid_component(c))
+}
+
+fn is_valid_component(component: &str) -> bool {
+    let is_alnum = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+
+    component.starts_with(is_alnum)
+        && component.ends_with(is_alnum)
+        && component.chars().all(|c| is_alnum(c) || matches!(c, '.' | '_' | '-'))
+}
+
+/// `sha256:<64 hex>` or `sha512:<128 hex>`, the algorithms the distribution spec registers.
+pub fn is_valid_digest(digest: &str) -> bool {
+    let Some((algorithm, hex)) = digest.split_once(':') else {
+        return false;
+    };
+    let length = match algorithm {
+        "sha256" => 64,
+        "sha512" => 128,
+        _ => return false,
+    };
+
+    hex.len() == length && hex.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+image_subpath!(
+    /// `<image>/dockerfile`
+    ImageDockerfilePath,
+    ["dockerfile"]
+);
+
+image_subpath!(
+    /// `<image>/filesystem`
+    ImageFilesystemPath,
+    ["filesystem"]
+);
+
+image_subpath!(
+    /// `<image>/sbom`
+    ImageSbomPath,
+    ["sbom"]
+);
+
+image_subpath!(
+    /// `<image>/vulnerabilities`
+    ImageVulnerabilitiesPath,
+    ["vulnerabilities"]
+);
+
+image_subpath!(
+    /// `<image>/retag`
+    ImageRetagPath,
+    ["retag"]
+);
+
+image_subpath!(
+    /// `<image>/promote`
+    ImagePromotePath,
+    ["promote"]
+);
+
+image_subpath!(
+    /// `<image>/impact`
+    ImageImpactPath,
+    ["impact"]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:2b1a9e4c8f0d3e5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a";
+
+    #[test]
+    fn validates_digests() {
+        assert!(is_valid_digest(DIGEST));
+        assert!(is_valid_digest(&format!("sha512:{}", "ab".repeat(64))));
+
+        for digest in [
+            "sha256:abc",
+            "md5:d41d8cd98f00b204e9800998ecf8427e",
+            &DIGEST.to_uppercase(),
+            &DIGEST.replace("sha256", "sha512"),
+            &DIGEST.replace(':', ""),
+            ":",
+        ] {
+            assert!(!is_valid_digest(digest), "{}", digest);
+        }
+    }
+
+    #[test]
+    fn parses_nested_image_paths() {
+        let path = ImagePath::parse(&["team", "backend", "api:v1"]).unwrap();
+        assert_eq!(
+            (path.repository.as_str(), path.reference.as_str()),
+            ("team/backend/api", "v1")
+        );
+
+        let by_digest = format!("alpine@{}", DIGEST);
+        let path = ImagePath::parse(&[&by_digest]).unwrap();
+        assert_eq!((path.repository.as_str(), path.reference.as_str()), ("alpine", DIGEST));
+
+        assert!(ImagePath::parse(&["alpine@sha256:abc"]).is_err());
+        assert!(ImagePath::parse(&["Team", "api:v1"]).is_err());
+        assert!(ImagePath::parse(&["team", "api"]).is_err());
+    }
+
+    #[test]
+    fn rejects_tags_leaving_the_repository() {
+        for tag in [
+            "alpine:x/../../../other/manifests/latest",
+            "alpine:..",
+            "alpine:v1?digest=1",
+            "alpine:",
+            "alpine:-v1",
+        ] {
+            assert!(ImagePath::parse(&[tag]).is_err(), "{}", tag);
+        }
+        assert!(ImagePath::parse(&["alpine:3.19_rc-1"]).is_ok());
+    }
+
+    #[test]
+    fn validates_repository_patterns() {
+        for pattern in ["*", "team/*", "team/*/api", "team/ap?", "team-*", "library/alpine"] {
+            assert!(is_valid_repository_pattern(pattern), "{}", pattern);
+        }
+        for pattern in [
+            "team/../other",
+            "team/*/../../other",
+            "team/./app",
+            "/team",
+            "team/",
+            "Team/*",
+            "team//*",
+            "",
+        ] {
+            assert!(!is_valid_repository_pattern(pattern), "{}", pattern);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::registries::{registry_prefix, RegistryEntry, DEFAULT_REGISTRY};
+use crate::routes::registry::Registry;
+use crate::routes::types::{ApiError, ApiErrorKind};
+use crate::types::Operation;
+use rocket::http::Status;
+use rocket::outcome::try_outcome;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+/// Names the operation a route performs, see [`Allowed`].
+pub trait Guarded {
+    const OPERATION: Operation;
+}
+
+pub struct Deleting;
+
+impl Guarded for Deleting {
+    const OPERATION: Operation = Operation::Delete;
+}
+
+pub struct Retagging;
+
+impl Guarded for Retagging {
+    const OPERATION: Operation = Operation::Retag;
+}
+
+/// The registry of the request, when it allows the operation. Otherwise the request is
+/// refused with a `403` and the `OPERATION_DISABLED` code before the route runs.
+pub struct Allowed<O: Guarded> {
+    registry: Registry,
+    operation: PhantomData<O>,
+}
+
+impl<O: Guarded> Deref for Allowed<O> {
+    type Target = Registry;
+
+    fn deref(&self) -> &Self::Target {
+        &self.registry
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, O: Guarded> FromRequest<'r> for Allowed<O> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let registry = try_outcome!(req.guard::<Registry>().await);
+
+        match ensure_allowed(&registry, O::OPERATION) {
+            Ok(()) => Outcome::Success(Allowed {
+                registry,
+                operation: PhantomData,
+            }),
+            Err(e) => refuse(req, e),
+        }
+    }
+}
+
+/// The error a guard refused the request with, for the catcher to answer with.
+#[derive(Default)]
+pub struct RefusedRequest(pub Option<ApiError>);
+
+/// Fails a guard with `error`, answered as is by the catcher instead of a bare status.
+pub fn refuse<T>(req: &Request<'_>, error: ApiError) -> Outcome<T, ()> {
+    let status = error.status;
+    req.local_cache(|| RefusedRequest(Some(error)));
+    Outcome::Error((status, ()))
+}
+
+/// For checks depending on the request body, e.g. the destination of a promotion.
+pub fn ensure_allowed(registry: &RegistryEntry, operation: Operation) -> Result<(), ApiError> {
+    if registry.permissions.allows(operation) {
+        return Ok(());
+    }
+
+    let variable = if registry.id == DEFAULT_REGISTRY {
+        format!("HARBUI_{}", operation.switch())
+    } else {
+        format!("{}{}", registry_prefix(&registry.id), operation.switch())
+    };
+    Err(ApiError::new(
+        Status::Forbidden,
+        ApiErrorKind::OperationDisabled,
+        &format!(
+            "{} is disabled for registry {}, set {}",
+            operation.describe(),
+            registry.id,
+            variable
+        ),
+    ))
+}
@@ -0,0 +1,153 @@
+use crate::registries::{Registries, RegistryEntry};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// `-` can't start a repository name, so no repository of the default registry is shadowed.
+const SCOPE_PREFIX: &str = "/api/-/registries/";
+
+/// Registry id taken from the request path, `None` for the default registry.
+#[derive(Clone, Debug, Default)]
+struct RegistryScope(Option<String>);
+
+/// Serves `/api/-/registries/<id>/<route>` as `/api/<route>` scoped to that registry,
+/// so every API route works for every registry without declaring it twice.
+pub struct RegistryScopeFairing;
+
+#[rocket::async_trait]
+impl Fairing for RegistryScopeFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Registry scope",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let path = req.uri().path().as_str();
+        let Some((id, rest)) = path
+            .strip_prefix(SCOPE_PREFIX)
+            .and_then(|scoped| scoped.split_once('/'))
+        else {
+            return;
+        };
+        if rest.is_empty() {
+            return;
+        }
+
+        let query = req.uri().query().map(|q| format!("?{}", q)).unwrap_or_default();
+        let id = id.to_string();
+        match Origin::parse_owned(format!("/api/{}{}", rest, query)) {
+            Ok(uri) => {
+                req.set_uri(uri);
+                req.local_cache(|| RegistryScope(Some(id)));
+            }
+            Err(e) => warn!("Can't scope {} to registry {}: {}", path, id, e),
+        }
+    }
+}
+
+/// The registry a request is scoped to, see [`RegistryScopeFairing`].
+pub struct Registry {
+    entry: Arc<RegistryEntry>,
+    scoped: bool,
+}
+
+impl Registry {
+    pub fn entry(&self) -> Arc<RegistryEntry> {
+        self.entry.clone()
+    }
+
+    /// `/api` or `/api/-/registries/<id>`, whichever the request used, for links back to the API.
+    pub fn api_base(&self) -> String {
+        if self.scoped {
+            format!("{}{}", SCOPE_PREFIX, self.entry.id)
+        } else {
+            "/api".to_string()
+        }
+    }
+}
+
+impl Deref for Registry {
+    type Target = RegistryEntry;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entry
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Registry {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(registries) = req.rocket().state::<Registries>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        let scope = req.local_cache(RegistryScope::default);
+
+        let entry = match &scope.0 {
+            Some(id) => registries.get(id),
+            None => Some(registries.default_registry()),
+        };
+
+        match entry {
+            Some(entry) => Outcome::Success(Registry {
+                entry,
+                scoped: scope.0.is_some(),
+            }),
+            None => Outcome::Error((Status::NotFound, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    struct Scope(Option<String>);
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for Scope {
+        type Error = ();
+
+        async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            Outcome::Success(Scope(req.local_cache(RegistryScope::default).0.clone()))
+        }
+    }
+
+    #[get("/<path..>")]
+    fn echo(path: std::path::PathBuf, scope: Scope) -> String {
+        format!("{} {:?}", path.display(), scope.0)
+    }
+
+    fn client() -> Client {
+        let rocket = rocket::build()
+            .attach(RegistryScopeFairing)
+            .mount("/api", routes![echo]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn scopes_prefixed_requests() {
+        let client = client();
+
+        let scoped = client
+            .get("/api/-/registries/staging/team/app:v1?platform=x")
+            .dispatch();
+        assert_eq!(scoped.into_string().unwrap(), r#"team/app:v1 Some("staging")"#);
+    }
+
+    #[test]
+    fn leaves_repositories_named_registries_alone() {
+        let client = client();
+
+        let unscoped = client.get("/api/registries/staging/app:v1").dispatch();
+        assert_eq!(unscoped.into_string().unwrap(), "registries/staging/app:v1 None");
+    }
+}
@@ -0,0 +1,155 @@
+use crate::auth::{Authentication, OidcError, PendingLogin, Provider, Session, SESSION_COOKIE};
+use crate::routes::types::{
+    ApiAnswer, ApiError, ApiErrorKind, ApiResponse, LoginMethodsResponse, LoginRequest, SessionResponse,
+};
+use crate::routes::user::User;
+use crate::settings::Settings;
+use rocket::http::{Cookie, CookieJar, RawStr, Status};
+use rocket::response::Redirect;
+use rocket::serde::json::Json;
+use rocket::State;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[get("/login")]
+pub async fn get_login_methods(authentication: &State<Authentication>) -> ApiResponse<LoginMethodsResponse> {
+    ApiAnswer::success(LoginMethodsResponse {
+        local: authentication.logins_enabled(),
+        oidc: authentication.oidc().is_some(),
+    })
+}
+
+#[post("/login", data = "<credentials>")]
+pub async fn login(
+    authentication: &State<Authentication>,
+    cookies: &CookieJar<'_>,
+    credentials: Json<LoginRequest>,
+) -> ApiResponse<SessionResponse> {
+    if !authentication.logins_enabled() {
+        return Err(ApiError::new(
+            Status::NotFound,
+            ApiErrorKind::NotConfigured,
+            "Logins are off, set HARBUI_USERS_FILE",
+        ));
+    }
+
+    let Some(session) = authentication.login(&credentials.username, &credentials.password).await else {
+        warn!("Failed login for {}", credentials.username);
+        return Err(ApiError::new(
+            Status::Unauthorized,
+            ApiErrorKind::InvalidCredentials,
+            "Wrong username or password",
+        ));
+    };
+    info!("{} logged in", session.user);
+    cookies.add_private(session.cookie());
+
+    ApiAnswer::success(session_response(&session))
+}
+
+/// Sends the browser to the OpenID provider, which sends it back to [`oidc_callback`].
+#[get("/oidc/login?<redirect>")]
+pub async fn oidc_login(
+    authentication: &State<Authentication>,
+    cookies: &CookieJar<'_>,
+    redirect: Option<&str>,
+) -> Result<Redirect, ApiError> {
+    let oidc = authentication.oidc().ok_or_else(|| {
+        ApiError::new(
+            Status::NotFound,
+            ApiErrorKind::NotConfigured,
+            "OpenID Connect is off, set HARBUI_OIDC_ISSUER",
+        )
+    })?;
+    // Only back into harbui, not to wherever a crafted link points
+    let redirect = redirect
+        .filter(|path| path.starts_with('/') && !path.starts_with("//") && !path.contains('\\'))
+        .unwrap_or("/");
+
+    let (url, pending) = oidc.authorization(redirect.to_string()).await?;
+    cookies.add_private(pending.cookie());
+
+    Ok(Redirect::to(url))
+}
+
+/// Failed logins end on the login page with the reason in `error`.
+#[get("/oidc/callback?<code>&<state>&<error>&<error_description>")]
+pub async fn oidc_callback(
+    authentication: &State<Authentication>,
+    settings: &State<Settings>,
+    cookies: &CookieJar<'_>,
+    code: Option<&str>,
+    state: Option<&str>,
+    error: Option<&str>,
+    error_description: Option<&str>,
+) -> Redirect {
+    let Some(oidc) = authentication.oidc() else {
+        return Redirect::to("/login");
+    };
+    let finished = async {
+        let pending = PendingLogin::take(cookies)
+            .ok_or_else(|| OidcError::Invalid("no login in progress, or it took too long".into()))?;
+        if let Some(error) = error {
+            return Err(OidcError::Denied(error_description.unwrap_or(error).to_string()));
+        }
+        if state != Some(pending.state.as_str()) {
+            return Err(OidcError::Invalid("state doesn't match".into()));
+        }
+        let code = code.ok_or_else(|| OidcError::Invalid("no code".into()))?;
+
+        Ok((oidc.finish(&pending, code).await?, pending.redirect))
+    };
+
+    match finished.await {
+        Ok((mut identity, redirect)) => {
+            let access = settings.access();
+            identity.groups.retain(|group| access.names_group(group));
+            info!(
+                "{} logged in with OpenID Connect, groups {:?}",
+                identity.user, identity.groups
+            );
+
+            let session = authentication.new_session(identity.user, identity.groups, Provider::Oidc);
+            cookies.add_private(session.cookie());
+            Redirect::to(redirect)
+        }
+        Err(e) => {
+            warn!("OpenID Connect login failed: {}", e);
+            Redirect::to(format!("/login?error={}", RawStr::new(&e.to_string()).percent_encode()))
+        }
+    }
+}
+
+#[post("/logout")]
+pub async fn logout(user: User, cookies: &CookieJar<'_>) -> ApiResponse<String> {
+    if user.session.is_some() {
+        cookies.remove_private(Cookie::from(SESSION_COOKIE));
+        info!("{} logged out", user.name);
+    }
+
+    ApiAnswer::success("{}".to_string())
+}
+
+#[get("/session")]
+pub async fn get_session(user: User) -> ApiResponse<SessionResponse> {
+    match &user.session {
+        Some(session) => ApiAnswer::success(session_response(session)),
+        None => ApiAnswer::success(SessionResponse {
+            authentication: user.is_identified(),
+            user: user.name,
+            csrf_token: None,
+            expires_at: None,
+        }),
+    }
+}
+
+fn session_response(session: &Session) -> SessionResponse {
+    SessionResponse {
+        user: session.user.clone(),
+        authentication: true,
+        csrf_token: Some(session.csrf_token.clone()),
+        expires_at: OffsetDateTime::from_unix_timestamp(session.expires_at)
+            .ok()
+            .and_then(|expires| expires.format(&Rfc3339).ok()),
+    }
+}
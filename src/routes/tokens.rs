@@ -0,0 +1,142 @@
+use crate::auth::{ApiToken, Authentication, Provider, TokenScope};
+use crate::routes::types::{
+    ApiAnswer, ApiError, ApiErrorKind, ApiResponse, CountResponse, CreateTokenRequest, CreatedTokenResponse,
+};
+use crate::routes::user::User;
+use crate::settings::Settings;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+/// The caller's tokens, or everyone's with `all` for admins.
+#[get("/tokens?<all>")]
+pub async fn get_tokens(
+    user: User,
+    authentication: &State<Authentication>,
+    all: Option<bool>,
+) -> ApiResponse<Vec<ApiToken>> {
+    ensure_identified(&user)?;
+    let owner = match all {
+        Some(true) => {
+            user.ensure_admin()?;
+            None
+        }
+        _ => Some(user.name.as_str()),
+    };
+
+    ApiAnswer::success(authentication.tokens().list(owner))
+}
+
+#[post("/tokens", data = "<request>")]
+pub async fn create_token(
+    user: User,
+    authentication: &State<Authentication>,
+    settings: &State<Settings>,
+    request: Json<CreateTokenRequest>,
+) -> ApiResponse<CreatedTokenResponse> {
+    ensure_identified(&user)?;
+    // A leaked token must not outlive its own expiry by minting others
+    if user.token.is_some() {
+        return Err(ApiError::new(
+            Status::Forbidden,
+            ApiErrorKind::AccessDenied,
+            "API tokens can't create tokens, log in to create one",
+        ));
+    }
+
+    let invalid = |message: &str| ApiError::new(Status::UnprocessableEntity, ApiErrorKind::Unprocessable, message);
+    let request = request.into_inner();
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(invalid("Token name is required"));
+    }
+    let repositories = request.repositories.unwrap_or_else(|| vec!["*".to_string()]);
+    if repositories.is_empty() || repositories.iter().any(|pattern| pattern.trim().is_empty()) {
+        return Err(invalid(
+            "Empty repository patterns, leave repositories out for all of them",
+        ));
+    }
+    let max_days = settings.config().token_max_days;
+    let days = request.expires_in_days.unwrap_or(max_days);
+    if days == 0 || days > max_days {
+        return Err(invalid(&format!("A token expires within 1 to {} days", max_days)));
+    }
+    let mut expires_at = OffsetDateTime::now_utc() + Duration::days(days as i64);
+    // Accounts disabled at the provider are only noticed at the next login, so the token
+    // doesn't outlast the session it's created from
+    if let Some(session) = user
+        .session
+        .as_ref()
+        .filter(|session| session.provider == Provider::Oidc)
+    {
+        let session_end = OffsetDateTime::from_unix_timestamp(session.expires_at).unwrap_or(expires_at);
+        expires_at = expires_at.min(session_end);
+    }
+    let expires_at = expires_at.format(&Rfc3339).unwrap_or_default();
+
+    let (secret, token) = authentication.tokens().create(ApiToken {
+        id: String::new(),
+        name: name.to_string(),
+        user: user.name.clone(),
+        groups: user.groups.clone(),
+        provider: user
+            .session
+            .as_ref()
+            .map_or(Provider::Proxy, |session| session.provider),
+        scope: TokenScope {
+            role: request.role,
+            repositories,
+        },
+        hint: String::new(),
+        created_at: String::new(),
+        expires_at,
+        last_used_at: None,
+    })?;
+    info!(
+        "{} created API token {} ({}) with the {} role",
+        user.name, token.id, token.name, token.scope.role
+    );
+
+    ApiAnswer::success(CreatedTokenResponse { secret, token })
+}
+
+/// Admins may revoke anyone's tokens.
+#[delete("/tokens/<id>")]
+pub async fn revoke_token(user: User, authentication: &State<Authentication>, id: &str) -> ApiResponse<String> {
+    ensure_identified(&user)?;
+    let owner = user.ensure_admin().is_err().then_some(user.name.as_str());
+    let token = authentication.tokens().revoke(id, owner)?;
+    info!("{} revoked API token {} of {}", user.name, token.id, token.user);
+
+    ApiAnswer::success("{}".to_string())
+}
+
+/// Admins revoke every token of a user, e.g. one who left.
+#[delete("/tokens?<owner>")]
+pub async fn revoke_user_tokens(
+    user: User,
+    authentication: &State<Authentication>,
+    owner: &str,
+) -> ApiResponse<CountResponse> {
+    ensure_identified(&user)?;
+    user.ensure_admin()?;
+    let revoked = authentication.tokens().revoke_all(owner)?;
+    info!("{} revoked the {} API tokens of {}", user.name, revoked.len(), owner);
+
+    ApiAnswer::success(CountResponse { count: revoked.len() })
+}
+
+/// Tokens stand for a user, there's none when everyone is let in.
+fn ensure_identified(user: &User) -> Result<(), ApiError> {
+    if user.is_identified() {
+        return Ok(());
+    }
+
+    Err(ApiError::new(
+        Status::NotFound,
+        ApiErrorKind::NotConfigured,
+        "API tokens need users, set HARBUI_USERS_FILE, HARBUI_OIDC_ISSUER or HARBUI_PROXY_USER_HEADER",
+    ))
+}
@@ -1,4 +1,13 @@
-use crate::types::ImageManifest;
+use crate::auth::{ApiToken, OidcError, Role, TokenError};
+use crate::registry_api::types::{ClientError, ErrorCode, ImageConfigResponse, Platform, RegistryError};
+use crate::replication::ReplicationError;
+use crate::retention::RetentionError;
+use crate::routes::request_id::RequestId;
+use crate::sbom::SbomFormat;
+use crate::types::{
+    CopyReport, DockerfileInstruction, FileNode, ImageManifest, Permissions, PlatformDiff, SeverityCounts,
+    Vulnerability,
+};
 use rocket::http::{ContentType, Status};
 use rocket::request::Request;
 use rocket::response::{self, Responder, Response};
@@ -14,7 +23,34 @@ pub struct CountResponse {
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct ConfigResponse {
     pub registry_domain: String,
+    pub registry_id: String,
     pub version: String,
+    /// What the registry of the request allows
+    pub operations: Permissions,
+    pub registries: Vec<RegistrySummary>,
+}
+
+/// Counts are only filled in by `/registries`, `error` tells why they are missing.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RegistrySummary {
+    pub id: String,
+    pub name: String,
+    pub domain: String,
+    pub deleting_allowed: bool,
+    pub operations: Permissions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repositories_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RegistriesResponse {
+    pub repositories_count: usize,
+    pub users_count: usize,
+    pub registries: Vec<RegistrySummary>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -24,12 +60,235 @@ pub struct ImageManifestResponse {
     pub manifests: Vec<ImageManifest>,
 }
 
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageConfigDetails {
+    pub digest: String,
+    pub config_digest: String,
+    pub platform: Option<Platform>,
+    pub config: ImageConfigResponse,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageConfigsResponse {
+    pub image: String,
+    pub reference: String,
+    pub configs: Vec<ImageConfigDetails>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlatformDockerfile {
+    pub digest: String,
+    pub platform: Option<Platform>,
+    pub dockerfile: String,
+    pub instructions: Vec<DockerfileInstruction>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DockerfileResponse {
+    pub image: String,
+    pub reference: String,
+    pub dockerfiles: Vec<PlatformDockerfile>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageDiffResponse {
+    pub from: String,
+    pub to: String,
+    pub platforms: Vec<PlatformDiff>,
+    pub only_in_from: Vec<String>,
+    pub only_in_to: Vec<String>,
+}
+
+/// `platform` picks the same platform on both sides, `from_platform` and `to_platform` one each.
+#[derive(FromForm, Debug)]
+pub struct DiffPlatforms {
+    pub platform: Option<String>,
+    pub from_platform: Option<String>,
+    pub to_platform: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LayerTreeResponse {
+    pub image: String,
+    pub reference: String,
+    pub digest: String,
+    pub media_type: String,
+    pub files_count: usize,
+    pub tree: Vec<FileNode>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlatformFilesystem {
+    pub digest: String,
+    pub platform: Option<Platform>,
+    pub files_count: usize,
+    pub tree: Vec<FileNode>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FilesystemResponse {
+    pub image: String,
+    pub reference: String,
+    pub filesystems: Vec<PlatformFilesystem>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlatformSbom {
+    pub digest: String,
+    pub platform: Option<Platform>,
+    pub packages_count: usize,
+    pub sbom: serde_json::Value,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SbomResponse {
+    pub image: String,
+    pub reference: String,
+    pub format: SbomFormat,
+    pub sboms: Vec<PlatformSbom>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlatformVulnerabilities {
+    pub digest: String,
+    pub platform: Option<Platform>,
+    pub counts: SeverityCounts,
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VulnerabilityReportResponse {
+    pub image: String,
+    pub reference: String,
+    pub advisories_count: usize,
+    pub reports: Vec<PlatformVulnerabilities>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetagRequest {
+    pub tag: String,
+}
+
+/// The tag defaults to the source reference, digests are pushed as is.
+/// The registry defaults to the source one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PromoteRequest {
+    pub registry: Option<String>,
+    pub repository: String,
+    pub tag: Option<String>,
+}
+
+/// Every tag pointing at the manifest a reference resolves to, `siblings` being the other ones.
+#[derive(Serialize, Clone, Debug)]
+pub struct DeleteImpactResponse {
+    pub repository: String,
+    pub reference: String,
+    pub digest: String,
+    pub tags: Vec<String>,
+    pub siblings: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeleteResponse {
+    pub digest: String,
+    pub deleted_tags: Vec<String>,
+    /// False when the registry removed the tag only
+    pub manifest_deleted: bool,
+}
+
+/// Images to delete, listed, matched by the filter, or both.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkDeleteRequest {
+    #[serde(default)]
+    pub images: Vec<ImageReference>,
+    pub filter: Option<BulkDeleteFilter>,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Deletes digests other tags point at too, taking those tags along
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageReference {
+    pub repository: String,
+    /// A tag or a digest
+    pub reference: String,
+}
+
+/// Repository patterns take `*` and `?`, `tags` is a regular expression matched against every
+/// tag of those repositories, and `older_than_days` compares with the image creation date.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkDeleteFilter {
+    pub repositories: Vec<String>,
+    pub tags: Option<String>,
+    pub older_than_days: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CopyResponse {
+    pub source: String,
+    pub target: String,
+    #[serde(flatten)]
+    pub report: CopyReport,
+}
+
+/// Ways to log in, for the login page to offer.
+#[derive(Serialize, Clone, Debug)]
+pub struct LoginMethodsResponse {
+    /// `POST /api/login` with a username and a password
+    pub local: bool,
+    /// `GET /api/oidc/login`
+    pub oidc: bool,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// `authentication` is false when harbui lets everyone in. Users named by the reverse proxy
+/// have no session, so no token and no expiry either.
+#[derive(Serialize, Clone, Debug)]
+pub struct SessionResponse {
+    pub user: String,
+    pub authentication: bool,
+    /// Send it back in `X-CSRF-Token` with every request changing something
+    pub csrf_token: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+/// A new API token. Leaving `repositories` out means all of those the owner may see, leaving
+/// `expires_in_days` out gives the longest lifetime allowed.
+#[derive(Deserialize, Clone)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    #[serde(default = "viewer")]
+    pub role: Role,
+    pub repositories: Option<Vec<String>>,
+    pub expires_in_days: Option<u32>,
+}
+
+fn viewer() -> Role {
+    Role::Viewer
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct CreatedTokenResponse {
+    /// Only ever shown here
+    pub secret: String,
+    #[serde(flatten)]
+    pub token: ApiToken,
+}
+
 pub type ApiResponse<T> = Result<ApiAnswer<T>, ApiError>;
 
 #[derive(Clone, Debug)]
 pub struct ApiAnswer<T> {
     pub json: Json<T>,
     pub status: Status,
+    pub link: Option<String>,
 }
 
 impl<T> ApiAnswer<T> {
@@ -40,6 +299,18 @@ impl<T> ApiAnswer<T> {
         Ok(ApiAnswer {
             json: Json(object),
             status: Status::Ok,
+            link: None,
+        })
+    }
+
+    pub fn paginated(object: T, next: Option<String>) -> ApiResponse<T>
+    where
+        T: Serialize,
+    {
+        Ok(ApiAnswer {
+            json: Json(object),
+            status: Status::Ok,
+            link: next.map(|url| format!("<{}>; rel=\"next\"", url)),
         })
     }
 }
@@ -49,36 +320,182 @@ where
     T: Serialize,
 {
     fn respond_to(self, req: &Request) -> response::Result<'r> {
-        Response::build_from(self.json.respond_to(req).unwrap())
-            .status(self.status)
-            .header(ContentType::JSON)
-            .ok()
+        let mut response = Response::build_from(self.json.respond_to(req).unwrap());
+        if let Some(link) = self.link {
+            response.raw_header("Link", link);
+        }
+
+        response.status(self.status).header(ContentType::JSON).ok()
     }
 }
 
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ApiErrorKind {
+    Unknown,
+    NotFound,
+    Unprocessable,
+    BadRequest,
+    UpstreamError,
+    RegistryUnreachable,
+    RegistryTimeout,
+    RegistryTls,
+    RegistryUnavailable,
+    RegistryDenied,
+    DecodeError,
+    UnsupportedMediaType,
+    NotConfigured,
+    Forbidden,
+    /// A switch like `HARBUI_DELETING_ALLOWED` turns the operation off
+    OperationDisabled,
+    Conflict,
+    /// No session, or an expired one, or an unknown API token
+    Unauthorized,
+    InvalidCredentials,
+    /// A request changing something without the token of its session
+    CsrfTokenInvalid,
+    /// The `[[access]]` grants don't give the caller a high enough role
+    AccessDenied,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ApiErrorCode {
+    Registry(ErrorCode),
+    Api(ApiErrorKind),
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ApiErrorBody<'a> {
+    pub code: &'a ApiErrorCode,
+    pub status: u16,
+    pub message: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub detail: &'a Vec<RegistryError>,
+    pub request_id: String,
+}
+
+#[derive(Clone, Debug)]
 pub struct ApiError {
     pub status: Status,
+    pub code: ApiErrorCode,
     pub message: String,
+    pub detail: Vec<RegistryError>,
 }
 
 impl ApiError {
-    pub fn unprocessable(message: &str) -> Self {
+    pub fn new(status: Status, kind: ApiErrorKind, message: &str) -> Self {
         Self {
-            status: Status::UnprocessableEntity,
+            status,
+            code: ApiErrorCode::Api(kind),
             message: message.to_owned(),
+            detail: Vec::new(),
         }
     }
 
-    pub fn not_found(message: &str) -> Self {
+    pub fn from_status(status: Status) -> Self {
+        let kind = match status.code {
+            400 => ApiErrorKind::BadRequest,
+            401 => ApiErrorKind::Unauthorized,
+            403 => ApiErrorKind::Forbidden,
+            404 => ApiErrorKind::NotFound,
+            422 => ApiErrorKind::Unprocessable,
+            _ => ApiErrorKind::Unknown,
+        };
+
+        Self::new(status, kind, status.reason_lossy())
+    }
+}
+
+impl From<ClientError> for ApiError {
+    fn from(err: ClientError) -> Self {
+        let message = err.to_string();
+        let (status, kind) = match &err {
+            ClientError::Transport(_) => (Status::BadGateway, ApiErrorKind::RegistryUnreachable),
+            ClientError::Timeout => (Status::GatewayTimeout, ApiErrorKind::RegistryTimeout),
+            ClientError::Tls(_) => (Status::BadGateway, ApiErrorKind::RegistryTls),
+            ClientError::Upstream { .. } => (Status::BadGateway, ApiErrorKind::RegistryUnavailable),
+            ClientError::UnexpectedStatus { .. } => (Status::BadGateway, ApiErrorKind::UpstreamError),
+            ClientError::Decode { .. } => (Status::BadGateway, ApiErrorKind::DecodeError),
+            ClientError::UnsupportedMediaType(_) => (Status::UnprocessableEntity, ApiErrorKind::UnsupportedMediaType),
+            ClientError::Auth { .. } => (Status::BadGateway, ApiErrorKind::RegistryDenied),
+            ClientError::NotFound(_) => (Status::NotFound, ApiErrorKind::NotFound),
+            ClientError::Registry { status, .. } => (*status, ApiErrorKind::BadRequest),
+        };
+
+        // Upstream auth failures are harbui's credentials problem, not the caller's
+        let code = match err.errors().first() {
+            Some(first) if kind != ApiErrorKind::RegistryDenied => ApiErrorCode::Registry(first.code.clone()),
+            _ => ApiErrorCode::Api(kind),
+        };
+
         Self {
-            status: Status::NotFound,
-            message: message.to_owned(),
+            status,
+            code,
+            message,
+            detail: err.errors().to_vec(),
         }
     }
 }
-impl<'r> Responder<'r, 'r> for ApiError {
-    fn respond_to(self, req: &Request) -> response::Result<'r> {
-        Response::build_from(self.message.respond_to(req).unwrap())
+
+impl From<ReplicationError> for ApiError {
+    fn from(err: ReplicationError) -> Self {
+        let (status, kind) = match &err {
+            ReplicationError::RuleNotFound(_) => (Status::NotFound, ApiErrorKind::NotFound),
+            ReplicationError::AlreadyRunning(_) => (Status::Conflict, ApiErrorKind::Conflict),
+            ReplicationError::Storage(_) => (Status::InternalServerError, ApiErrorKind::Unknown),
+        };
+
+        Self::new(status, kind, &err.to_string())
+    }
+}
+
+impl From<TokenError> for ApiError {
+    fn from(err: TokenError) -> Self {
+        let (status, kind) = match &err {
+            TokenError::NotFound(_) => (Status::NotFound, ApiErrorKind::NotFound),
+            TokenError::Storage(_) => (Status::InternalServerError, ApiErrorKind::Unknown),
+        };
+
+        Self::new(status, kind, &err.to_string())
+    }
+}
+
+impl From<OidcError> for ApiError {
+    fn from(err: OidcError) -> Self {
+        let (status, kind) = match &err {
+            OidcError::Provider(_) => (Status::BadGateway, ApiErrorKind::UpstreamError),
+            OidcError::Denied(_) => (Status::Unauthorized, ApiErrorKind::InvalidCredentials),
+            OidcError::Invalid(_) => (Status::Unauthorized, ApiErrorKind::Unauthorized),
+        };
+
+        Self::new(status, kind, &err.to_string())
+    }
+}
+
+impl From<RetentionError> for ApiError {
+    fn from(err: RetentionError) -> Self {
+        let (status, kind) = match &err {
+            RetentionError::PolicyNotFound(_) => (Status::NotFound, ApiErrorKind::NotFound),
+            RetentionError::AlreadyRunning(_) => (Status::Conflict, ApiErrorKind::Conflict),
+            RetentionError::Storage(_) => (Status::InternalServerError, ApiErrorKind::Unknown),
+        };
+
+        Self::new(status, kind, &err.to_string())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &Request) -> response::Result<'static> {
+        let body = ApiErrorBody {
+            code: &self.code,
+            status: self.status.code,
+            message: &self.message,
+            detail: &self.detail,
+            request_id: RequestId::of(req).0,
+        };
+
+        Response::build_from(Json(body).respond_to(req)?)
             .status(self.status)
             .header(ContentType::JSON)
             .ok()
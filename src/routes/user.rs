@@ -0,0 +1,194 @@
+use crate::auth::{AccessPolicy, ApiToken, Authentication, Role, Session, CSRF_HEADER, TOKEN_PREFIX};
+use crate::routes::permission::refuse;
+use crate::routes::types::{ApiError, ApiErrorKind};
+use crate::settings::Settings;
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use std::sync::Arc;
+
+/// Name of the caller when authentication is off.
+pub const ANONYMOUS: &str = "anonymous";
+
+/// The caller of an API route, named by an API token in `Authorization: Bearer harbui_...`, by
+/// the reverse proxy or by a session cookie from `/api/login` or an OpenID Connect login.
+/// Session requests changing something also need its CSRF token in `X-CSRF-Token`. What the
+/// caller may do comes from the `[[access]]` grants, narrowed by the scope of a token.
+pub struct User {
+    pub name: String,
+    pub groups: Vec<String>,
+    pub session: Option<Session>,
+    pub token: Option<ApiToken>,
+    /// `None` when authentication is off and everything is allowed
+    access: Option<Arc<AccessPolicy>>,
+}
+
+impl User {
+    pub fn is_identified(&self) -> bool {
+        self.access.is_some()
+    }
+
+    pub fn allows(&self, registry: &str, repository: &str, role: Role) -> bool {
+        match &self.access {
+            Some(access) => access
+                .role(&self.name, &self.groups, registry, repository)
+                .and_then(|granted| match &self.token {
+                    Some(token) => token.scope.limit(repository, granted),
+                    None => Some(granted),
+                })
+                .is_some_and(|granted| granted >= role),
+            None => true,
+        }
+    }
+
+    /// Repositories the user can't see are answered as missing, the others with a `403`.
+    pub fn ensure(&self, registry: &str, repository: &str, role: Role) -> Result<(), ApiError> {
+        if self.allows(registry, repository, role) {
+            return Ok(());
+        }
+        if !self.allows(registry, repository, Role::Viewer) {
+            return Err(ApiError::new(
+                Status::NotFound,
+                ApiErrorKind::NotFound,
+                "Repository not found",
+            ));
+        }
+
+        Err(ApiError::new(
+            Status::Forbidden,
+            ApiErrorKind::AccessDenied,
+            &format!("{} needs the {} role on {}", self.caller(), role, repository),
+        ))
+    }
+
+    pub fn ensure_admin(&self) -> Result<(), ApiError> {
+        match &self.access {
+            Some(access)
+                if !access.is_admin(&self.name, &self.groups)
+                    || self.token.as_ref().is_some_and(|token| !token.scope.is_admin()) =>
+            {
+                Err(ApiError::new(
+                    Status::Forbidden,
+                    ApiErrorKind::AccessDenied,
+                    &format!("{} needs the admin role on all repositories", self.caller()),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// For messages, tokens may lack a role their owner has.
+    fn caller(&self) -> String {
+        match &self.token {
+            Some(token) => format!("API token {} of {}", token.name, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Only the repositories the user may see.
+    pub fn visible(&self, registry: &str, mut repositories: Vec<String>) -> Vec<String> {
+        repositories.retain(|repository| self.allows(registry, repository, Role::Viewer));
+        repositories
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for User {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (Some(authentication), Some(settings)) =
+            (req.rocket().state::<Authentication>(), req.rocket().state::<Settings>())
+        else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+        if !authentication.enabled() {
+            return Outcome::Success(User {
+                name: ANONYMOUS.to_string(),
+                groups: Vec::new(),
+                session: None,
+                token: None,
+                access: None,
+            });
+        }
+
+        // Other bearer tokens may be meant for the reverse proxy
+        let bearer = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|secret| secret.starts_with(TOKEN_PREFIX));
+        if let Some(secret) = bearer {
+            // Not sent by browsers on their own, so no CSRF check
+            let Some(token) = authentication.token(secret) else {
+                return refuse(
+                    req,
+                    ApiError::new(
+                        Status::Unauthorized,
+                        ApiErrorKind::Unauthorized,
+                        "Invalid, expired or revoked API token",
+                    ),
+                );
+            };
+
+            return Outcome::Success(User {
+                name: token.user.clone(),
+                groups: token.groups.clone(),
+                session: None,
+                token: Some(token),
+                access: Some(settings.access()),
+            });
+        }
+
+        let safe = matches!(req.method(), Method::Get | Method::Head | Method::Options);
+
+        if let Some(name) = authentication.proxy_user(req.headers()) {
+            // No token to check, but browsers tell where a request comes from
+            let site = req.headers().get_one("Sec-Fetch-Site");
+            if !safe && site.is_some_and(|site| site != "same-origin" && site != "none") {
+                return refuse(
+                    req,
+                    ApiError::new(
+                        Status::Forbidden,
+                        ApiErrorKind::CsrfTokenInvalid,
+                        "Changes must come from the harbui UI",
+                    ),
+                );
+            }
+
+            return Outcome::Success(User {
+                name,
+                groups: Vec::new(),
+                session: None,
+                token: None,
+                access: Some(settings.access()),
+            });
+        }
+
+        let Some(session) = authentication.session(req.cookies()) else {
+            return refuse(
+                req,
+                ApiError::new(Status::Unauthorized, ApiErrorKind::Unauthorized, "Log in first"),
+            );
+        };
+        if !safe && !session.csrf_matches(req.headers().get_one(CSRF_HEADER)) {
+            return refuse(
+                req,
+                ApiError::new(
+                    Status::Forbidden,
+                    ApiErrorKind::CsrfTokenInvalid,
+                    &format!("Missing or wrong {} header", CSRF_HEADER),
+                ),
+            );
+        }
+
+        Outcome::Success(User {
+            name: session.user.clone(),
+            groups: session.groups.clone(),
+            session: Some(session),
+            token: None,
+            access: Some(settings.access()),
+        })
+    }
+}
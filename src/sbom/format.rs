@@ -0,0 +1,242 @@
+use crate::registry_api::types::Platform;
+use crate::sbom::Inventory;
+use crate::types::{OsRelease, Package, PackageType};
+use rocket::form::FromFormField;
+use serde::Serialize;
+use serde_json::{json, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(FromFormField, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SbomFormat {
+    #[default]
+    #[field(value = "cyclonedx")]
+    CycloneDx,
+    #[field(value = "spdx")]
+    Spdx,
+}
+
+/// The image manifest an SBOM describes.
+pub struct SbomSubject<'a> {
+    pub image: &'a str,
+    pub digest: &'a str,
+    pub platform: Option<&'a Platform>,
+    pub tool_version: &'a str,
+}
+
+pub fn render(format: SbomFormat, subject: &SbomSubject, inventory: &Inventory) -> Value {
+    match format {
+        SbomFormat::CycloneDx => cyclonedx(subject, inventory),
+        SbomFormat::Spdx => spdx(subject, inventory),
+    }
+}
+
+/// CycloneDX 1.5 JSON
+fn cyclonedx(subject: &SbomSubject, inventory: &Inventory) -> Value {
+    let mut components: Vec<Value> = inventory
+        .packages
+        .iter()
+        .map(|package| {
+            let purl = purl(package, inventory.os.as_ref());
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": purl,
+                "name": package.name,
+                "version": package.version,
+                "purl": purl,
+                "properties": [
+                    { "name": "harbui:package:type", "value": package.package_type },
+                    { "name": "harbui:location", "value": package.location },
+                    { "name": "harbui:layer", "value": package.layer },
+                ],
+            });
+            if let Some(license) = &package.license {
+                component["licenses"] = json!([{ "license": { "name": license } }]);
+            }
+
+            component
+        })
+        .collect();
+
+    if let Some(os) = &inventory.os {
+        components.insert(
+            0,
+            json!({
+                "type": "operating-system",
+                "bom-ref": format!("os:{}", os.id),
+                "name": os.id,
+                "version": os.version_id,
+                "description": os.pretty_name,
+            }),
+        );
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", Uuid::new_v4()),
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp(),
+            "tools": {
+                "components": [{ "type": "application", "name": "harbui", "version": subject.tool_version }],
+            },
+            "component": {
+                "type": "container",
+                "bom-ref": subject.digest,
+                "name": subject.image,
+                "version": subject.digest,
+                "properties": [
+                    { "name": "harbui:platform", "value": subject.platform.map(|p| p.to_string()) },
+                ],
+            },
+        },
+        "components": components,
+    })
+}
+
+/// SPDX 2.3 JSON
+fn spdx(subject: &SbomSubject, inventory: &Inventory) -> Value {
+    let mut packages = vec![json!({
+        "SPDXID": "SPDXRef-Image",
+        "name": subject.image,
+        "versionInfo": subject.digest,
+        "downloadLocation": "NOASSERTION",
+        "primaryPackagePurpose": "CONTAINER",
+        "comment": subject.platform.map(|p| format!("Platform {}", p)),
+    })];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": "SPDXRef-Image",
+    })];
+
+    for (index, package) in inventory.packages.iter().enumerate() {
+        let id = format!("SPDXRef-Package-{}", index);
+
+        packages.push(json!({
+            "SPDXID": id,
+            "name": package.name,
+            "versionInfo": package.version,
+            "downloadLocation": "NOASSERTION",
+            "licenseConcluded": "NOASSERTION",
+            // Distribution license fields are free text, not always valid SPDX expressions
+            "licenseDeclared": "NOASSERTION",
+            "licenseComments": package.license,
+            "sourceInfo": format!("{} found in {}", package_kind(package), package.location),
+            "externalRefs": [{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl(package, inventory.os.as_ref()),
+            }],
+        }));
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-Image",
+            "relationshipType": "CONTAINS",
+            "relatedSpdxElement": id,
+        }));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": format!("{}@{}", subject.image, subject.digest),
+        "documentNamespace": format!("https://harbui/spdx/{}/{}", subject.image, Uuid::new_v4()),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": [format!("Tool: harbui-{}", subject.tool_version)],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+fn package_kind(package: &Package) -> &'static str {
+    match package.package_type {
+        PackageType::Deb => "dpkg package",
+        PackageType::Apk => "apk package",
+        PackageType::Rpm => "rpm package",
+        PackageType::Python => "python package",
+        PackageType::Go => "go module",
+        PackageType::Npm => "npm package",
+    }
+}
+
+/// Package URL, see https://github.com/package-url/purl-spec
+pub fn purl(package: &Package, os: Option<&OsRelease>) -> String {
+    let distro = os.map(|os| os.id.as_str());
+    let qualifiers = |package: &Package| -> String {
+        let mut qualifiers = Vec::new();
+        if let Some(arch) = &package.arch {
+            qualifiers.push(format!("arch={}", encode(arch)));
+        }
+        if let Some(os) = os {
+            let distro = match &os.version_id {
+                Some(version) => format!("{}-{}", os.id, version),
+                None => os.id.clone(),
+            };
+            qualifiers.push(format!("distro={}", encode(&distro)));
+        }
+
+        if qualifiers.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", qualifiers.join("&"))
+        }
+    };
+
+    let version = encode(&package.version);
+    match package.package_type {
+        PackageType::Deb => format!(
+            "pkg:deb/{}/{}@{}{}",
+            distro.unwrap_or("debian"),
+            encode(&package.name),
+            version,
+            qualifiers(package)
+        ),
+        PackageType::Apk => format!(
+            "pkg:apk/{}/{}@{}{}",
+            distro.unwrap_or("alpine"),
+            encode(&package.name),
+            version,
+            qualifiers(package)
+        ),
+        PackageType::Rpm => format!(
+            "pkg:rpm/{}/{}@{}{}",
+            distro.unwrap_or("redhat"),
+            encode(&package.name),
+            version,
+            qualifiers(package)
+        ),
+        PackageType::Python => format!(
+            "pkg:pypi/{}@{}",
+            encode(&package.name.to_lowercase().replace('_', "-")),
+            version
+        ),
+        PackageType::Go => {
+            let path: Vec<String> = package.name.split('/').map(encode).collect();
+            format!("pkg:golang/{}@{}", path.join("/"), version)
+        }
+        PackageType::Npm => match package.name.strip_prefix('@').and_then(|n| n.split_once('/')) {
+            Some((scope, name)) => format!("pkg:npm/%40{}/{}@{}", encode(scope), encode(name), version),
+            None => format!("pkg:npm/{}@{}", encode(&package.name), version),
+        },
+    }
+}
+
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn timestamp() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
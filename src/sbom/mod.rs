@@ -0,0 +1,170 @@
+mod format;
+mod parsers;
+
+pub use format::{render, SbomFormat, SbomSubject};
+
+use crate::layers::{merge_layers, walk_layer, EntryVisitor};
+use crate::manager::PlatformManifest;
+use crate::registry_api::types::{ClientError, Layer};
+use crate::registry_api::RegistryClient;
+use crate::types::{FileEntry, FileKind, OsRelease, Package};
+use parsers::Source;
+use rocket::futures::future::try_join_all;
+use rocket::tokio::sync::OnceCell;
+use rocket::tokio::task;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, RwLock};
+
+const MAX_DATABASE_SIZE: u64 = 256 << 20;
+const MAX_CACHED_INVENTORIES: usize = 512;
+
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    pub os: Option<OsRelease>,
+    pub packages: Vec<Package>,
+}
+
+/// Inventories by config digest. Image contents never change for a given config,
+/// so entries are only dropped to bound memory.
+#[derive(Default)]
+pub struct InventoryCache {
+    inventories: RwLock<HashMap<String, Arc<Inventory>>>,
+    /// Extractions under way, shared by concurrent requests for the same image
+    pending: Mutex<HashMap<String, Arc<OnceCell<Arc<Inventory>>>>>,
+}
+
+impl InventoryCache {
+    pub async fn get(
+        &self,
+        client: &RegistryClient,
+        image: &str,
+        manifest: &PlatformManifest,
+    ) -> Result<Arc<Inventory>, ClientError> {
+        let key = &manifest.config.digest;
+        if let Some(inventory) = self.cached(key) {
+            return Ok(inventory);
+        }
+
+        let pending = self
+            .pending
+            .lock()
+            .ok()
+            .map(|mut pending| pending.entry(key.clone()).or_default().clone());
+        let extracted = match &pending {
+            Some(cell) => cell
+                .get_or_try_init(|| async { inventory(client, image, &manifest.layers).await.map(Arc::new) })
+                .await
+                .cloned(),
+            None => inventory(client, image, &manifest.layers).await.map(Arc::new),
+        };
+
+        // Cached before the extraction is forgotten, so that no request starts another one
+        if let (Ok(inventory), Ok(mut inventories)) = (&extracted, self.inventories.write()) {
+            if inventories.len() >= MAX_CACHED_INVENTORIES {
+                inventories.clear();
+            }
+            inventories.insert(key.clone(), inventory.clone());
+        }
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(key);
+        }
+
+        extracted
+    }
+
+    /// The inventory of an image extracted before, without downloading anything.
+    pub fn cached(&self, config_digest: &str) -> Option<Arc<Inventory>> {
+        self.inventories.read().ok()?.get(config_digest).cloned()
+    }
+}
+
+/// Keeps every entry for whiteout handling, but only the content of package databases
+/// and the build info of executables.
+#[derive(Default)]
+struct PackageFiles {
+    entries: Vec<FileEntry>,
+    contents: HashMap<String, Vec<u8>>,
+}
+
+impl EntryVisitor for PackageFiles {
+    fn visit(&mut self, entry: FileEntry, content: &mut dyn Read) -> io::Result<()> {
+        if entry.kind == FileKind::File && !entry.whiteout {
+            let data = match Source::of(&entry.path) {
+                Some(_) if entry.size <= MAX_DATABASE_SIZE => {
+                    let mut data = Vec::with_capacity(entry.size as usize);
+                    content.read_to_end(&mut data)?;
+                    Some(data)
+                }
+                Some(_) => None,
+                None if entry.mode & 0o111 != 0 => parsers::find_go_buildinfo(content)?,
+                None => None,
+            };
+
+            if let Some(data) = data {
+                self.contents.insert(entry.path.clone(), data);
+            }
+        }
+
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+/// Lists the packages installed in the filesystem the layers stack up to.
+/// Databases deleted or replaced by a later layer are not reported.
+pub async fn inventory(client: &RegistryClient, image: &str, layers: &[Layer]) -> Result<Inventory, ClientError> {
+    let futures = layers
+        .iter()
+        .map(|layer| walk_layer(client, image, layer, PackageFiles::default()));
+    let files = try_join_all(futures).await?;
+
+    let mut contents: HashMap<String, HashMap<String, Vec<u8>>> = HashMap::new();
+    let mut stacked = Vec::new();
+    for (layer, files) in layers.iter().zip(files) {
+        contents.entry(layer.digest.clone()).or_default().extend(files.contents);
+        stacked.push((layer.digest.clone(), files.entries));
+    }
+
+    task::spawn_blocking(move || build_inventory(merge_layers(stacked), &contents))
+        .await
+        .map_err(|e| ClientError::Decode {
+            message: e.to_string(),
+            excerpt: image.to_string(),
+        })
+}
+
+fn build_inventory(files: Vec<FileEntry>, contents: &HashMap<String, HashMap<String, Vec<u8>>>) -> Inventory {
+    let mut inventory = Inventory::default();
+
+    for file in files {
+        let layer = file.layer.as_ref().and_then(|layer| contents.get(layer));
+        let Some(data) = layer.and_then(|c| c.get(&file.path)) else {
+            continue;
+        };
+
+        let packages = match Source::of(&file.path) {
+            Some(Source::OsRelease) => {
+                inventory.os = inventory.os.or_else(|| parsers::parse_os_release(data));
+                continue;
+            }
+            Some(Source::Dpkg) => parsers::parse_dpkg(data),
+            Some(Source::Apk) => parsers::parse_apk(data),
+            Some(Source::Rpm) => parsers::parse_rpm(data).unwrap_or_else(|e| {
+                warn!("Can't read RPM database {}: {:?}", file.path, e);
+                Vec::new()
+            }),
+            Some(Source::Python) => parsers::parse_python(data).into_iter().collect(),
+            Some(Source::Npm) => parsers::parse_npm(data),
+            None => parsers::parse_go_buildinfo(data),
+        };
+
+        inventory.packages.extend(packages.into_iter().map(|package| Package {
+            location: format!("/{}", file.path),
+            layer: file.layer.clone(),
+            ..package
+        }));
+    }
+
+    inventory
+}
@@ -0,0 +1,472 @@
+use crate::types::{OsRelease, Package, PackageType};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use uuid::Uuid;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const GO_BUILDINFO_MAGIC: &[u8] = b"\xff Go buildinf:";
+const GO_BUILDINFO_MAX_SIZE: u64 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    OsRelease,
+    Dpkg,
+    Apk,
+    Rpm,
+    Python,
+    Npm,
+}
+
+impl Source {
+    pub fn of(path: &str) -> Option<Self> {
+        let name = path.rsplit('/').next().unwrap_or_default();
+
+        match path {
+            "etc/os-release" | "usr/lib/os-release" => Some(Source::OsRelease),
+            "var/lib/dpkg/status" => Some(Source::Dpkg),
+            // Distroless images keep one status file per package
+            _ if path.starts_with("var/lib/dpkg/status.d/") && !name.ends_with(".md5sums") => Some(Source::Dpkg),
+            "lib/apk/db/installed" => Some(Source::Apk),
+            "var/lib/rpm/rpmdb.sqlite" | "usr/lib/sysimage/rpm/rpmdb.sqlite" => Some(Source::Rpm),
+            _ if path.ends_with(".dist-info/METADATA") || path.ends_with(".egg-info/PKG-INFO") => Some(Source::Python),
+            // Lockfiles of installed dependencies duplicate the project's one
+            _ if name == "package-lock.json" && !path.contains("node_modules/") => Some(Source::Npm),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse_os_release(data: &[u8]) -> Option<OsRelease> {
+    let text = String::from_utf8_lossy(data);
+    let fields: HashMap<&str, String> = text
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim().trim_matches('"').to_string()))
+        .collect();
+
+    Some(OsRelease {
+        id: fields.get("ID")?.clone(),
+        version_id: fields.get("VERSION_ID").cloned(),
+        pretty_name: fields.get("PRETTY_NAME").cloned(),
+    })
+}
+
+/// `/var/lib/dpkg/status`, `Key: value` paragraphs separated by blank lines.
+pub fn parse_dpkg(data: &[u8]) -> Vec<Package> {
+    String::from_utf8_lossy(data)
+        .split("\n\n")
+        .filter_map(|paragraph| {
+            let fields: HashMap<&str, &str> = paragraph
+                .lines()
+                .filter(|line| !line.starts_with([' ', '\t']))
+                .filter_map(|line| line.split_once(':'))
+                .map(|(key, value)| (key, value.trim()))
+                .collect();
+
+            // Removed packages keep their entry with a `deinstall ok config-files` status
+            if fields.get("Status").is_some_and(|s| !s.ends_with(" installed")) {
+                return None;
+            }
+
+            Some(Package {
+                name: fields.get("Package")?.to_string(),
+                version: fields.get("Version")?.to_string(),
+                package_type: PackageType::Deb,
+                arch: fields.get("Architecture").map(|a| a.to_string()),
+                license: None,
+                // `Source: glibc (2.36-9)` when the source version differs
+                source: fields
+                    .get("Source")
+                    .and_then(|s| s.split_whitespace().next())
+                    .map(String::from),
+                location: String::new(),
+                layer: None,
+            })
+        })
+        .collect()
+}
+
+/// `/lib/apk/db/installed`, single letter `K:value` records separated by blank lines.
+pub fn parse_apk(data: &[u8]) -> Vec<Package> {
+    String::from_utf8_lossy(data)
+        .split("\n\n")
+        .filter_map(|record| {
+            let fields: HashMap<&str, &str> = record.lines().filter_map(|line| line.split_once(':')).collect();
+
+            Some(Package {
+                name: fields.get("P")?.to_string(),
+                version: fields.get("V")?.to_string(),
+                package_type: PackageType::Apk,
+                arch: fields.get("A").map(|a| a.to_string()),
+                license: fields.get("L").map(|l| l.to_string()),
+                source: fields.get("o").map(|o| o.to_string()),
+                location: String::new(),
+                layer: None,
+            })
+        })
+        .collect()
+}
+
+/// `rpmdb.sqlite` used since Fedora 33 and RHEL 9, every row of `Packages` is an RPM header blob.
+pub fn parse_rpm(data: &[u8]) -> io::Result<Vec<Package>> {
+    // SQLite needs a file to open
+    let path = std::env::temp_dir().join(format!("harbui-rpmdb-{}.sqlite", Uuid::new_v4()));
+    std::fs::write(&path, data)?;
+
+    let packages = read_rpmdb(&path).map_err(io::Error::other);
+    let _ = std::fs::remove_file(&path);
+
+    packages
+}
+
+fn read_rpmdb(path: &std::path::Path) -> rusqlite::Result<Vec<Package>> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare("SELECT blob FROM Packages")?;
+    let blobs = statement
+        .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(blobs
+        .iter()
+        .filter_map(|blob| RpmHeader::parse(blob))
+        .filter_map(|header| {
+            let name = header.string(RPMTAG_NAME)?;
+            // Imported signing keys are stored as pseudo packages
+            if name == "gpg-pubkey" {
+                return None;
+            }
+
+            let version = match header.int(RPMTAG_EPOCH) {
+                Some(epoch) => format!(
+                    "{}:{}-{}",
+                    epoch,
+                    header.string(RPMTAG_VERSION)?,
+                    header.string(RPMTAG_RELEASE)?
+                ),
+                None => format!("{}-{}", header.string(RPMTAG_VERSION)?, header.string(RPMTAG_RELEASE)?),
+            };
+
+            Some(Package {
+                name,
+                version,
+                package_type: PackageType::Rpm,
+                arch: header.string(RPMTAG_ARCH),
+                license: header.string(RPMTAG_LICENSE),
+                source: header.string(RPMTAG_SOURCERPM),
+                location: String::new(),
+                layer: None,
+            })
+        })
+        .collect())
+}
+
+const RPMTAG_NAME: u32 = 1000;
+const RPMTAG_VERSION: u32 = 1001;
+const RPMTAG_RELEASE: u32 = 1002;
+const RPMTAG_EPOCH: u32 = 1003;
+const RPMTAG_LICENSE: u32 = 1014;
+const RPMTAG_ARCH: u32 = 1022;
+const RPMTAG_SOURCERPM: u32 = 1044;
+
+const RPM_INT32_TYPE: u32 = 4;
+const RPM_STRING_TYPES: [u32; 3] = [6, 8, 9];
+
+/// Header blob: entry count and data size, 16 byte `(tag, type, offset, count)` entries, then the data.
+struct RpmHeader<'a> {
+    entries: HashMap<u32, (u32, usize)>,
+    data: &'a [u8],
+}
+
+impl<'a> RpmHeader<'a> {
+    fn parse(blob: &'a [u8]) -> Option<Self> {
+        let be32 =
+            |offset: usize| -> Option<u32> { Some(u32::from_be_bytes(blob.get(offset..offset + 4)?.try_into().ok()?)) };
+
+        let count = be32(0)? as usize;
+        let size = be32(4)? as usize;
+        let data_start = 8 + count.checked_mul(16)?;
+        let data = blob.get(data_start..data_start.checked_add(size)?)?;
+
+        let entries = (0..count)
+            .filter_map(|i| {
+                let entry = 8 + i * 16;
+                Some((be32(entry)?, (be32(entry + 4)?, be32(entry + 8)? as usize)))
+            })
+            .collect();
+
+        Some(Self { entries, data })
+    }
+
+    fn string(&self, tag: u32) -> Option<String> {
+        let (kind, offset) = *self.entries.get(&tag)?;
+        if !RPM_STRING_TYPES.contains(&kind) {
+            return None;
+        }
+
+        let value = self.data.get(offset..)?;
+        let end = value.iter().position(|b| *b == 0)?;
+
+        Some(String::from_utf8_lossy(&value[..end]).into_owned())
+    }
+
+    fn int(&self, tag: u32) -> Option<u32> {
+        let (kind, offset) = *self.entries.get(&tag)?;
+        if kind != RPM_INT32_TYPE {
+            return None;
+        }
+
+        Some(u32::from_be_bytes(self.data.get(offset..offset + 4)?.try_into().ok()?))
+    }
+}
+
+/// `*.dist-info/METADATA` and `*.egg-info/PKG-INFO`, email style headers.
+pub fn parse_python(data: &[u8]) -> Option<Package> {
+    let text = String::from_utf8_lossy(data);
+    let fields: HashMap<&str, &str> = text
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(": "))
+        .collect();
+
+    Some(Package {
+        name: fields.get("Name")?.to_string(),
+        version: fields.get("Version")?.to_string(),
+        package_type: PackageType::Python,
+        arch: None,
+        license: fields
+            .get("License-Expression")
+            .or(fields.get("License"))
+            .filter(|l| !l.is_empty() && **l != "UNKNOWN")
+            .map(|l| l.to_string()),
+        source: None,
+        location: String::new(),
+        layer: None,
+    })
+}
+
+/// `package-lock.json`, the `packages` map of lockfile v2/v3 or the nested `dependencies` of v1.
+pub fn parse_npm(data: &[u8]) -> Vec<Package> {
+    let Ok(lockfile) = serde_json::from_slice::<Value>(data) else {
+        return Vec::new();
+    };
+
+    let package = |name: &str, entry: &Value| -> Option<Package> {
+        Some(Package {
+            name: name.to_string(),
+            version: entry.get("version")?.as_str()?.to_string(),
+            package_type: PackageType::Npm,
+            arch: None,
+            license: entry.get("license").and_then(Value::as_str).map(String::from),
+            source: None,
+            location: String::new(),
+            layer: None,
+        })
+    };
+
+    if let Some(packages) = lockfile.get("packages").and_then(Value::as_object) {
+        return packages
+            .iter()
+            // `""` is the project itself, links point to workspace folders
+            .filter(|(path, entry)| !path.is_empty() && entry.get("link").is_none())
+            .filter_map(|(path, entry)| {
+                let name = entry
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_else(|| path.rsplit("node_modules/").next().unwrap_or(path));
+                package(name, entry)
+            })
+            .collect();
+    }
+
+    fn walk(
+        dependencies: Option<&Value>,
+        packages: &mut Vec<Package>,
+        package: &dyn Fn(&str, &Value) -> Option<Package>,
+    ) {
+        for (name, entry) in dependencies.and_then(Value::as_object).into_iter().flatten() {
+            packages.extend(package(name, entry));
+            walk(entry.get("dependencies"), packages, package);
+        }
+    }
+
+    let mut packages = Vec::new();
+    walk(lockfile.get("dependencies"), &mut packages, &package);
+
+    packages
+}
+
+/// Looks for the `.go.buildinfo` section of an ELF binary without buffering the whole file.
+/// The section is 16 byte aligned, so only aligned offsets are checked.
+pub fn find_go_buildinfo(reader: &mut dyn Read) -> io::Result<Option<Vec<u8>>> {
+    let mut chunk = vec![0; 1 << 16];
+    let mut buffer = Vec::new();
+    let mut first = true;
+
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        if first {
+            if buffer.len() < ELF_MAGIC.len() {
+                continue;
+            }
+            if !buffer.starts_with(ELF_MAGIC) {
+                return Ok(None);
+            }
+            first = false;
+        }
+
+        let found = (0..buffer.len().saturating_sub(GO_BUILDINFO_MAGIC.len() - 1))
+            .step_by(16)
+            .find(|i| buffer[*i..].starts_with(GO_BUILDINFO_MAGIC));
+
+        if let Some(start) = found {
+            let mut info = buffer.split_off(start);
+            let remaining = GO_BUILDINFO_MAX_SIZE.saturating_sub(info.len() as u64);
+            reader.take(remaining).read_to_end(&mut info)?;
+            return Ok(Some(info));
+        }
+
+        // Keep an aligned tail long enough to hold a magic split across reads
+        let keep_from = buffer.len().saturating_sub(GO_BUILDINFO_MAGIC.len()) / 16 * 16;
+        buffer.drain(..keep_from);
+    }
+}
+
+/// Build info of Go 1.18+ binaries: a 32 byte header followed by the varint prefixed
+/// Go version and module info (`path`, `mod` and `dep` lines, `=>` for replacements).
+pub fn parse_go_buildinfo(info: &[u8]) -> Vec<Package> {
+    const INLINE_STRINGS: u8 = 0x2;
+
+    if info.len() < 32 || info[15] & INLINE_STRINGS == 0 {
+        return Vec::new();
+    }
+
+    let mut rest = &info[32..];
+    let (Some(go_version), Some(mut modinfo)) = (read_varint_string(&mut rest), read_varint_string(&mut rest)) else {
+        return Vec::new();
+    };
+    // Module info is wrapped in 16 byte sentinels
+    if modinfo.len() >= 33 && modinfo[modinfo.len() - 17] == b'\n' {
+        modinfo = &modinfo[16..modinfo.len() - 16];
+    }
+
+    let go_package = |name: &str, version: &str| Package {
+        name: name.to_string(),
+        version: version.to_string(),
+        package_type: PackageType::Go,
+        arch: None,
+        license: None,
+        source: None,
+        location: String::new(),
+        layer: None,
+    };
+
+    let mut packages = vec![go_package("stdlib", &String::from_utf8_lossy(go_version))];
+    for line in String::from_utf8_lossy(modinfo).lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["mod" | "dep", path, version, ..] => packages.push(go_package(path, version)),
+            ["=>", path, version, ..] => {
+                if let Some(last) = packages.last_mut() {
+                    *last = go_package(path, version);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    packages
+}
+
+fn read_varint_string<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let mut length: usize = 0;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        length |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            let value = data.get(i + 1..i + 1 + length)?;
+            *data = &data[i + 1 + length..];
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(packages: &[Package]) -> Vec<(&str, &str, Option<&str>, Option<&str>)> {
+        packages
+            .iter()
+            .map(|p| {
+                (
+                    p.name.as_str(),
+                    p.version.as_str(),
+                    p.arch.as_deref(),
+                    p.source.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_dpkg_status() {
+        let packages = parse_dpkg(include_bytes!("fixtures/dpkg-status"));
+
+        assert_eq!(
+            summary(&packages),
+            vec![
+                ("libc6", "2.36-9+deb12u7", Some("amd64"), Some("glibc")),
+                ("tzdata", "2024a-0+deb12u1", Some("all"), None),
+                ("base-files", "12.4+deb12u5", Some("amd64"), None),
+            ]
+        );
+        assert!(packages.iter().all(|p| p.package_type == PackageType::Deb));
+    }
+
+    #[test]
+    fn parses_apk_installed_database() {
+        let packages = parse_apk(include_bytes!("fixtures/apk-installed"));
+
+        assert_eq!(
+            summary(&packages),
+            vec![
+                ("musl", "1.2.4_git20230717-r4", Some("x86_64"), Some("musl")),
+                ("libcrypto3", "3.1.4-r5", Some("x86_64"), Some("openssl")),
+            ]
+        );
+        assert_eq!(packages[1].license.as_deref(), Some("Apache-2.0"));
+    }
+
+    #[test]
+    fn parses_rpm_database() {
+        let packages = parse_rpm(include_bytes!("fixtures/rpmdb.sqlite")).unwrap();
+
+        assert_eq!(
+            summary(&packages),
+            vec![
+                (
+                    "bash",
+                    "5.2.15-3.fc39",
+                    Some("x86_64"),
+                    Some("bash-5.2.15-3.fc39.src.rpm")
+                ),
+                (
+                    "openssl-libs",
+                    "1:3.1.1-4.fc39",
+                    Some("x86_64"),
+                    Some("openssl-3.1.1-4.fc39.src.rpm")
+                ),
+            ]
+        );
+        assert_eq!(packages[0].license.as_deref(), Some("GPL-3.0-or-later"));
+        assert!(parse_rpm(b"not a database").is_err());
+    }
+}
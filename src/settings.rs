@@ -0,0 +1,449 @@
+use crate::auth::{AccessPolicy, Grant};
+use crate::registries::{registry_prefix, validate_host, Registries, RegistryEntry};
+use crate::types::{Config, Operation};
+use envconfig::{Envconfig, Error as EnvError};
+use rocket::tokio;
+use rocket::tokio::signal::unix::{signal, SignalKind};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The optional TOML config file. Every key but `access` has an environment variable
+/// counterpart, and environment variables win over the file.
+///
+/// ```toml
+/// copying_allowed = true
+///
+/// [registry]
+/// host = "registry.example.com"
+/// deleting_allowed = true
+///
+/// [[registries]]
+/// id = "staging"
+/// host = "staging.example.com:5000"
+/// unsecured = true
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    registry: Option<FileRegistry>,
+    #[serde(default)]
+    registries: Vec<FileRegistry>,
+    copying_allowed: Option<bool>,
+    advisories_dir: Option<String>,
+    replication_file: Option<String>,
+    retention_file: Option<String>,
+    users_file: Option<String>,
+    session_hours: Option<u32>,
+    tokens_file: Option<String>,
+    token_max_days: Option<u32>,
+    proxy_user_header: Option<String>,
+    oidc: Option<FileOidc>,
+    /// Only in the file, there are no environment variables for grants
+    #[serde(default)]
+    access: Vec<Grant>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileRegistry {
+    id: Option<String>,
+    host: Option<String>,
+    name: Option<String>,
+    unsecured: Option<bool>,
+    http_basic_user: Option<String>,
+    http_basic_password: Option<String>,
+    deleting_allowed: Option<bool>,
+    retagging_allowed: Option<bool>,
+    pushing_allowed: Option<bool>,
+    garbage_collecting_allowed: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileOidc {
+    issuer: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_url: Option<String>,
+    scopes: Option<String>,
+    username_claim: Option<String>,
+    groups_claim: Option<String>,
+}
+
+/// Configuration and registries, reloaded from the file and the environment on `SIGHUP`
+/// or when the file changes. A reload that doesn't validate keeps the current settings.
+/// The advisory directory, the replication, retention and token files and where users come
+/// from are only read at startup, the users file itself is reread when it changes.
+#[derive(Clone)]
+pub struct Settings {
+    file: Option<PathBuf>,
+    config: Arc<RwLock<Arc<Config>>>,
+    access: Arc<RwLock<Arc<AccessPolicy>>>,
+    pub registries: Registries,
+}
+
+impl Settings {
+    /// `file` comes from `--config <path>` or `HARBUI_CONFIG`.
+    pub fn load(file: Option<PathBuf>) -> Result<Self, Vec<String>> {
+        let (config, registries, access) = load(file.as_deref())?;
+
+        Ok(Self {
+            file,
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            access: Arc::new(RwLock::new(Arc::new(access))),
+            registries: Registries::new(registries),
+        })
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn access(&self) -> Arc<AccessPolicy> {
+        self.access.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn reload(&self) {
+        let (config, registries, access) = match load(self.file.as_deref()) {
+            Ok(loaded) => loaded,
+            Err(errors) => {
+                for e in errors {
+                    error!("Invalid configuration: {}", e);
+                }
+                warn!("Keeping the current configuration");
+                return;
+            }
+        };
+
+        let current = self.config();
+        if config.advisories_dir != current.advisories_dir
+            || config.replication_file != current.replication_file
+            || config.retention_file != current.retention_file
+            || config.users_file != current.users_file
+            || config.session_hours != current.session_hours
+            || config.tokens_file != current.tokens_file
+            || config.proxy_user_header != current.proxy_user_header
+            || config.oidc_issuer != current.oidc_issuer
+            || config.oidc_client_id != current.oidc_client_id
+            || config.oidc_client_secret != current.oidc_client_secret
+            || config.oidc_redirect_url != current.oidc_redirect_url
+        {
+            warn!(
+                "Advisory directory, replication, retention, users file, session, API tokens file and OpenID Connect changes only apply after a restart"
+            );
+        }
+
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+        *self.access.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(access);
+        self.registries.replace(registries);
+        info!("Configuration reloaded");
+    }
+
+    /// Reloads on `SIGHUP` and when the modification time of the config file changes.
+    pub fn watch(&self) {
+        let settings = self.clone();
+        tokio::spawn(async move {
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    error!("Can't listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            loop {
+                hangups.recv().await;
+                info!("SIGHUP received, reloading configuration");
+                settings.reload();
+            }
+        });
+
+        let Some(file) = self.file.clone() else {
+            return;
+        };
+        let settings = self.clone();
+        tokio::spawn(async move {
+            let modified = |file: &Path| fs::metadata(file).and_then(|m| m.modified()).ok();
+            let mut last: Option<SystemTime> = modified(&file);
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                let current = modified(&file);
+                if current != last {
+                    last = current;
+                    info!("{} changed, reloading configuration", file.display());
+                    settings.reload();
+                }
+            }
+        });
+    }
+}
+
+fn load(file: Option<&Path>) -> Result<(Config, Vec<RegistryEntry>, AccessPolicy), Vec<String>> {
+    let (mut vars, grants) = match file {
+        Some(file) => file_vars(file).map_err(|e| vec![e])?,
+        None => (HashMap::new(), Vec::new()),
+    };
+    vars.extend(env::vars());
+
+    let config = Config::init_from_hashmap(&vars).map_err(|e| vec![describe(&e, &vars)])?;
+    let mut errors = validate(&config);
+
+    let registries = match Registries::build(&config, &vars) {
+        Ok(registries) => registries,
+        Err(registry_errors) => {
+            errors.extend(registry_errors);
+            return Err(errors);
+        }
+    };
+    errors.extend(validate_grants(&grants, &registries));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok((config, registries, AccessPolicy::new(grants)))
+}
+
+fn validate(config: &Config) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if let Err(e) = validate_host(&config.host) {
+        errors.push(format!("REGISTRY_HOST (registry.host): {}", e));
+    }
+    if let Some(dir) = &config.advisories_dir {
+        if !Path::new(dir).is_dir() {
+            errors.push(format!(
+                "HARBUI_ADVISORIES_DIR (advisories_dir): {} is not a directory",
+                dir
+            ));
+        }
+    }
+    let files = [
+        ("HARBUI_REPLICATION_FILE (replication_file)", &config.replication_file),
+        ("HARBUI_RETENTION_FILE (retention_file)", &config.retention_file),
+        ("HARBUI_TOKENS_FILE (tokens_file)", &config.tokens_file),
+    ];
+    for (name, file) in files {
+        let Some(file) = file else {
+            continue;
+        };
+        let parent = Path::new(file).parent().filter(|p| !p.as_os_str().is_empty());
+        if parent.is_some_and(|parent| !parent.is_dir()) {
+            errors.push(format!("{}: the directory of {} doesn't exist", name, file));
+        }
+    }
+
+    if let Some(file) = &config.users_file {
+        if !Path::new(file).is_file() {
+            errors.push(format!("HARBUI_USERS_FILE (users_file): {} is not a file", file));
+        }
+    }
+    if let Some(url) = &config.oidc_redirect_url {
+        if reqwest::Url::parse(url).is_err() {
+            errors.push(format!(
+                "HARBUI_OIDC_REDIRECT_URL (oidc.redirect_url): {} is not a URL",
+                url
+            ));
+        }
+    }
+    if config.session_hours == 0 {
+        errors.push("HARBUI_SESSION_HOURS (session_hours): must be at least 1".to_string());
+    }
+    if config.token_max_days == 0 {
+        errors.push("HARBUI_TOKEN_MAX_DAYS (token_max_days): must be at least 1".to_string());
+    }
+
+    errors
+}
+
+fn validate_grants(grants: &[Grant], registries: &[RegistryEntry]) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for (index, grant) in grants.iter().enumerate() {
+        if grant.users.is_empty() && grant.groups.is_empty() {
+            errors.push(format!(
+                "access[{}]: neither users nor groups, use users = [\"*\"] for everyone",
+                index
+            ));
+        }
+        if grant.repositories.is_empty() {
+            errors.push(format!(
+                "access[{}]: repositories is empty, leave it out for all of them",
+                index
+            ));
+        }
+        for id in &grant.registries {
+            if !registries.iter().any(|registry| registry.id == *id) {
+                errors.push(format!("access[{}]: unknown registry {}", index, id));
+            }
+        }
+    }
+
+    errors
+}
+
+impl FileRegistry {
+    fn switches(&self) -> [(Operation, Option<bool>); 4] {
+        [
+            (Operation::Delete, self.deleting_allowed),
+            (Operation::Retag, self.retagging_allowed),
+            (Operation::Push, self.pushing_allowed),
+            (Operation::GarbageCollect, self.garbage_collecting_allowed),
+        ]
+    }
+}
+
+/// The file as the environment variables it stands for, and its grants.
+fn file_vars(file: &Path) -> Result<(HashMap<String, String>, Vec<Grant>), String> {
+    let content = fs::read_to_string(file).map_err(|e| format!("Can't read {}: {}", file.display(), e))?;
+    let parsed: FileConfig = toml::from_str(&content).map_err(|e| format!("{}: {}", file.display(), e))?;
+
+    let mut vars = HashMap::new();
+    let mut set = |name: String, value: Option<String>| {
+        if let Some(value) = value {
+            vars.insert(name, value);
+        }
+    };
+
+    if let Some(registry) = parsed.registry {
+        if registry.id.is_some() {
+            return Err(format!(
+                "{}: [registry] is the default registry and takes no id",
+                file.display()
+            ));
+        }
+        for (operation, allowed) in registry.switches() {
+            set(format!("HARBUI_{}", operation.switch()), allowed.map(|v| v.to_string()));
+        }
+        set("REGISTRY_HOST".into(), registry.host);
+        set("REGISTRY_NAME".into(), registry.name);
+        set("REGISTRY_UNSECURED".into(), registry.unsecured.map(|v| v.to_string()));
+        set("REGISTRY_HTTP_BASIC_USER".into(), registry.http_basic_user);
+        set("REGISTRY_HTTP_BASIC_PASSWORD".into(), registry.http_basic_password);
+    }
+    set(
+        "HARBUI_COPYING_ALLOWED".into(),
+        parsed.copying_allowed.map(|v| v.to_string()),
+    );
+    set("HARBUI_ADVISORIES_DIR".into(), parsed.advisories_dir);
+    set("HARBUI_REPLICATION_FILE".into(), parsed.replication_file);
+    set("HARBUI_RETENTION_FILE".into(), parsed.retention_file);
+    set("HARBUI_USERS_FILE".into(), parsed.users_file);
+    set("HARBUI_PROXY_USER_HEADER".into(), parsed.proxy_user_header);
+    if let Some(oidc) = parsed.oidc {
+        set("HARBUI_OIDC_ISSUER".into(), oidc.issuer);
+        set("HARBUI_OIDC_CLIENT_ID".into(), oidc.client_id);
+        set("HARBUI_OIDC_CLIENT_SECRET".into(), oidc.client_secret);
+        set("HARBUI_OIDC_REDIRECT_URL".into(), oidc.redirect_url);
+        set("HARBUI_OIDC_SCOPES".into(), oidc.scopes);
+        set("HARBUI_OIDC_USERNAME_CLAIM".into(), oidc.username_claim);
+        set("HARBUI_OIDC_GROUPS_CLAIM".into(), oidc.groups_claim);
+    }
+    set(
+        "HARBUI_SESSION_HOURS".into(),
+        parsed.session_hours.map(|v| v.to_string()),
+    );
+    set("HARBUI_TOKENS_FILE".into(), parsed.tokens_file);
+    set(
+        "HARBUI_TOKEN_MAX_DAYS".into(),
+        parsed.token_max_days.map(|v| v.to_string()),
+    );
+
+    let mut ids = Vec::new();
+    for (index, registry) in parsed.registries.into_iter().enumerate() {
+        let Some(id) = registry.id.clone() else {
+            return Err(format!("{}: registries[{}] has no id", file.display(), index));
+        };
+        let prefix = registry_prefix(&id);
+
+        for (operation, allowed) in registry.switches() {
+            set(
+                format!("{}{}", prefix, operation.switch()),
+                allowed.map(|v| v.to_string()),
+            );
+        }
+        set(format!("{}HOST", prefix), registry.host);
+        set(format!("{}NAME", prefix), registry.name);
+        set(
+            format!("{}UNSECURED", prefix),
+            registry.unsecured.map(|v| v.to_string()),
+        );
+        set(format!("{}HTTP_BASIC_USER", prefix), registry.http_basic_user);
+        set(format!("{}HTTP_BASIC_PASSWORD", prefix), registry.http_basic_password);
+        ids.push(id);
+    }
+    if !ids.is_empty() {
+        set("HARBUI_REGISTRIES".into(), Some(ids.join(",")));
+    }
+
+    Ok((vars, parsed.access))
+}
+
+fn describe(error: &EnvError, vars: &HashMap<String, String>) -> String {
+    match error {
+        EnvError::EnvVarMissing { name: "REGISTRY_HOST" } => {
+            "REGISTRY_HOST (or registry.host in the config file) is required".to_string()
+        }
+        EnvError::EnvVarMissing { name } => format!("{} is required", name),
+        EnvError::ParseError { name } => format!(
+            "{} must be {}, not {:?}",
+            name,
+            expected(name),
+            vars.get(*name).map(String::as_str).unwrap_or_default()
+        ),
+    }
+}
+
+/// What the variables of [`Config`] that aren't plain strings hold.
+fn expected(name: &str) -> &'static str {
+    match name {
+        "HARBUI_SESSION_HOURS" => "a whole number of hours",
+        "HARBUI_TOKEN_MAX_DAYS" => "a whole number of days",
+        "REGISTRY_UNSECURED"
+        | "HARBUI_DELETING_ALLOWED"
+        | "HARBUI_COPYING_ALLOWED"
+        | "HARBUI_RETAGGING_ALLOWED"
+        | "HARBUI_PUSHING_ALLOWED"
+        | "HARBUI_GARBAGE_COLLECTING_ALLOWED" => "true or false",
+        _ => "a valid value",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(name: &str, value: &str) -> String {
+        let vars = HashMap::from([
+            ("REGISTRY_HOST".to_string(), "registry.example.com".to_string()),
+            (name.to_string(), value.to_string()),
+        ]);
+        let error = Config::init_from_hashmap(&vars).err().expect("invalid value accepted");
+
+        describe(&error, &vars)
+    }
+
+    #[test]
+    fn describes_parse_errors_with_type_and_value() {
+        assert_eq!(
+            error("HARBUI_SESSION_HOURS", "12h"),
+            r#"HARBUI_SESSION_HOURS must be a whole number of hours, not "12h""#
+        );
+        assert_eq!(
+            error("HARBUI_TOKEN_MAX_DAYS", "-1"),
+            r#"HARBUI_TOKEN_MAX_DAYS must be a whole number of days, not "-1""#
+        );
+        assert_eq!(
+            error("HARBUI_DELETING_ALLOWED", "yes"),
+            r#"HARBUI_DELETING_ALLOWED must be true or false, not "yes""#
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Lists kept in a JSON file: replication rules, retention policies and API tokens.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[derive(Debug)]
+pub struct StoreError(String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Items kept in a file when one is given and only in memory otherwise. Changes are saved
+/// before they are kept, so that memory never holds what a restart would lose.
+pub struct JsonStore<T> {
+    file: Option<PathBuf>,
+    items: RwLock<Vec<T>>,
+    /// Held while writing the file, so that writes land in the order of the changes
+    saving: Mutex<()>,
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> JsonStore<T> {
+    /// Fails on a file that can't be read, starting without its items would lose them on the
+    /// next save. `what` names them in the message.
+    pub fn open(what: &str, file: Option<String>) -> Result<Self, String> {
+        let file = file.map(PathBuf::from);
+        let items = match &file {
+            Some(file) => load(file).map_err(|e| format!("Can't load {} from {}: {}", what, file.display(), e))?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            file,
+            items: RwLock::new(items),
+            saving: Mutex::new(()),
+        })
+    }
+
+    /// Items that are never saved, like job reports.
+    pub fn in_memory() -> Self {
+        Self {
+            file: None,
+            items: RwLock::new(Vec::new()),
+            saving: Mutex::new(()),
+        }
+    }
+
+    // Updates are single assignments, so a lock poisoned by a panic elsewhere is still consistent
+    pub fn read(&self) -> RwLockReadGuard<'_, Vec<T>> {
+        self.items.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Vec<T>> {
+        self.items.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies `change` to a copy of the items and keeps it once saved. Nothing changes when
+    /// `change` fails.
+    pub fn update<R, E: From<StoreError>>(&self, change: impl FnOnce(&mut Vec<T>) -> Result<R, E>) -> Result<R, E> {
+        let _saving = self.saving.lock().unwrap_or_else(|e| e.into_inner());
+        let mut items = self.read().clone();
+        let result = change(&mut items)?;
+        self.write_file(&items)?;
+        *self.write() = items;
+
+        Ok(result)
+    }
+
+    /// Changes only worth saving along with others, like times of use. They are kept in memory
+    /// until the next [`save`](Self::save) or [`update`](Self::update).
+    pub fn update_unsaved(&self, change: impl FnOnce(&mut Vec<T>)) {
+        change(&mut self.write());
+    }
+
+    pub fn save(&self) -> Result<(), StoreError> {
+        let _saving = self.saving.lock().unwrap_or_else(|e| e.into_inner());
+        let items = self.read().clone();
+
+        self.write_file(&items)
+    }
+
+    fn write_file(&self, items: &[T]) -> Result<(), StoreError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        // Written aside first, a crash mid-write must not lose the items
+        let partial = file.with_extension("partial");
+        serde_json::to_vec_pretty(items)
+            .map_err(|e| e.to_string())
+            .and_then(|data| fs::write(&partial, data).map_err(|e| e.to_string()))
+            .and_then(|_| fs::rename(&partial, file).map_err(|e| e.to_string()))
+            .map_err(StoreError)
+    }
+}
+
+fn load<T: DeserializeOwned>(file: &PathBuf) -> Result<Vec<T>, String> {
+    match fs::read(file) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file() -> PathBuf {
+        std::env::temp_dir().join(format!("harbui-store-{}.json", uuid::Uuid::new_v4().simple()))
+    }
+
+    fn open(file: &std::path::Path) -> Result<JsonStore<String>, String> {
+        JsonStore::open("names", Some(file.to_string_lossy().to_string()))
+    }
+
+    #[test]
+    fn keeps_changes_once_saved() {
+        let file = file();
+        let store = open(&file).unwrap();
+
+        store
+            .update(|names| {
+                names.push("a".to_string());
+                Ok::<_, StoreError>(())
+            })
+            .unwrap();
+        assert_eq!(*open(&file).unwrap().read(), vec!["a"]);
+
+        let failed: Result<(), StoreError> = store.update(|names| {
+            names.push("b".to_string());
+            Err(StoreError("refused".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(*store.read(), vec!["a"]);
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn keeps_nothing_it_can_not_save() {
+        let file = std::env::temp_dir().join("harbui-missing-directory").join("names.json");
+        let store = open(&file).unwrap();
+
+        let saved = store.update(|names| {
+            names.push("a".to_string());
+            Ok::<_, StoreError>(())
+        });
+        assert!(saved.is_err());
+        assert!(store.read().is_empty());
+    }
+
+    #[test]
+    fn refuses_unreadable_files() {
+        let file = file();
+        fs::write(&file, "{not json").unwrap();
+
+        let error = open(&file).err().unwrap();
+        assert!(error.starts_with("Can't load names from"), "{}", error);
+        fs::remove_file(&file).unwrap();
+    }
+}
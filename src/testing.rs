@@ -0,0 +1,322 @@
+//! A tiny HTTP server standing in for registries, token servers and identity providers in tests.
+
+use crate::registry_api::{Config, RegistryClient};
+use reqwest::Url;
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::{self};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub struct Request {
+    pub method: String,
+    /// With the query
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `http://<host>` of the server, to point at itself in answers.
+    pub fn origin(&self) -> String {
+        format!("http://{}", self.header("host").unwrap_or_default())
+    }
+}
+
+pub struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self::status(status)
+            .header("Content-Type", "application/json")
+            .body(body.to_string().into_bytes())
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+}
+
+/// Answers every request with `handler` until the test ends, returns `127.0.0.1:<port>`.
+pub async fn serve<F>(handler: F) -> String
+where
+    F: Fn(&Request) -> Reply + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let _ = answer(stream, &*handler).await;
+            });
+        }
+    });
+
+    address
+}
+
+async fn answer<F>(mut stream: TcpStream, handler: &F) -> std::io::Result<()>
+where
+    F: Fn(&Request) -> Reply,
+{
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+    let head_end = loop {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        data.extend_from_slice(&buffer[..read]);
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: data[head_end..].to_vec(),
+    };
+    let length = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    while request.body.len() < length {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.body.extend_from_slice(&buffer[..read]);
+    }
+
+    let reply = handler(&request);
+    let mut response = format!(
+        "HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.write_all(&reply.body).await?;
+    stream.shutdown().await
+}
+
+const MANIFEST_TYPE: &str = "application/vnd.docker.distribution.manifest.v2+json";
+
+/// A registry keeping blobs by `<repository>@<digest>` and manifests by `<repository>:<reference>`,
+/// enough to copy images into and out of it.
+#[derive(Default)]
+pub struct FakeRegistry {
+    pub blobs: HashMap<String, Vec<u8>>,
+    pub manifests: HashMap<String, Vec<u8>>,
+    pub uploads: Vec<Vec<u8>>,
+    pub mountable: bool,
+    /// Numbers of the upload `PATCH` and `PUT` requests answered with a 503 instead of being accepted
+    pub failing_uploads: Vec<usize>,
+    pub upload_requests: usize,
+    /// `<method> <path> <Range or Content-Range>` of every request
+    pub log: Vec<String>,
+}
+
+impl FakeRegistry {
+    fn answer(&mut self, req: &Request) -> Reply {
+        let url = Url::parse(&format!("http://registry{}", req.path)).unwrap();
+        let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let range = req.header("range").or(req.header("content-range")).unwrap_or_default();
+        self.log.push(
+            format!("{} {} {}", req.method, url.path(), range)
+                .trim_end()
+                .to_string(),
+        );
+
+        let path = url.path().trim_start_matches("/v2/");
+        if let Some((repository, upload)) = path.split_once("/blobs/uploads/") {
+            return self.upload(req, repository, upload, &query);
+        }
+        if let Some((repository, digest)) = path.split_once("/blobs/") {
+            let Some(blob) = self.blobs.get(&format!("{}@{}", repository, digest)) else {
+                return Reply::status(404);
+            };
+            let offset = range.trim_start_matches("bytes=").trim_end_matches('-');
+            return match (req.method.as_str(), offset.parse::<usize>()) {
+                ("HEAD", _) => Reply::status(200),
+                (_, Ok(offset)) => Reply::status(206).body(blob[offset..].to_vec()),
+                _ => Reply::status(200).body(blob.clone()),
+            };
+        }
+        if let Some(repository) = path.strip_suffix("/tags/list") {
+            let prefix = format!("{}:", repository);
+            let mut tags: Vec<&str> = self
+                .manifests
+                .keys()
+                .filter_map(|key| key.strip_prefix(&prefix))
+                .filter(|reference| !reference.starts_with("sha256:"))
+                .collect();
+            tags.sort();
+            return Reply::json(200, &json!({"name": repository, "tags": tags}));
+        }
+
+        let (repository, reference) = path.split_once("/manifests/").unwrap();
+        if req.method == "PUT" {
+            let digest = sha256_digest(&req.body);
+            self.manifests
+                .insert(format!("{}:{}", repository, reference), req.body.clone());
+            self.manifests
+                .insert(format!("{}:{}", repository, digest), req.body.clone());
+            return Reply::status(201).header("Docker-Content-Digest", &digest);
+        }
+        match self.manifests.get(&format!("{}:{}", repository, reference)) {
+            Some(manifest) => Reply::status(200)
+                .header("Content-Type", MANIFEST_TYPE)
+                .header("Docker-Content-Digest", &sha256_digest(manifest))
+                .body(manifest.clone()),
+            None => Reply::status(404),
+        }
+    }
+
+    fn upload(&mut self, req: &Request, repository: &str, upload: &str, query: &HashMap<String, String>) -> Reply {
+        let session = |status: u16, id: usize, size: usize| {
+            Reply::status(status)
+                .header("Location", &format!("/v2/{}/blobs/uploads/{}", repository, id))
+                .header("Range", &format!("0-{}", size.saturating_sub(1)))
+        };
+
+        if req.method == "POST" {
+            if let (Some(digest), Some(from)) = (query.get("mount"), query.get("from")) {
+                if !self.mountable {
+                    return Reply::json(405, &json!({"errors": [{"code": "UNSUPPORTED", "message": "no"}]}));
+                }
+                let blob = self.blobs[&format!("{}@{}", from, digest)].clone();
+                self.blobs.insert(format!("{}@{}", repository, digest), blob);
+                return Reply::status(201);
+            }
+            self.uploads.push(Vec::new());
+            return session(202, self.uploads.len() - 1, 0);
+        }
+
+        let id: usize = upload.parse().unwrap();
+        if req.method == "GET" {
+            return session(204, id, self.uploads[id].len());
+        }
+        self.upload_requests += 1;
+        if self.failing_uploads.contains(&self.upload_requests) {
+            return Reply::status(503);
+        }
+        if req.method == "PATCH" {
+            let start = req.header("content-range").unwrap().split('-').next().unwrap();
+            if start.parse::<usize>().unwrap() != self.uploads[id].len() {
+                return Reply::status(416);
+            }
+            self.uploads[id].extend_from_slice(&req.body);
+            return session(202, id, self.uploads[id].len());
+        }
+
+        let mut blob = self.uploads[id].clone();
+        blob.extend_from_slice(&req.body);
+        let digest = &query["digest"];
+        if sha256_digest(&blob) != *digest {
+            return Reply::json(400, &json!({"errors": [{"code": "DIGEST_INVALID", "message": "no"}]}));
+        }
+        self.blobs.insert(format!("{}@{}", repository, digest), blob);
+        Reply::status(201)
+    }
+
+    /// Adds an image made of `layer` as `<repository>:<tag>`, returns the manifest digest.
+    pub fn push(&mut self, repository: &str, tag: &str, layer: &[u8]) -> String {
+        let config = b"{}".to_vec();
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_TYPE,
+            "config": {
+                "mediaType": "application/vnd.docker.container.image.v1+json",
+                "size": config.len(),
+                "digest": sha256_digest(&config),
+            },
+            "layers": [{
+                "mediaType": "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                "size": layer.len(),
+                "digest": sha256_digest(layer),
+            }],
+        })
+        .to_string()
+        .into_bytes();
+
+        let digest = sha256_digest(&manifest);
+        self.blobs
+            .insert(format!("{}@{}", repository, sha256_digest(&config)), config);
+        self.blobs
+            .insert(format!("{}@{}", repository, sha256_digest(layer)), layer.to_vec());
+        self.manifests
+            .insert(format!("{}:{}", repository, tag), manifest.clone());
+        self.manifests.insert(format!("{}:{}", repository, digest), manifest);
+        digest
+    }
+}
+
+pub fn sha256_digest(content: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(content))
+}
+
+/// Serves `registry`, returns its address and the registry to look into afterwards.
+pub async fn serve_registry(registry: FakeRegistry) -> (String, Arc<Mutex<FakeRegistry>>) {
+    let registry = Arc::new(Mutex::new(registry));
+    let shared = registry.clone();
+    let address = serve(move |req| shared.lock().unwrap().answer(req)).await;
+
+    (address, registry)
+}
+
+/// An anonymous client for a stub at `address`.
+pub fn registry_client(address: String) -> RegistryClient {
+    RegistryClient::new(&Config {
+        base_uri: address,
+        is_secured: false,
+        http_basic_user: None,
+        http_basic_pass: None,
+    })
+}
@@ -1,10 +1,13 @@
 use envconfig::Envconfig;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Envconfig, Clone)]
 pub struct Config {
     #[envconfig(from = "REGISTRY_HOST")]
     pub host: String,
+    #[envconfig(from = "REGISTRY_NAME")]
+    pub registry_name: Option<String>,
     #[envconfig(from = "REGISTRY_UNSECURED", default = "false")]
     pub unsecured: bool,
     #[envconfig(from = "REGISTRY_HTTP_BASIC_USER")]
@@ -13,8 +16,114 @@ pub struct Config {
     pub http_basic_pass: Option<String>,
     #[envconfig(from = "HARBUI_DELETING_ALLOWED", default = "false")]
     pub deleting_allowed: bool,
+    /// Default for retagging and pushing when their own switches aren't set
+    #[envconfig(from = "HARBUI_COPYING_ALLOWED", default = "false")]
+    pub copying_allowed: bool,
+    #[envconfig(from = "HARBUI_RETAGGING_ALLOWED")]
+    pub retagging_allowed: Option<bool>,
+    #[envconfig(from = "HARBUI_PUSHING_ALLOWED")]
+    pub pushing_allowed: Option<bool>,
+    #[envconfig(from = "HARBUI_GARBAGE_COLLECTING_ALLOWED", default = "false")]
+    pub garbage_collecting_allowed: bool,
     #[envconfig(from = "HARBUI_VERSION", default = "dev")]
     pub version: String,
+    #[envconfig(from = "HARBUI_ADVISORIES_DIR")]
+    pub advisories_dir: Option<String>,
+    #[envconfig(from = "HARBUI_REPLICATION_FILE")]
+    pub replication_file: Option<String>,
+    #[envconfig(from = "HARBUI_RETENTION_FILE")]
+    pub retention_file: Option<String>,
+    /// htpasswd file of the users allowed to log in, everyone is let in without it
+    #[envconfig(from = "HARBUI_USERS_FILE")]
+    pub users_file: Option<String>,
+    #[envconfig(from = "HARBUI_SESSION_HOURS", default = "12")]
+    pub session_hours: u32,
+    /// JSON file keeping the API tokens, which are lost on restart without it
+    #[envconfig(from = "HARBUI_TOKENS_FILE")]
+    pub tokens_file: Option<String>,
+    /// Longest lifetime of an API token, and the lifetime of those created without one
+    #[envconfig(from = "HARBUI_TOKEN_MAX_DAYS", default = "90")]
+    pub token_max_days: u32,
+    /// Header naming the user, set by a reverse proxy that authenticated them
+    #[envconfig(from = "HARBUI_PROXY_USER_HEADER")]
+    pub proxy_user_header: Option<String>,
+    /// OpenID Connect logins, discovered from `<issuer>/.well-known/openid-configuration`
+    #[envconfig(from = "HARBUI_OIDC_ISSUER")]
+    pub oidc_issuer: Option<String>,
+    #[envconfig(from = "HARBUI_OIDC_CLIENT_ID")]
+    pub oidc_client_id: Option<String>,
+    #[envconfig(from = "HARBUI_OIDC_CLIENT_SECRET")]
+    pub oidc_client_secret: Option<String>,
+    /// Where the provider sends users back, `<harbui URL>/api/oidc/callback`
+    #[envconfig(from = "HARBUI_OIDC_REDIRECT_URL")]
+    pub oidc_redirect_url: Option<String>,
+    #[envconfig(from = "HARBUI_OIDC_SCOPES", default = "openid profile email groups")]
+    pub oidc_scopes: String,
+    /// Users may change claims like `preferred_username` at some providers, `sub` never changes
+    #[envconfig(from = "HARBUI_OIDC_USERNAME_CLAIM", default = "sub")]
+    pub oidc_username_claim: String,
+    #[envconfig(from = "HARBUI_OIDC_GROUPS_CLAIM", default = "groups")]
+    pub oidc_groups_claim: String,
+    /// Signs and encrypts the session cookies
+    #[envconfig(from = "SECRET_KEY")]
+    pub secret_key: Option<String>,
+    #[envconfig(from = "HARBUI_REGISTRIES")]
+    pub registries: Option<String>,
+}
+
+/// Operations changing a registry, each behind its own switch: `HARBUI_<SWITCH>` for the
+/// default registry and `HARBUI_REGISTRY_<ID>_<SWITCH>` for the others.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// Deleting tags and manifests, by hand, in bulk or by retention policies
+    Delete,
+    /// Tagging an image again within its repository
+    Retag,
+    /// Writing images copied from elsewhere: promotion and replication
+    Push,
+    /// Reported for UIs driving the registry's own garbage collection, no harbui route needs it
+    GarbageCollect,
+}
+
+impl Operation {
+    pub fn switch(&self) -> &'static str {
+        match self {
+            Operation::Delete => "DELETING_ALLOWED",
+            Operation::Retag => "RETAGGING_ALLOWED",
+            Operation::Push => "PUSHING_ALLOWED",
+            Operation::GarbageCollect => "GARBAGE_COLLECTING_ALLOWED",
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Operation::Delete => "Deleting images",
+            Operation::Retag => "Retagging images",
+            Operation::Push => "Pushing images",
+            Operation::GarbageCollect => "Garbage collection",
+        }
+    }
+}
+
+/// What a registry allows, reported by `/api/config` so the UI hides what would be refused.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Permissions {
+    pub delete: bool,
+    pub retag: bool,
+    pub push: bool,
+    pub garbage_collect: bool,
+}
+
+impl Permissions {
+    pub fn allows(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::Delete => self.delete,
+            Operation::Retag => self.retag,
+            Operation::Push => self.push,
+            Operation::GarbageCollect => self.garbage_collect,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -23,6 +132,15 @@ pub struct ImageTags {
     pub tags: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct NamespaceNode {
+    pub name: String,
+    pub path: String,
+    pub is_repository: bool,
+    pub repositories_count: usize,
+    pub children: Vec<NamespaceNode>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct ImageManifest {
     pub digest: String,
@@ -30,10 +148,395 @@ pub struct ImageManifest {
     pub total_size: u64,
     pub os: String,
     pub architecture: String,
+    pub layers: Vec<ImageLayer>,
+    pub vulnerabilities: Option<SeverityCounts>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImageLayer {
+    pub digest: Option<String>,
+    pub media_type: Option<String>,
+    pub compression: Option<String>,
+    pub distributable: bool,
+    pub size: u64,
+    pub created: Option<String>,
+    pub created_by: Option<String>,
+    pub comment: Option<String>,
+    pub empty_layer: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DockerfileInstruction {
+    pub instruction: Option<String>,
+    pub created_by: Option<String>,
+    pub digest: Option<String>,
+    pub size: u64,
+    pub empty_layer: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LayerRef {
+    pub digest: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ValueChange<T> {
+    pub from: Option<T>,
+    pub to: Option<T>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MapDiff {
+    pub added: BTreeMap<String, String>,
+    pub removed: BTreeMap<String, String>,
+    pub changed: BTreeMap<String, ValueChange<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HistoryDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PlatformDiff {
+    pub from_platform: String,
+    pub to_platform: String,
+    pub from_digest: String,
+    pub to_digest: String,
+    pub shared_layers: Vec<LayerRef>,
+    pub removed_layers: Vec<LayerRef>,
+    pub added_layers: Vec<LayerRef>,
+    pub from_size: u64,
+    pub to_size: u64,
+    pub size_delta: i64,
+    pub env: MapDiff,
+    pub labels: MapDiff,
+    pub entrypoint: Option<ValueChange<Vec<String>>>,
+    pub cmd: Option<ValueChange<Vec<String>>>,
+    pub history: HistoryDiff,
+    /// Versions by `<type>/<name>`, only when both inventories were already extracted
+    pub packages: Option<MapDiff>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    #[default]
+    Directory,
+    Symlink,
+    Hardlink,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FileEntry {
+    pub path: String,
+    pub kind: FileKind,
+    pub size: u64,
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub link_target: Option<String>,
+    pub whiteout: bool,
+    pub layer: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FileNode {
+    pub name: String,
+    #[serde(flatten)]
+    pub entry: FileEntry,
+    pub children: Vec<FileNode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageType {
+    Deb,
+    Apk,
+    Rpm,
+    Python,
+    Go,
+    Npm,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub package_type: PackageType,
+    pub arch: Option<String>,
+    pub license: Option<String>,
+    pub source: Option<String>,
+    pub location: String,
+    pub layer: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OsRelease {
+    pub id: String,
+    pub version_id: Option<String>,
+    pub pretty_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum SeverityLevel {
+    #[default]
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Vulnerability {
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+    pub severity: SeverityLevel,
+    pub score: Option<f64>,
+    pub package: String,
+    pub version: String,
+    pub package_type: PackageType,
+    pub location: String,
+    pub fixed_versions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SeverityCounts {
+    pub critical: usize,
+    pub high: usize,
+    pub medium: usize,
+    pub low: usize,
+    pub unknown: usize,
+    pub total: usize,
+}
+
+impl SeverityCounts {
+    pub fn of(vulnerabilities: &[Vulnerability]) -> Self {
+        let mut counts = Self::default();
+        for vulnerability in vulnerabilities {
+            let count = match vulnerability.severity {
+                SeverityLevel::Critical => &mut counts.critical,
+                SeverityLevel::High => &mut counts.high,
+                SeverityLevel::Medium => &mut counts.medium,
+                SeverityLevel::Low => &mut counts.low,
+                SeverityLevel::Unknown => &mut counts.unknown,
+            };
+            *count += 1;
+            counts.total += 1;
+        }
+
+        counts
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CopyReport {
+    pub digest: String,
+    pub manifests: usize,
+    pub blobs_mounted: usize,
+    pub blobs_copied: usize,
+    pub blobs_skipped: usize,
+    pub bytes_copied: u64,
+}
+
+/// Another registry replication rules copy from or to. An omitted password on update keeps
+/// the current one, and passwords are never sent back.
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
-pub struct Manifests {
+pub struct RemoteRegistry {
+    pub host: String,
+    #[serde(default)]
+    pub unsecured: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationDirection {
+    /// From the configured registry to the remote one
+    Push,
+    /// From the remote registry to the configured one
+    Pull,
+}
+
+/// Repository and tag patterns accept `*` (any characters, `/` included) and `?`.
+/// No tag patterns means every tag.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplicationRule {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    /// The harbui registry replicated from or to, the default one when omitted
+    pub registry: Option<String>,
+    pub remote: RemoteRegistry,
+    pub direction: ReplicationDirection,
+    pub repositories: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Prepended to the repository names on the destination, e.g. `mirror` for `mirror/team/app`
+    pub destination_namespace: Option<String>,
+    /// Runs the rule periodically when set
+    pub interval_minutes: Option<u64>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// When a job last started, kept by harbui so restarts don't run the rule again early
+    #[serde(default)]
+    pub last_run_at: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    /// Finished, but some images couldn't be copied
+    Partial,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobTrigger {
+    Manual,
+    Scheduled,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReplicationProgress {
+    pub repositories: usize,
+    pub images_total: usize,
+    pub images_copied: usize,
+    pub images_up_to_date: usize,
+    pub images_failed: usize,
+    pub manifests: usize,
+    pub blobs_copied: usize,
+    pub blobs_skipped: usize,
+    pub bytes_copied: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReplicationJob {
+    pub id: String,
+    pub rule_id: String,
+    pub rule_name: String,
+    pub trigger: JobTrigger,
+    pub status: JobStatus,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    /// The image being copied
+    pub current: Option<String>,
+    pub progress: ReplicationProgress,
+    pub errors: Vec<String>,
+}
+
+/// Tags of the matching repositories are deleted unless a keep rule holds: they match
+/// `keep_tags`, are among the `keep_last` newest, are younger than `older_than_days`, or share
+/// their digest with a kept tag. At least one of `keep_last` and `older_than_days` is required.
+/// Registries don't record push dates, so the image creation date stands for it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    /// The harbui registry cleaned up, the default one when omitted
+    pub registry: Option<String>,
+    /// Patterns like the replication ones, `*` and `?` as wildcards
+    pub repositories: Vec<String>,
+    pub keep_last: Option<usize>,
+    /// Regular expressions, e.g. `^v?\d+\.\d+\.\d+$` to keep releases
+    #[serde(default)]
+    pub keep_tags: Vec<String>,
+    pub older_than_days: Option<u64>,
+    /// Runs the policy periodically when set
+    pub interval_minutes: Option<u64>,
+    /// Runs only report what would be deleted until this is turned off
+    #[serde(default = "default_true")]
+    pub dry_run: bool,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// When a run last started, kept by harbui so restarts don't run the policy again early
+    #[serde(default)]
+    pub last_run_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    Keep,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetentionDecision {
+    pub repository: String,
     pub tag: String,
-    pub image_manifests: Vec<ImageManifest>,
+    pub digest: Option<String>,
+    pub created: Option<String>,
+    pub action: RetentionAction,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetentionRun {
+    pub id: String,
+    pub policy_id: String,
+    pub policy_name: String,
+    pub registry: String,
+    pub trigger: JobTrigger,
+    pub dry_run: bool,
+    pub status: JobStatus,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub repositories: usize,
+    pub tags_evaluated: usize,
+    /// Tags to delete, whether or not the run deleted them
+    pub tags_to_delete: usize,
+    pub tags_deleted: usize,
+    /// Every evaluated tag with the reason it is kept or deleted
+    pub decisions: Vec<RetentionDecision>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkDeleteStatus {
+    Deleted,
+    /// Dry run, the image resolved and would be deleted
+    WouldDelete,
+    /// Other tags point at the digest and `force` wasn't given
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BulkDeleteItem {
+    pub repository: String,
+    pub reference: String,
+    pub digest: Option<String>,
+    pub status: BulkDeleteStatus,
+    pub error: Option<String>,
+    /// Tags not asked for that point at the same digest and go with it
+    pub also_deleted: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BulkDeleteReport {
+    pub dry_run: bool,
+    /// Distinct manifests deleted, or to delete for a dry run
+    pub digests: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub warnings: Vec<String>,
+    pub items: Vec<BulkDeleteItem>,
 }
@@ -0,0 +1,51 @@
+//! Helpers shared by access rules, API tokens, replication and retention.
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Glob match where `*` spans any characters, `/` included, and `?` a single one.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether something run every `interval_minutes` is due again, right away when it never ran.
+/// Unreadable `last_run` times count as never.
+pub fn interval_elapsed(interval_minutes: Option<u64>, last_run: Option<&str>, now: OffsetDateTime) -> bool {
+    let Some(minutes) = interval_minutes else {
+        return false;
+    };
+    let last = last_run.and_then(|last| OffsetDateTime::parse(last, &Rfc3339).ok());
+
+    last.is_none_or(|last| now - last >= time::Duration::minutes(minutes as i64))
+}
+
+/// The current time as RFC 3339, as timestamps are stored.
+pub fn now() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()
+}
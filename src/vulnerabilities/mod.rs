@@ -0,0 +1,503 @@
+mod osv;
+mod severity;
+mod version;
+
+use crate::manager::PlatformManifest;
+use crate::registry_api::types::ClientError;
+use crate::registry_api::RegistryClient;
+use crate::sbom::{Inventory, InventoryCache};
+use crate::types::{OsRelease, Package, PackageType, SeverityCounts, Vulnerability};
+use osv::{Advisory, Affected, Event};
+use rocket::tokio::sync::Mutex;
+use rocket::tokio::task;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use version::VersionScheme;
+
+/// How often the advisory directory is checked for changes.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// OSV advisories loaded from a local directory, e.g. an extracted `osv.dev` ecosystem dump.
+/// The directory is reloaded when it (or one of its direct children) changes, so updating
+/// the database is just a matter of replacing it.
+pub struct AdvisoryDatabase {
+    directory: Option<PathBuf>,
+    loaded: Mutex<Option<(Arc<Advisories>, Instant)>>,
+}
+
+/// `(ecosystem, package name)` to the advisories and the index of the matching `affected` entry.
+type AdvisoryIndex = HashMap<(String, String), Vec<(Arc<Advisory>, usize)>>;
+
+pub struct Advisories {
+    fingerprint: Vec<SystemTime>,
+    count: usize,
+    index: AdvisoryIndex,
+}
+
+impl AdvisoryDatabase {
+    pub fn new(directory: Option<String>) -> Self {
+        Self {
+            directory: directory.map(PathBuf::from),
+            loaded: Mutex::new(None),
+        }
+    }
+
+    /// Returns the current advisories, or `None` when no directory is configured.
+    pub async fn advisories(&self) -> Option<Arc<Advisories>> {
+        let directory = self.directory.clone()?;
+        let mut loaded = self.loaded.lock().await;
+
+        if let Some((current, checked_at)) = loaded.as_ref() {
+            if checked_at.elapsed() < RELOAD_CHECK_INTERVAL {
+                return Some(current.clone());
+            }
+        }
+
+        let current = loaded.as_ref().map(|(current, _)| current.clone());
+        let advisories = task::spawn_blocking(move || {
+            let fingerprint = fingerprint(&directory);
+            match current {
+                Some(current) if current.fingerprint == fingerprint => current,
+                _ => Arc::new(Advisories::load(&directory, fingerprint)),
+            }
+        })
+        .await
+        .ok()?;
+
+        *loaded = Some((advisories.clone(), Instant::now()));
+
+        Some(advisories)
+    }
+}
+
+/// Modification times of the directory and its direct children, enough to notice
+/// the directory being replaced or an ecosystem folder being updated.
+fn fingerprint(directory: &Path) -> Vec<SystemTime> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut times: Vec<SystemTime> = modified(directory).into_iter().collect();
+
+    if let Ok(entries) = fs::read_dir(directory) {
+        let mut children: Vec<SystemTime> = entries
+            .flatten()
+            .filter_map(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+            .collect();
+        children.sort();
+        times.extend(children);
+    }
+
+    times
+}
+
+impl Advisories {
+    fn load(directory: &Path, fingerprint: Vec<SystemTime>) -> Self {
+        let mut advisories = Self {
+            fingerprint,
+            count: 0,
+            index: HashMap::new(),
+        };
+
+        let mut pending = vec![directory.to_path_buf()];
+        while let Some(path) = pending.pop() {
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error!("Can't read advisories from {}: {:?}", path.display(), e);
+                    continue;
+                }
+            };
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if path.extension().is_some_and(|e| e == "json") {
+                    advisories.load_file(&path);
+                }
+            }
+        }
+
+        info!("Loaded {} advisories from {}", advisories.count, directory.display());
+        advisories
+    }
+
+    fn load_file(&mut self, path: &Path) {
+        let parsed = fs::read(path).map_err(|e| e.to_string()).and_then(|data| {
+            // Single advisories or arrays of them
+            serde_json::from_slice::<Advisory>(&data)
+                .map(|a| vec![a])
+                .or_else(|_| serde_json::from_slice::<Vec<Advisory>>(&data))
+                .map_err(|e| e.to_string())
+        });
+
+        let advisories = match parsed {
+            Ok(advisories) => advisories,
+            Err(e) => {
+                warn!("Skipping advisory file {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        for advisory in advisories.into_iter().filter(|a| a.withdrawn.is_none()) {
+            let advisory = Arc::new(advisory);
+            self.count += 1;
+
+            for (index, affected) in advisory.affected.iter().enumerate() {
+                let Some(package) = &affected.package else {
+                    continue;
+                };
+                let ecosystem = package.ecosystem.split(':').next().unwrap_or_default();
+                let key = (ecosystem.to_string(), normalize_name(ecosystem, &package.name));
+
+                self.index.entry(key).or_default().push((advisory.clone(), index));
+            }
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn matches(&self, package: &Package, os: Option<&OsRelease>) -> Vec<Vulnerability> {
+        let Some(target) = Target::of(package, os) else {
+            return Vec::new();
+        };
+        let key = (
+            target.ecosystem.to_string(),
+            normalize_name(target.ecosystem, &target.name),
+        );
+
+        let mut vulnerabilities: Vec<Vulnerability> = Vec::new();
+        for (advisory, index) in self.index.get(&key).into_iter().flatten() {
+            let affected = &advisory.affected[*index];
+            let ecosystem = affected
+                .package
+                .as_ref()
+                .map(|p| p.ecosystem.as_str())
+                .unwrap_or_default();
+
+            if !target.matches_release(ecosystem) || !target.is_affected(affected) {
+                continue;
+            }
+            // An advisory may list the same package once per release
+            if vulnerabilities.iter().any(|v| v.id == advisory.id) {
+                continue;
+            }
+
+            let (severity, score) = severity::rate(advisory, affected);
+            vulnerabilities.push(Vulnerability {
+                id: advisory.id.clone(),
+                aliases: advisory.aliases.clone(),
+                summary: advisory.summary.clone(),
+                severity,
+                score,
+                package: package.name.clone(),
+                version: package.version.clone(),
+                package_type: package.package_type,
+                location: package.location.clone(),
+                fixed_versions: fixed_versions(affected),
+            });
+        }
+
+        vulnerabilities
+    }
+}
+
+/// Borrowed pieces needed to scan an image.
+pub struct Scanner<'a> {
+    pub inventories: &'a InventoryCache,
+    pub advisories: Arc<Advisories>,
+}
+
+impl Scanner<'_> {
+    pub async fn scan(
+        &self,
+        client: &RegistryClient,
+        image: &str,
+        manifest: &PlatformManifest,
+    ) -> Result<Vec<Vulnerability>, ClientError> {
+        let inventory = self.inventories.get(client, image, manifest).await?;
+
+        Ok(self.matches(&inventory))
+    }
+
+    /// Severity counts of an image scanned before, `None` rather than downloading its layers.
+    pub fn cached_counts(&self, manifest: &PlatformManifest) -> Option<SeverityCounts> {
+        let inventory = self.inventories.cached(&manifest.config.digest)?;
+
+        Some(SeverityCounts::of(&self.matches(&inventory)))
+    }
+
+    fn matches(&self, inventory: &Inventory) -> Vec<Vulnerability> {
+        let mut vulnerabilities: Vec<Vulnerability> = inventory
+            .packages
+            .iter()
+            .flat_map(|package| self.advisories.matches(package, inventory.os.as_ref()))
+            .collect();
+
+        vulnerabilities.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.id.cmp(&b.id)));
+        vulnerabilities
+    }
+}
+
+/// A package as OSV names it: ecosystem, package name and how versions are ordered.
+struct Target {
+    ecosystem: &'static str,
+    releases: Vec<String>,
+    name: String,
+    version: String,
+    scheme: VersionScheme,
+}
+
+impl Target {
+    fn of(package: &Package, os: Option<&OsRelease>) -> Option<Self> {
+        let os_id = os.map(|os| os.id.as_str());
+        let version_id = os.and_then(|os| os.version_id.as_deref());
+        // Distribution advisories are filed against source packages
+        let source = package.source.clone().unwrap_or_else(|| package.name.clone());
+
+        let (ecosystem, name, version, scheme) = match package.package_type {
+            PackageType::Deb => {
+                let ecosystem = if os_id == Some("ubuntu") { "Ubuntu" } else { "Debian" };
+                (ecosystem, source, package.version.clone(), VersionScheme::Dpkg)
+            }
+            PackageType::Apk => ("Alpine", source, package.version.clone(), VersionScheme::Apk),
+            PackageType::Rpm => {
+                let ecosystem = match os_id? {
+                    "rocky" => "Rocky Linux",
+                    "almalinux" => "AlmaLinux",
+                    "rhel" => "Red Hat",
+                    "opensuse-leap" | "opensuse-tumbleweed" => "openSUSE",
+                    "sles" => "SUSE",
+                    "mariner" | "azurelinux" => "Mariner",
+                    _ => return None,
+                };
+                (
+                    ecosystem,
+                    package.name.clone(),
+                    package.version.clone(),
+                    VersionScheme::Rpm,
+                )
+            }
+            PackageType::Python => (
+                "PyPI",
+                package.name.clone(),
+                package.version.clone(),
+                VersionScheme::Pep440,
+            ),
+            PackageType::Go => {
+                let version = package.version.trim_start_matches("go").trim_start_matches('v');
+                ("Go", package.name.clone(), version.to_string(), VersionScheme::Semver)
+            }
+            PackageType::Npm => (
+                "npm",
+                package.name.clone(),
+                package.version.clone(),
+                VersionScheme::Semver,
+            ),
+        };
+
+        let releases = match (package.package_type, version_id) {
+            (PackageType::Deb | PackageType::Apk | PackageType::Rpm, Some(version)) => {
+                release_names(ecosystem, version)
+            }
+            _ => Vec::new(),
+        };
+
+        Some(Self {
+            ecosystem,
+            releases,
+            name,
+            version,
+            scheme,
+        })
+    }
+
+    /// `Debian:12`, `Alpine:v3.19` or `Ubuntu:22.04:LTS` only apply to that release. Without
+    /// an OS version nothing tells which one the image runs, so none of them match.
+    fn matches_release(&self, ecosystem: &str) -> bool {
+        let Some((_, release)) = ecosystem.split_once(':') else {
+            return true;
+        };
+
+        release.split(':').any(|part| self.releases.iter().any(|r| r == part))
+    }
+
+    fn is_affected(&self, affected: &Affected) -> bool {
+        if affected.versions.contains(&self.version) {
+            return true;
+        }
+
+        affected
+            .ranges
+            .iter()
+            .filter(|range| range.kind != "GIT")
+            .any(|range| self.in_range(&range.events))
+    }
+
+    /// Replays the range events in version order up to the package version.
+    fn in_range(&self, events: &[Event]) -> bool {
+        let compare = |a: &str, b: &str| match (a, b) {
+            ("0", "0") => Ordering::Equal,
+            ("0", _) => Ordering::Less,
+            (_, "0") => Ordering::Greater,
+            _ => self.scheme.compare(a, b),
+        };
+
+        let mut events: Vec<&Event> = events.iter().collect();
+        events.sort_by(|a, b| compare(a.version(), b.version()));
+
+        let mut affected = false;
+        for event in events {
+            let order = if event.version() == "0" {
+                Ordering::Less
+            } else {
+                self.scheme.compare(event.version(), &self.version)
+            };
+
+            match event {
+                Event::Introduced(_) if order.is_le() => affected = true,
+                Event::Fixed(_) | Event::Limit(_) if order.is_le() => affected = false,
+                Event::LastAffected(_) if order.is_lt() => affected = false,
+                _ => {}
+            }
+        }
+
+        affected
+    }
+}
+
+fn release_names(ecosystem: &str, version: &str) -> Vec<String> {
+    let parts: Vec<&str> = version.split('.').collect();
+    let mut names = vec![version.to_string(), parts[0].to_string()];
+    if parts.len() > 1 {
+        names.push(parts[..2].join("."));
+    }
+    if ecosystem == "Alpine" {
+        names = names.into_iter().map(|n| format!("v{}", n)).collect();
+    }
+
+    names
+}
+
+fn normalize_name(ecosystem: &str, name: &str) -> String {
+    match ecosystem {
+        // PEP 503 normalization
+        "PyPI" => name
+            .to_lowercase()
+            .split(['-', '_', '.'])
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-"),
+        _ => name.to_string(),
+    }
+}
+
+fn fixed_versions(affected: &Affected) -> Vec<String> {
+    affected
+        .ranges
+        .iter()
+        .flat_map(|range| &range.events)
+        .filter_map(|event| match event {
+            Event::Fixed(version) => Some(version.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(package_type: PackageType, version: &str, os: Option<(&str, Option<&str>)>) -> Target {
+        let package = Package {
+            name: "pkg".to_string(),
+            version: version.to_string(),
+            package_type,
+            arch: None,
+            license: None,
+            source: None,
+            location: String::new(),
+            layer: None,
+        };
+        let os = os.map(|(id, version)| OsRelease {
+            id: id.to_string(),
+            version_id: version.map(String::from),
+            pretty_name: None,
+        });
+
+        Target::of(&package, os.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn matches_advisories_of_the_image_release() {
+        let debian = target(PackageType::Deb, "1.0-1", Some(("debian", Some("12"))));
+        assert!(debian.matches_release("Debian"));
+        assert!(debian.matches_release("Debian:12"));
+        assert!(!debian.matches_release("Debian:11"));
+
+        let alpine = target(PackageType::Apk, "1.0-r0", Some(("alpine", Some("3.19.1"))));
+        assert!(alpine.matches_release("Alpine:v3.19"));
+        assert!(!alpine.matches_release("Alpine:v3.18"));
+
+        let ubuntu = target(PackageType::Deb, "1.0-1", Some(("ubuntu", Some("22.04"))));
+        assert!(ubuntu.matches_release("Ubuntu:22.04:LTS"));
+        assert!(!ubuntu.matches_release("Ubuntu:Pro:20.04:LTS"));
+
+        // An unknown release could be any of them
+        let unknown = target(PackageType::Deb, "1.0-1", Some(("debian", None)));
+        assert!(unknown.matches_release("Debian"));
+        assert!(!unknown.matches_release("Debian:12"));
+        let unknown = target(PackageType::Apk, "1.0-r0", None);
+        assert!(!unknown.matches_release("Alpine:v3.19"));
+
+        let python = target(PackageType::Python, "1.0", None);
+        assert!(python.matches_release("PyPI"));
+    }
+
+    #[test]
+    fn replays_ranges_up_to_the_package_version() {
+        let introduced = |v: &str| Event::Introduced(v.to_string());
+        let fixed = |v: &str| Event::Fixed(v.to_string());
+        let cases = [
+            (vec![introduced("0"), fixed("1.0-2")], "1.0-1", true),
+            (vec![introduced("0"), fixed("1.0-2")], "1.0-2", false),
+            (vec![introduced("0"), fixed("1.0-2")], "1:0.1-1", false),
+            (vec![introduced("1.0-1"), fixed("1.0-2")], "1.0-1", true),
+            (vec![introduced("1.0-1"), fixed("1.0-2")], "0.9-1", false),
+            (
+                vec![introduced("0"), Event::LastAffected("1.5".to_string())],
+                "1.5",
+                true,
+            ),
+            (
+                vec![introduced("0"), Event::LastAffected("1.5".to_string())],
+                "1.5-1",
+                false,
+            ),
+            (vec![introduced("0"), Event::Limit("2.0".to_string())], "2.0", false),
+            (vec![introduced("0"), fixed("1.0~rc2")], "1.0~rc1", true),
+            (vec![introduced("0"), fixed("1.0~rc2")], "1.0", false),
+            // Events may come in any order, each introduced range ends at the next fix
+            (
+                vec![fixed("2.1"), introduced("2.0"), fixed("1.2"), introduced("1.0")],
+                "1.5",
+                false,
+            ),
+            (
+                vec![fixed("2.1"), introduced("2.0"), fixed("1.2"), introduced("1.0")],
+                "2.0.1",
+                true,
+            ),
+            (vec![introduced("1.0")], "9.9", true),
+        ];
+
+        for (events, version, affected) in cases {
+            let target = target(PackageType::Deb, version, Some(("debian", Some("12"))));
+            assert_eq!(target.in_range(&events), affected, "{} in {:?}", version, events);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The subset of the OSV schema (https://ossf.github.io/osv-schema/) needed for matching.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Advisory {
+    pub id: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+    pub withdrawn: Option<String>,
+    #[serde(default)]
+    pub severity: Vec<Severity>,
+    #[serde(default)]
+    pub affected: Vec<Affected>,
+    pub database_specific: Option<Value>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Severity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub score: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Affected {
+    pub package: Option<AffectedPackage>,
+    #[serde(default)]
+    pub ranges: Vec<Range>,
+    #[serde(default)]
+    pub versions: Vec<String>,
+    #[serde(default)]
+    pub severity: Vec<Severity>,
+    pub ecosystem_specific: Option<Value>,
+    pub database_specific: Option<Value>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct AffectedPackage {
+    pub ecosystem: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Range {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Introduced(String),
+    Fixed(String),
+    LastAffected(String),
+    Limit(String),
+}
+
+impl Event {
+    pub fn version(&self) -> &str {
+        match self {
+            Event::Introduced(v) | Event::Fixed(v) | Event::LastAffected(v) | Event::Limit(v) => v,
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::types::SeverityLevel;
+use crate::vulnerabilities::osv::{Advisory, Affected, Severity};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Picks the most specific severity an advisory carries: the ecosystem's own rating,
+/// then the GHSA one, then a CVSS v3 vector.
+pub fn rate(advisory: &Advisory, affected: &Affected) -> (SeverityLevel, Option<f64>) {
+    let score = affected.severity.iter().chain(&advisory.severity).find_map(cvss_score);
+
+    let label = [
+        &affected.ecosystem_specific,
+        &affected.database_specific,
+        &advisory.database_specific,
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|fields| {
+        ["severity", "urgency"]
+            .iter()
+            .find_map(|key| fields.get(key).and_then(Value::as_str).and_then(level))
+    })
+    .or_else(|| {
+        affected
+            .severity
+            .iter()
+            .chain(&advisory.severity)
+            .find_map(|s| level(&s.score))
+    });
+
+    match (label, score) {
+        (Some(label), _) => (label, score),
+        (None, Some(score)) => (level_of_score(score), Some(score)),
+        (None, None) => (SeverityLevel::Unknown, None),
+    }
+}
+
+fn level(label: &str) -> Option<SeverityLevel> {
+    match label.to_lowercase().as_str() {
+        "critical" => Some(SeverityLevel::Critical),
+        "high" | "important" => Some(SeverityLevel::High),
+        "medium" | "moderate" => Some(SeverityLevel::Medium),
+        "low" | "negligible" | "unimportant" => Some(SeverityLevel::Low),
+        _ => None,
+    }
+}
+
+fn level_of_score(score: f64) -> SeverityLevel {
+    match score {
+        s if s >= 9.0 => SeverityLevel::Critical,
+        s if s >= 7.0 => SeverityLevel::High,
+        s if s >= 4.0 => SeverityLevel::Medium,
+        s if s > 0.0 => SeverityLevel::Low,
+        _ => SeverityLevel::Unknown,
+    }
+}
+
+/// CVSS v3.x base score from a `CVSS:3.1/AV:N/AC:L/...` vector.
+fn cvss_score(severity: &Severity) -> Option<f64> {
+    if severity.kind != "CVSS_V3" {
+        return None;
+    }
+
+    let metrics: HashMap<&str, &str> = severity
+        .score
+        .split('/')
+        .skip(1)
+        .filter_map(|metric| metric.split_once(':'))
+        .collect();
+    let scope_changed = *metrics.get("S")? == "C";
+
+    let attack_vector = match *metrics.get("AV")? {
+        "N" => 0.85,
+        "A" => 0.62,
+        "L" => 0.55,
+        _ => 0.2,
+    };
+    let attack_complexity = if *metrics.get("AC")? == "L" { 0.77 } else { 0.44 };
+    let privileges = match (*metrics.get("PR")?, scope_changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        (_, false) => 0.27,
+        (_, true) => 0.5,
+    };
+    let interaction = if *metrics.get("UI")? == "N" { 0.85 } else { 0.62 };
+    let impact = |metric: &str| -> Option<f64> {
+        Some(match *metrics.get(metric)? {
+            "H" => 0.56,
+            "L" => 0.22,
+            _ => 0.0,
+        })
+    };
+
+    let base = 1.0 - (1.0 - impact("C")?) * (1.0 - impact("I")?) * (1.0 - impact("A")?);
+    let impact = if scope_changed {
+        7.52 * (base - 0.029) - 3.25 * (base - 0.02).powi(15)
+    } else {
+        6.42 * base
+    };
+    let exploitability = 8.22 * attack_vector * attack_complexity * privileges * interaction;
+
+    if impact <= 0.0 {
+        return Some(0.0);
+    }
+
+    let score = if scope_changed {
+        (1.08 * (impact + exploitability)).min(10.0)
+    } else {
+        (impact + exploitability).min(10.0)
+    };
+
+    Some(round_up(score))
+}
+
+/// Rounds up to one decimal, as defined in the CVSS v3.1 specification.
+fn round_up(value: f64) -> f64 {
+    let scaled = (value * 100_000.0).round() as i64;
+    if scaled % 10_000 == 0 {
+        scaled as f64 / 100_000.0
+    } else {
+        ((scaled / 10_000) + 1) as f64 / 10.0
+    }
+}
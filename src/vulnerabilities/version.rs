@@ -0,0 +1,489 @@
+use std::cmp::Ordering;
+
+/// Version ordering used by an OSV ecosystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VersionScheme {
+    Dpkg,
+    Apk,
+    Rpm,
+    Pep440,
+    Semver,
+}
+
+impl VersionScheme {
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            VersionScheme::Dpkg => compare_dpkg(a, b),
+            VersionScheme::Apk => compare_apk(a, b),
+            VersionScheme::Rpm => compare_rpm(a, b),
+            VersionScheme::Pep440 => compare_pep440(a, b),
+            VersionScheme::Semver => compare_semver(a, b),
+        }
+    }
+}
+
+fn split_epoch(version: &str) -> (u64, &str) {
+    match version.split_once(':') {
+        Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => (epoch.parse().unwrap_or(0), rest),
+        _ => (0, version),
+    }
+}
+
+fn leading_number(s: &str) -> (u64, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (s[..end].parse().unwrap_or(0), &s[end..])
+}
+
+/// `[epoch:]upstream[-revision]`, compared the way `dpkg --compare-versions` does.
+pub fn compare_dpkg(a: &str, b: &str) -> Ordering {
+    let (epoch_a, a) = split_epoch(a);
+    let (epoch_b, b) = split_epoch(b);
+    let (upstream_a, revision_a) = a.rsplit_once('-').unwrap_or((a, ""));
+    let (upstream_b, revision_b) = b.rsplit_once('-').unwrap_or((b, ""));
+
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| compare_dpkg_part(upstream_a, upstream_b))
+        .then_with(|| compare_dpkg_part(revision_a, revision_b))
+}
+
+fn compare_dpkg_part(mut a: &str, mut b: &str) -> Ordering {
+    // `~` sorts before everything, even the end of the string, letters sort before other symbols
+    fn order(c: Option<char>) -> i32 {
+        match c {
+            Some('~') => -1,
+            None => 0,
+            Some(c) if c.is_ascii_alphabetic() => c as i32,
+            Some(c) => c as i32 + 256,
+        }
+    }
+
+    while !a.is_empty() || !b.is_empty() {
+        let text_a = &a[..a.find(|c: char| c.is_ascii_digit()).unwrap_or(a.len())];
+        let text_b = &b[..b.find(|c: char| c.is_ascii_digit()).unwrap_or(b.len())];
+
+        let mut chars_a = text_a.chars();
+        let mut chars_b = text_b.chars();
+        loop {
+            let (ca, cb) = (chars_a.next(), chars_b.next());
+            if ca.is_none() && cb.is_none() {
+                break;
+            }
+            match order(ca).cmp(&order(cb)) {
+                Ordering::Equal => {}
+                other => return other,
+            }
+        }
+
+        let (number_a, rest_a) = leading_number(&a[text_a.len()..]);
+        let (number_b, rest_b) = leading_number(&b[text_b.len()..]);
+        match number_a.cmp(&number_b) {
+            Ordering::Equal => {}
+            other => return other,
+        }
+
+        a = rest_a;
+        b = rest_b;
+    }
+
+    Ordering::Equal
+}
+
+/// `1.2.3[letter][_suffix[N]...][-rN]` as used by Alpine.
+pub fn compare_apk(a: &str, b: &str) -> Ordering {
+    type Key = (Vec<u64>, Option<char>, Vec<(u8, u64)>, u64);
+
+    fn parse(version: &str) -> Key {
+        let (version, revision) = match version.rsplit_once("-r") {
+            Some((v, r)) if r.chars().all(|c| c.is_ascii_digit()) => (v, r.parse().unwrap_or(0)),
+            _ => (version, 0),
+        };
+        let mut parts = version.split('_');
+        let base = parts.next().unwrap_or_default();
+
+        let letter = base.chars().last().filter(|c| c.is_ascii_alphabetic());
+        let numbers = base
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+            .split('.')
+            .map(|n| leading_number(n).0)
+            .collect();
+
+        let suffixes = parts
+            .map(|suffix| {
+                let name = suffix.trim_end_matches(|c: char| c.is_ascii_digit());
+                // Pre-releases sort before the release itself, patch levels after it
+                let rank = match name {
+                    "alpha" => 0,
+                    "beta" => 1,
+                    "pre" => 2,
+                    "rc" => 3,
+                    "cvs" => 5,
+                    "svn" => 6,
+                    "git" => 7,
+                    "hg" => 8,
+                    _ => 9,
+                };
+                (rank, leading_number(&suffix[name.len()..]).0)
+            })
+            .collect();
+
+        (numbers, letter, suffixes, revision)
+    }
+
+    let (numbers_a, letter_a, suffixes_a, revision_a) = parse(a);
+    let (numbers_b, letter_b, suffixes_b, revision_b) = parse(b);
+    // No suffix ranks between pre-releases and patch levels
+    let suffix = |suffixes: &[(u8, u64)], i: usize| suffixes.get(i).copied().unwrap_or((4, 0));
+
+    compare_numbers(&numbers_a, &numbers_b)
+        .then_with(|| letter_a.cmp(&letter_b))
+        .then_with(|| {
+            (0..suffixes_a.len().max(suffixes_b.len()))
+                .map(|i| suffix(&suffixes_a, i).cmp(&suffix(&suffixes_b, i)))
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        })
+        .then_with(|| revision_a.cmp(&revision_b))
+}
+
+/// `[epoch:]version[-release]`, each part compared with `rpmvercmp`.
+pub fn compare_rpm(a: &str, b: &str) -> Ordering {
+    let (epoch_a, a) = split_epoch(a);
+    let (epoch_b, b) = split_epoch(b);
+    let (version_a, release_a) = a.rsplit_once('-').unwrap_or((a, ""));
+    let (version_b, release_b) = b.rsplit_once('-').unwrap_or((b, ""));
+
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| rpmvercmp(version_a, version_b))
+        .then_with(|| {
+            // A missing release matches any release
+            if release_a.is_empty() || release_b.is_empty() {
+                Ordering::Equal
+            } else {
+                rpmvercmp(release_a, release_b)
+            }
+        })
+}
+
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    let separator = |c: char| !c.is_ascii_alphanumeric() && c != '~' && c != '^';
+    let (mut a, mut b) = (a, b);
+
+    loop {
+        a = a.trim_start_matches(separator);
+        b = b.trim_start_matches(separator);
+
+        // `~` sorts before anything, `^` after the end of the version but before anything else
+        match (a.strip_prefix('~'), b.strip_prefix('~')) {
+            (Some(ra), Some(rb)) => {
+                a = ra;
+                b = rb;
+                continue;
+            }
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            _ => {}
+        }
+        match (a.strip_prefix('^'), b.strip_prefix('^')) {
+            (Some(ra), Some(rb)) => {
+                a = ra;
+                b = rb;
+                continue;
+            }
+            (Some(_), None) => {
+                return if b.is_empty() {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
+                }
+            }
+            (None, Some(_)) => {
+                return if a.is_empty() {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            _ => {}
+        }
+
+        if a.is_empty() || b.is_empty() {
+            return a.len().cmp(&b.len());
+        }
+
+        let numeric = a.starts_with(|c: char| c.is_ascii_digit());
+        let segment = |s: &str| -> usize {
+            s.find(|c: char| {
+                if numeric {
+                    !c.is_ascii_digit()
+                } else {
+                    !c.is_ascii_alphabetic()
+                }
+            })
+            .unwrap_or(s.len())
+        };
+        let (segment_a, segment_b) = (&a[..segment(a)], &b[..segment(b)]);
+
+        // Numeric segments are newer than alphabetic ones
+        if segment_b.is_empty() {
+            return if numeric { Ordering::Greater } else { Ordering::Less };
+        }
+
+        let order = if numeric {
+            let (na, nb) = (segment_a.trim_start_matches('0'), segment_b.trim_start_matches('0'));
+            na.len().cmp(&nb.len()).then_with(|| na.cmp(nb))
+        } else {
+            segment_a.cmp(segment_b)
+        };
+        if order.is_ne() {
+            return order;
+        }
+
+        a = &a[segment_a.len()..];
+        b = &b[segment_b.len()..];
+    }
+}
+
+/// Python versions: `[N!]N(.N)*[{a|b|rc}N][.postN][.devN]`.
+pub fn compare_pep440(a: &str, b: &str) -> Ordering {
+    type Key = (u64, Vec<u64>, (i8, u64), Option<u64>, u128);
+
+    fn parse(version: &str) -> Key {
+        let version = version.trim().to_lowercase();
+        let version = version.trim_start_matches('v');
+        let version = version.split('+').next().unwrap_or_default();
+        let (epoch, version) = match version.split_once('!') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, version),
+        };
+
+        let release_end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+        let release = version[..release_end]
+            .split('.')
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse().unwrap_or(0))
+            .collect();
+        let mut rest = version[release_end..].trim_start_matches(['.', '-', '_']);
+
+        let mut pre = None;
+        for (name, rank) in [
+            ("alpha", 0),
+            ("beta", 1),
+            ("rc", 2),
+            ("a", 0),
+            ("b", 1),
+            ("c", 2),
+            ("pre", 2),
+        ] {
+            if let Some(after) = rest.strip_prefix(name) {
+                let (number, after) = leading_number(after.trim_start_matches(['.', '-', '_']));
+                pre = Some((rank, number));
+                rest = after.trim_start_matches(['.', '-', '_']);
+                break;
+            }
+        }
+
+        let mut post = None;
+        if let Some(after) = rest.strip_prefix("post").or_else(|| rest.strip_prefix("rev")) {
+            let (number, after) = leading_number(after);
+            post = Some(number);
+            rest = after.trim_start_matches(['.', '-', '_']);
+        }
+
+        let dev = rest.strip_prefix("dev").map(|after| leading_number(after).0);
+        // `1.0.dev1` comes before `1.0a1`, and any dev release before its non-dev counterpart
+        let pre = match pre {
+            Some(pre) => pre,
+            None if dev.is_some() && post.is_none() => (-1, 0),
+            None => (3, 0),
+        };
+
+        (epoch, release, pre, post, dev.map_or(u128::MAX, u128::from))
+    }
+
+    let (epoch_a, release_a, pre_a, post_a, dev_a) = parse(a);
+    let (epoch_b, release_b, pre_b, post_b, dev_b) = parse(b);
+
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| compare_numbers(&release_a, &release_b))
+        .then_with(|| pre_a.cmp(&pre_b))
+        .then_with(|| post_a.cmp(&post_b))
+        .then_with(|| dev_a.cmp(&dev_b))
+}
+
+/// `[v]MAJOR.MINOR.PATCH[-prerelease][+build]`, also used for Go modules and npm.
+pub fn compare_semver(a: &str, b: &str) -> Ordering {
+    fn parse(version: &str) -> (Vec<u64>, Option<&str>) {
+        let version = version.trim().trim_start_matches('v');
+        let version = version.split('+').next().unwrap_or_default();
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre)),
+            None => (version, None),
+        };
+
+        (release.split('.').map(|n| leading_number(n).0).collect(), pre)
+    }
+
+    let (release_a, pre_a) = parse(a);
+    let (release_b, pre_b) = parse(b);
+
+    compare_numbers(&release_a, &release_b).then_with(|| match (pre_a, pre_b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            let mut ids_a = a.split('.');
+            let mut ids_b = b.split('.');
+            loop {
+                let order = match (ids_a.next(), ids_b.next()) {
+                    (None, None) => return Ordering::Equal,
+                    (None, Some(_)) => Ordering::Less,
+                    (Some(_), None) => Ordering::Greater,
+                    (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+                        (Ok(x), Ok(y)) => x.cmp(&y),
+                        (Ok(_), Err(_)) => Ordering::Less,
+                        (Err(_), Ok(_)) => Ordering::Greater,
+                        (Err(_), Err(_)) => x.cmp(y),
+                    },
+                };
+                if order.is_ne() {
+                    return order;
+                }
+            }
+        }
+    })
+}
+
+/// Compares dotted release numbers, missing trailing components count as zero.
+fn compare_numbers(a: &[u64], b: &[u64]) -> Ordering {
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each version is older than the next one, `=` pairs compare equal.
+    fn assert_ordered(scheme: VersionScheme, versions: &[&str]) {
+        for pair in versions.windows(2) {
+            let order = if pair[1].starts_with('=') {
+                Ordering::Equal
+            } else {
+                Ordering::Less
+            };
+            let (a, b) = (pair[0].trim_start_matches('='), pair[1].trim_start_matches('='));
+            assert_eq!(scheme.compare(a, b), order, "{:?}: {} against {}", scheme, a, b);
+            assert_eq!(
+                scheme.compare(b, a),
+                order.reverse(),
+                "{:?}: {} against {}",
+                scheme,
+                b,
+                a
+            );
+        }
+    }
+
+    #[test]
+    fn orders_dpkg_versions() {
+        assert_ordered(
+            VersionScheme::Dpkg,
+            &[
+                "1.0~~", "1.0~rc1", "1.0~rc2", "1.0", "=1.0-0", "1.0-1", "1.0-2", "1.0a", "1.0+b1", "1.9", "1.10",
+                "1:0.1", "2:0.1",
+            ],
+        );
+        assert_ordered(VersionScheme::Dpkg, &["2.36-9+deb12u4", "2.36-9+deb12u7", "2.36-10"]);
+    }
+
+    #[test]
+    fn orders_apk_versions() {
+        assert_ordered(
+            VersionScheme::Apk,
+            &[
+                "1.0_alpha1",
+                "1.0_alpha2",
+                "1.0_beta1",
+                "1.0_pre1",
+                "1.0_rc1",
+                "1.0",
+                "=1.0-r0",
+                "1.0-r1",
+                "1.0_git20230717",
+                "1.0_p1",
+                "1.0a",
+                "1.0.1",
+            ],
+        );
+        assert_ordered(VersionScheme::Apk, &["3.1.4-r5", "3.1.10-r0", "3.10.0-r0"]);
+    }
+
+    #[test]
+    fn orders_rpm_versions() {
+        assert_ordered(
+            VersionScheme::Rpm,
+            &[
+                "1.0~rc1-1",
+                "1.0-1",
+                "1.0-2",
+                "1.0^post1-1",
+                "1.0a-1",
+                "1.0.1-1",
+                "1.10-1",
+                "1:0.1-1",
+            ],
+        );
+        assert_ordered(VersionScheme::Rpm, &["3.1.1-4.fc39", "3.1.1-10.fc39", "3.1.01-11.fc39"]);
+        // Ranges often leave out the release, which then matches any
+        assert_eq!(compare_rpm("1.0", "1.0-1"), Ordering::Equal);
+        assert_eq!(compare_rpm("1.0-2", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn orders_pep440_versions() {
+        assert_ordered(
+            VersionScheme::Pep440,
+            &[
+                "1.0.dev1",
+                "1.0a1",
+                "1.0a2.dev1",
+                "1.0a2",
+                "1.0b1",
+                "1.0rc1",
+                "1.0",
+                "=1.0.0",
+                "=v1.0+local",
+                "1.0.post1.dev1",
+                "1.0.post1",
+                "1.0.1",
+                "1!0.1",
+            ],
+        );
+    }
+
+    #[test]
+    fn orders_semver_versions() {
+        assert_ordered(
+            VersionScheme::Semver,
+            &[
+                "1.0.0-2",
+                "1.0.0-10",
+                "1.0.0-alpha",
+                "1.0.0-alpha.1",
+                "1.0.0-beta",
+                "1.0.0-rc.1",
+                "1.0.0",
+                "=v1.0.0+build",
+                "1.9.0",
+                "1.10.0",
+            ],
+        );
+    }
+}